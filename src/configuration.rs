@@ -0,0 +1,348 @@
+use std::env::{self, VarError};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use ipnetwork::IpNetwork;
+
+use crate::network::{
+    AccountingMode, CaptureConfiguration, CaptureFilter, Decapsulation, Scope, DEFAULT_GENEVE_PORT,
+    DEFAULT_VXLAN_PORT,
+};
+use crate::publish::{ConfigurationDetails, UtilizationDetails};
+
+const DEFAULT_CAPTURE_SNAPLEN: u32 = 128;
+const DEFAULT_CAPTURE_FILE_SIZE: u64 = 16 * 1024 * 1024;
+const DEFAULT_CAPTURE_FILE_DURATION: Duration = Duration::from_secs(60);
+const DEFAULT_CAPTURE_FILES: usize = 10;
+const DEFAULT_COMMAND_LINE_LENGTH: usize = 256;
+const DEFAULT_REDACTED_ARGUMENTS: [&str; 5] = ["password", "passwd", "secret", "token", "key"];
+
+/// Settings read from `BANDWHICHD_*` environment variables.
+pub struct Configuration {
+    pub server: Option<String>,
+    pub replay: Option<ReplayConfiguration>,
+    pub capture: Option<CaptureConfiguration>,
+    pub decapsulation: DecapsulationConfiguration,
+    pub utilization_details: UtilizationDetails,
+    pub configuration_details: ConfigurationDetails,
+    /// Scopes whose traffic is not reported.
+    pub ignored_scopes: Vec<Scope>,
+    pub sniffing: SniffingConfiguration,
+    pub measurement_source: MeasurementSource,
+}
+
+/// Where the utilization of connections is measured.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MeasurementSource {
+    /// Sniffing the frames of all interfaces.
+    #[default]
+    Capture,
+    /// Reading the counters of netfilter conntrack entries, needing neither packet sockets nor
+    /// promiscuous mode. Only IP layer lengths are known, so the accounting mode is L3.
+    ///
+    /// Entries are polled when a window closes, so traffic is undercounted: connections created
+    /// and destroyed between two polls are not seen at all, and bytes of a connection added after
+    /// its last poll are lost when it is destroyed.
+    Conntrack,
+}
+
+impl fmt::Display for MeasurementSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MeasurementSource::Capture => "capture",
+            MeasurementSource::Conntrack => "conntrack",
+        })
+    }
+}
+
+impl FromStr for MeasurementSource {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "capture" => Ok(MeasurementSource::Capture),
+            "conntrack" => Ok(MeasurementSource::Conntrack),
+            _ => Err("expected one of capture or conntrack".to_string()),
+        }
+    }
+}
+
+/// Settings for replaying a pcap or pcapng file instead of sniffing live interfaces.
+pub struct ReplayConfiguration {
+    pub file: PathBuf,
+    pub interface_name: String,
+    pub local_networks: Vec<IpNetwork>,
+}
+
+/// Interfaces whose tunnel traffic is reported by its inner flows.
+#[derive(Clone)]
+pub struct DecapsulationConfiguration {
+    pub interfaces: Vec<String>,
+    pub tunnels: Decapsulation,
+}
+
+impl DecapsulationConfiguration {
+    pub fn for_interface(&self, interface_name: &str) -> Option<Decapsulation> {
+        if self
+            .interfaces
+            .iter()
+            .any(|interface| interface == interface_name)
+        {
+            Some(self.tunnels)
+        } else {
+            None
+        }
+    }
+}
+
+/// Interfaces sniffed differently from the defaults.
+#[derive(Clone, Default)]
+pub struct SniffingConfiguration {
+    /// Interfaces attached to mirror or SPAN ports, reporting transit flows.
+    pub mirror_interfaces: Vec<String>,
+    /// Mirror interfaces put into promiscuous mode, which all other interfaces are always in.
+    pub promiscuous_interfaces: Vec<String>,
+    /// Interfaces of a router, reporting the traffic forwarded between them.
+    pub router_interfaces: Vec<String>,
+}
+
+impl SniffingConfiguration {
+    pub fn is_mirror(&self, interface_name: &str) -> bool {
+        self.mirror_interfaces
+            .iter()
+            .any(|interface| interface == interface_name)
+    }
+    pub fn is_promiscuous(&self, interface_name: &str) -> bool {
+        !self.is_mirror(interface_name)
+            || self
+                .promiscuous_interfaces
+                .iter()
+                .any(|interface| interface == interface_name)
+    }
+    pub fn is_router(&self, interface_name: &str) -> bool {
+        self.router_interfaces
+            .iter()
+            .any(|interface| interface == interface_name)
+    }
+}
+
+impl Configuration {
+    pub fn from_env() -> Result<Self, failure::Error> {
+        let server = optional_var("BANDWHICHD_SERVER")?;
+        let replay = match optional_var("BANDWHICHD_REPLAY_FILE")? {
+            None => None,
+            Some(file) => Some(ReplayConfiguration {
+                file: PathBuf::from(file),
+                interface_name: optional_var("BANDWHICHD_REPLAY_INTERFACE")?
+                    .unwrap_or_else(|| "replay".to_string()),
+                local_networks: list_var("BANDWHICHD_REPLAY_LOCAL_NETWORKS")?,
+            }),
+        };
+        let capture = match optional_var("BANDWHICHD_CAPTURE_DIRECTORY")? {
+            None => None,
+            Some(directory) => Some(CaptureConfiguration {
+                directory: PathBuf::from(directory),
+                snaplen: parsed_var("BANDWHICHD_CAPTURE_SNAPLEN")?
+                    .unwrap_or(DEFAULT_CAPTURE_SNAPLEN),
+                max_file_size: non_zero_var("BANDWHICHD_CAPTURE_FILE_SIZE")?
+                    .unwrap_or(DEFAULT_CAPTURE_FILE_SIZE),
+                max_file_duration: non_zero_var("BANDWHICHD_CAPTURE_FILE_DURATION")?
+                    .map(Duration::from_secs)
+                    .unwrap_or(DEFAULT_CAPTURE_FILE_DURATION),
+                max_files: non_zero_var("BANDWHICHD_CAPTURE_FILES")?
+                    .unwrap_or(DEFAULT_CAPTURE_FILES),
+                filter: CaptureFilter {
+                    ports: list_var("BANDWHICHD_CAPTURE_PORTS")?,
+                    networks: list_var("BANDWHICHD_CAPTURE_NETWORKS")?,
+                },
+            }),
+        };
+        let decapsulation = DecapsulationConfiguration {
+            interfaces: list_var("BANDWHICHD_DECAPSULATION_INTERFACES")?,
+            tunnels: Decapsulation {
+                vxlan_port: parsed_var("BANDWHICHD_DECAPSULATION_VXLAN_PORT")?
+                    .unwrap_or(DEFAULT_VXLAN_PORT),
+                geneve_port: parsed_var("BANDWHICHD_DECAPSULATION_GENEVE_PORT")?
+                    .unwrap_or(DEFAULT_GENEVE_PORT),
+            },
+        };
+        let measurement_source: MeasurementSource =
+            parsed_var("BANDWHICHD_MEASUREMENT_SOURCE")?.unwrap_or_default();
+        let utilization_details = UtilizationDetails {
+            vlan_ids: parsed_var("BANDWHICHD_REPORT_VLAN_IDS")?.unwrap_or(false),
+            remote_names: parsed_var("BANDWHICHD_REPORT_REMOTE_NAMES")?.unwrap_or(false),
+            conntrack: parsed_var("BANDWHICHD_REPORT_CONNTRACK")?.unwrap_or(false),
+            processes: parsed_var("BANDWHICHD_REPORT_PROCESSES")?.unwrap_or(false),
+            systemd_units: parsed_var("BANDWHICHD_AGGREGATE_BY_SYSTEMD_UNIT")?.unwrap_or(false),
+            version: parsed_var("BANDWHICHD_UTILIZATION_MESSAGE_VERSION")?.unwrap_or_default(),
+            accounting_mode: match measurement_source {
+                MeasurementSource::Capture => {
+                    parsed_var("BANDWHICHD_ACCOUNTING_MODE")?.unwrap_or_default()
+                }
+                MeasurementSource::Conntrack => AccountingMode::L3,
+            },
+        };
+        let redacted_arguments = list_var("BANDWHICHD_REDACTED_ARGUMENTS")?;
+        let configuration_details = ConfigurationDetails {
+            version: parsed_var("BANDWHICHD_CONFIGURATION_MESSAGE_VERSION")?.unwrap_or_default(),
+            command_line_length: parsed_var("BANDWHICHD_COMMAND_LINE_LENGTH")?
+                .unwrap_or(DEFAULT_COMMAND_LINE_LENGTH),
+            redacted_arguments: if redacted_arguments.is_empty() {
+                DEFAULT_REDACTED_ARGUMENTS
+                    .iter()
+                    .map(|argument| argument.to_string())
+                    .collect()
+            } else {
+                redacted_arguments
+            },
+        };
+        let ignored_scopes = list_var("BANDWHICHD_IGNORED_SCOPES")?;
+        let sniffing = SniffingConfiguration {
+            mirror_interfaces: list_var("BANDWHICHD_MIRROR_INTERFACES")?,
+            promiscuous_interfaces: list_var("BANDWHICHD_PROMISCUOUS_INTERFACES")?,
+            router_interfaces: list_var("BANDWHICHD_ROUTER_INTERFACES")?,
+        };
+        Ok(Configuration {
+            server,
+            replay,
+            capture,
+            decapsulation,
+            utilization_details,
+            configuration_details,
+            ignored_scopes,
+            sniffing,
+            measurement_source,
+        })
+    }
+}
+
+fn optional_var(key: &str) -> Result<Option<String>, failure::Error> {
+    match env::var(key) {
+        Ok(value) if value.trim().is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value.trim().to_string())),
+        Err(VarError::NotPresent) => Ok(None),
+        Err(error) => Err(failure::format_err!("{}: {}", key, error)),
+    }
+}
+
+fn parsed_var<T>(key: &str) -> Result<Option<T>, failure::Error>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    optional_var(key)?
+        .map(|value| {
+            value.parse().map_err(|error| {
+                failure::format_err!("{}: invalid value {}: {}", key, value, error)
+            })
+        })
+        .transpose()
+}
+
+/// Parses a count or size, which must not be zero.
+fn non_zero_var<T>(key: &str) -> Result<Option<T>, failure::Error>
+where
+    T: FromStr + Default + PartialEq,
+    T::Err: std::fmt::Display,
+{
+    match parsed_var(key)? {
+        Some(value) if value == T::default() => {
+            Err(failure::format_err!("{}: has to be at least 1", key))
+        }
+        value => Ok(value),
+    }
+}
+
+/// Parses a comma separated list, an unset variable being an empty list.
+fn list_var<T>(key: &str) -> Result<Vec<T>, failure::Error>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    optional_var(key)?
+        .map(|value| parse_list(key, &value))
+        .unwrap_or_else(|| Ok(vec![]))
+}
+
+fn parse_list<T>(key: &str, value: &str) -> Result<Vec<T>, failure::Error>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            item.parse()
+                .map_err(|error| failure::format_err!("{}: invalid value {}: {}", key, item, error))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_comma_separated_lists() {
+        // when
+        let result: Vec<IpNetwork> = parse_list("KEY", " 10.0.0.0/8, fe80::1/64,,").unwrap();
+
+        // then
+        assert_eq!(
+            result,
+            vec![
+                IpNetwork::from_str("10.0.0.0/8").unwrap(),
+                IpNetwork::from_str("fe80::1/64").unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn should_name_variable_of_invalid_list_items() {
+        // when
+        let result = parse_list::<IpNetwork>("KEY", "10.0.0.0/8,nonsense");
+
+        // then
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("KEY: invalid value nonsense"));
+    }
+
+    #[test]
+    fn should_reject_zero_counts_and_sizes() {
+        // given
+        env::set_var("BANDWHICHD_TEST_ZERO_COUNT", "0");
+        env::set_var("BANDWHICHD_TEST_COUNT", "3");
+
+        // when
+        let zero = non_zero_var::<usize>("BANDWHICHD_TEST_ZERO_COUNT");
+        let count = non_zero_var::<usize>("BANDWHICHD_TEST_COUNT");
+
+        // then
+        assert_eq!(
+            zero.unwrap_err().to_string(),
+            "BANDWHICHD_TEST_ZERO_COUNT: has to be at least 1"
+        );
+        assert_eq!(count.unwrap(), Some(3));
+    }
+
+    #[test]
+    fn should_keep_promiscuous_mode_of_all_but_unlisted_mirror_interfaces() {
+        // given
+        let sniffing = SniffingConfiguration {
+            mirror_interfaces: vec!["span0".to_string(), "span1".to_string()],
+            promiscuous_interfaces: vec!["span1".to_string()],
+            ..SniffingConfiguration::default()
+        };
+
+        // then
+        assert!(sniffing.is_promiscuous("eth0"));
+        assert!(!sniffing.is_promiscuous("span0"));
+        assert!(sniffing.is_promiscuous("span1"));
+    }
+}
@@ -7,23 +7,29 @@ use std::time::{Duration, Instant, SystemTime};
 
 use pnet::datalink::{DataLinkReceiver, NetworkInterface};
 
+use crate::configuration::{Configuration, MeasurementSource};
 use crate::machine_id::MachineId;
-use crate::network::{LocalSocket, Sniffer, Utilization};
-use crate::os_release::OsRelease;
-use crate::publish::{
-    Message, NetworkConfigurationV1MeasurementMessage, NetworkUtilizationV1MeasurementMessage,
+use crate::network::{
+    are_counted, CaptureRotation, Collector, ConntrackEntry, ConntrackMeter, ConntrackTable,
+    DnsCache, Forwarding, LocalSocket, ProcessCache, ProcessDetails, RingCapture, Sniffer,
+    Utilization,
 };
+use crate::os_release::OsRelease;
+use crate::publish::{HttpSink, Message, Sink};
 
+mod configuration;
 mod machine_id;
 mod network;
 mod os;
 mod os_release;
 mod publish;
+mod replay;
 
 const DEFAULT_NETWORK_CONFIGURATION_PUBLISH_INTERVAL: Duration = Duration::from_secs(600);
 const DEFAULT_NETWORK_UTILIZATION_PUBLISH_INTERVAL: Duration = Duration::from_secs(10);
 const DEFAULT_WATCHDOG_NOTIFY_INTERVAL: Duration = Duration::from_secs(10);
 const WATCHDOG_MARGIN: Duration = Duration::from_secs(2);
+const NETWORK_UTILIZATION_HAND_OFF_TIMEOUT: Duration = Duration::from_secs(2);
 const MAXIMUM_NUMBER_OF_CONSECUTIVE_PUBLISH_ERRORS: u8 = 3;
 
 fn main() {
@@ -34,14 +40,33 @@ fn main() {
 }
 
 fn try_main() -> Result<(), failure::Error> {
-    let server = std::env::var("BANDWHICHD_SERVER")?;
-    let os_input = os::get_input()?;
-    start(server, os_input);
+    let mut configuration = Configuration::from_env()?;
+    if configuration.replay.is_some() {
+        return replay::run(configuration, DEFAULT_NETWORK_UTILIZATION_PUBLISH_INTERVAL);
+    }
+    let server = configuration
+        .server
+        .clone()
+        .ok_or_else(|| failure::err_msg("BANDWHICHD_SERVER is not set"))?;
+    if configuration.measurement_source == MeasurementSource::Conntrack
+        && !are_counted(&read_conntrack_entries())
+    {
+        eprintln!(
+            "Conntrack entries are not counted, enable net.netfilter.nf_conntrack_acct; \
+             capturing traffic instead"
+        );
+        configuration.measurement_source = MeasurementSource::Capture;
+    }
+    let os_input = match configuration.measurement_source {
+        MeasurementSource::Capture => os::get_input(&configuration.sniffing)?,
+        MeasurementSource::Conntrack => os::get_input_without_capture(),
+    };
+    start(server, configuration, os_input);
     Ok(())
 }
 
 pub struct OpenSockets {
-    sockets_to_procs: HashMap<LocalSocket, String>,
+    sockets_to_procs: HashMap<LocalSocket, ProcessDetails>,
 }
 
 pub struct OsInputOutput {
@@ -50,6 +75,14 @@ pub struct OsInputOutput {
     pub get_open_sockets: fn() -> OpenSockets,
 }
 
+/// Reads the conntrack table, reporting failures as an empty table.
+fn read_conntrack_entries() -> Vec<ConntrackEntry> {
+    os::read_conntrack().unwrap_or_else(|error| {
+        eprintln!("Conntrack unavailable, {}", error);
+        vec![]
+    })
+}
+
 fn abort() {
     if libsystemd::daemon::booted() {
         libsystemd::daemon::notify(
@@ -64,20 +97,46 @@ fn abort() {
     process::exit(131);
 }
 
-pub fn start(server: String, os_input: OsInputOutput) {
+pub fn start(server: String, configuration: Configuration, os_input: OsInputOutput) {
     let start = Instant::now();
     let systemd_enabled = libsystemd::daemon::booted();
     let machine_id = MachineId::default();
     let maybe_os_release = OsRelease::read().ok();
-    let publish_endpoint = format!("{}/v1/messages", server);
 
     let mut active_threads = vec![];
     let last_publish_network_configuration = Arc::new(Mutex::new(start));
     let last_publish_network_utilization = Arc::new(Mutex::new(start));
 
     let get_open_sockets = os_input.get_open_sockets;
-
-    let network_utilization = Arc::new(Mutex::new(Utilization::new()));
+    let dns_cache = if configuration.utilization_details.remote_names {
+        Some(DnsCache::default())
+    } else {
+        None
+    };
+
+    let process_cache = if configuration.utilization_details.processes
+        || configuration.utilization_details.systemd_units
+    {
+        Some(ProcessCache::new(
+            os::get_socket_inodes,
+            os::get_inode_processes,
+        ))
+    } else {
+        None
+    };
+
+    let mut network_utilization_collector = Collector::new();
+    let network_utilization_accumulators = os_input
+        .network_interfaces
+        .iter()
+        .map(|_| {
+            let accumulator = network_utilization_collector.accumulator();
+            match &process_cache {
+                Some(process_cache) => accumulator.with_process_cache(process_cache.clone()),
+                None => accumulator,
+            }
+        })
+        .collect::<Vec<_>>();
 
     active_threads.push(
         thread::Builder::new()
@@ -86,9 +145,9 @@ pub fn start(server: String, os_input: OsInputOutput) {
                 let machine_id = machine_id.clone();
                 let last_publish_network_configuration = last_publish_network_configuration.clone();
                 let publish_interval = DEFAULT_NETWORK_CONFIGURATION_PUBLISH_INTERVAL;
-                let publish_endpoint = publish_endpoint.clone();
+                let configuration_details = configuration.configuration_details.clone();
 
-                let client = reqwest::blocking::Client::new();
+                let mut sink = HttpSink::new(&server);
                 let mut error_count = 0;
 
                 move || loop {
@@ -98,36 +157,22 @@ pub fn start(server: String, os_input: OsInputOutput) {
                     *last_publish_network_configuration.lock().unwrap() = publish_start_time;
 
                     {
-                        let message = Message::NetworkConfigurationV1Measurement(
-                            NetworkConfigurationV1MeasurementMessage::from(
-                                machine_id.clone(),
-                                SystemTime::now(),
-                                maybe_os_release.clone(),
-                                gethostname::gethostname().into_string().unwrap(),
-                                pnet::datalink::interfaces(),
-                                open_sockets,
-                            ),
+                        let message = Message::network_configuration(
+                            machine_id.clone(),
+                            SystemTime::now(),
+                            maybe_os_release.clone(),
+                            gethostname::gethostname().into_string().unwrap(),
+                            pnet::datalink::interfaces(),
+                            open_sockets,
+                            &configuration_details,
                         );
-                        let publish_result =
-                            client.post(publish_endpoint.clone()).json(&message).send();
-                        match publish_result {
-                            Ok(response) if response.status() == 200 => {
+                        match sink.publish(&message) {
+                            Ok(()) => {
                                 error_count = 0;
                             }
-                            Ok(response) => {
-                                error_count += 1;
-                                eprintln!("Publish error, response: {:?}", response);
-                                if systemd_enabled {
-                                    libsystemd::daemon::notify(
-                                        false,
-                                        &[libsystemd::daemon::NotifyState::Errno(5)],
-                                    )
-                                    .unwrap();
-                                }
-                            }
                             Err(error) => {
                                 error_count += 1;
-                                eprintln!("Publish error, error: {:?}", error);
+                                eprintln!("Publish error, {}", error);
                                 if systemd_enabled {
                                     libsystemd::daemon::notify(
                                         false,
@@ -157,47 +202,77 @@ pub fn start(server: String, os_input: OsInputOutput) {
             .name("publish_network_utilization_handler".to_string())
             .spawn({
                 let last_publish_network_utilization = last_publish_network_utilization.clone();
-                let network_utilization = network_utilization.clone();
+                let mut network_utilization_collector = network_utilization_collector;
                 let publish_interval = DEFAULT_NETWORK_UTILIZATION_PUBLISH_INTERVAL;
-
-                let client = reqwest::blocking::Client::new();
+                let utilization_details = configuration.utilization_details;
+                let dns_cache = dns_cache.clone();
+                let process_cache = process_cache.clone();
+                let read_conntrack = utilization_details.conntrack
+                    || configuration.measurement_source == MeasurementSource::Conntrack;
+                let mut conntrack_meter = match configuration.measurement_source {
+                    MeasurementSource::Capture => None,
+                    MeasurementSource::Conntrack => Some(ConntrackMeter::new(
+                        &pnet::datalink::interfaces(),
+                        &read_conntrack_entries(),
+                        SystemTime::now(),
+                    )),
+                };
+
+                let mut sink = HttpSink::new(&server);
                 let mut error_count = 0;
 
                 move || {
                     park_timeout(publish_interval);
                     loop {
                         let publish_start_time = Instant::now();
-                        let utilization = { network_utilization.lock().unwrap().clone_and_reset() };
+                        let conntrack_entries = if read_conntrack {
+                            read_conntrack_entries()
+                        } else {
+                            vec![]
+                        };
+                        let mut utilization: Utilization = match &mut conntrack_meter {
+                            Some(conntrack_meter) => {
+                                let mut utilization = conntrack_meter
+                                    .measure(&conntrack_entries, SystemTime::now())
+                                    .unwrap_or_else(|error| {
+                                        eprintln!("Conntrack measurement error, {}", error);
+                                        Utilization::new()
+                                    });
+                                if let Some(process_cache) = &process_cache {
+                                    utilization.resolve_processes(process_cache, SystemTime::now());
+                                }
+                                utilization
+                            }
+                            None => network_utilization_collector
+                                .clone_and_reset(NETWORK_UTILIZATION_HAND_OFF_TIMEOUT),
+                        };
+                        if utilization_details.conntrack {
+                            utilization.annotate_conntrack(&ConntrackTable::new(conntrack_entries));
+                        }
+                        if let Some(dns_cache) = &dns_cache {
+                            utilization.resolve_remote_names(dns_cache);
+                        }
 
                         *last_publish_network_utilization.lock().unwrap() = publish_start_time;
 
-                        {
-                            let message = Message::NetworkUtilizationV1Measurement(
-                                NetworkUtilizationV1MeasurementMessage::from(
-                                    machine_id.clone(),
-                                    utilization,
-                                ),
-                            );
-                            let publish_result =
-                                client.post(publish_endpoint.clone()).json(&message).send();
-                            match publish_result {
-                                Ok(response) if response.status() == 200 => {
+                        let transit = Message::network_transit(
+                            machine_id.clone(),
+                            &utilization,
+                            utilization_details,
+                        );
+                        let utilization = Message::network_utilization(
+                            machine_id.clone(),
+                            utilization,
+                            utilization_details,
+                        );
+                        for message in std::iter::once(utilization).chain(transit) {
+                            match sink.publish(&message) {
+                                Ok(()) => {
                                     error_count = 0;
                                 }
-                                Ok(response) => {
-                                    error_count += 1;
-                                    eprintln!("Publish error, response: {:?}", response);
-                                    if systemd_enabled {
-                                        libsystemd::daemon::notify(
-                                            false,
-                                            &[libsystemd::daemon::NotifyState::Errno(5)],
-                                        )
-                                        .unwrap();
-                                    }
-                                }
                                 Err(error) => {
                                     error_count += 1;
-                                    eprintln!("Publish error, error: {:?}", error);
+                                    eprintln!("Publish error, {}", error);
                                     if systemd_enabled {
                                         libsystemd::daemon::notify(
                                             false,
@@ -223,27 +298,109 @@ pub fn start(server: String, os_input: OsInputOutput) {
             .unwrap(),
     );
 
+    let capture_configuration = configuration.capture;
+    let decapsulation_configuration = configuration.decapsulation;
+    let accounting_mode = configuration.utilization_details.accounting_mode;
+    let ignored_scopes = configuration.ignored_scopes;
+    let sniffing_configuration = configuration.sniffing;
+    let forwarding = if sniffing_configuration.router_interfaces.is_empty() {
+        None
+    } else {
+        Some(Forwarding::new(
+            os_input
+                .network_interfaces
+                .iter()
+                .flat_map(|interface| interface.ips.iter().map(|ip_network| ip_network.ip()))
+                .collect(),
+        ))
+    };
+    let capture_rotation = CaptureRotation::default();
+    if capture_configuration.is_some() {
+        match signal_hook::iterator::Signals::new([signal_hook::consts::SIGUSR1]) {
+            Ok(mut signals) => active_threads.push(
+                thread::Builder::new()
+                    .name("capture_rotation_handler".to_string())
+                    .spawn({
+                        let capture_rotation = capture_rotation.clone();
+                        move || {
+                            for _ in signals.forever() {
+                                capture_rotation.request();
+                            }
+                        }
+                    })
+                    .unwrap(),
+            ),
+            Err(error) => eprintln!("Capture rotation on SIGUSR1 unavailable, {}", error),
+        }
+    }
+
     let sniffer_threads = os_input
         .network_interfaces
         .into_iter()
-        .zip(os_input.network_frames.into_iter())
-        .map(|(interface, frames)| {
-            let name = format!("sniffing_handler_{}", interface.name);
-            let network_utilization = network_utilization.clone();
-
-            thread::Builder::new()
-                .name(name)
-                .spawn(move || {
-                    let mut sniffer = Sniffer::new(interface, frames);
+        .zip(os_input.network_frames)
+        .zip(network_utilization_accumulators)
+        .map(
+            |((interface, frames), mut network_utilization_accumulator)| {
+                let name = format!("sniffing_handler_{}", interface.name);
+                let decapsulation = decapsulation_configuration.for_interface(&interface.name);
+                let capture = capture_configuration
+                    .clone()
+                    .and_then(|capture_configuration| {
+                        RingCapture::new(
+                            capture_configuration,
+                            &interface.name,
+                            os::shared::get_link_type(&interface),
+                            capture_rotation.clone(),
+                        )
+                        .map_err(|err| {
+                            eprintln!("Capture on {} unavailable: {}", interface.name, err)
+                        })
+                        .ok()
+                    });
+                let dns_cache = dns_cache.clone();
+                let ignored_scopes = ignored_scopes.clone();
+                let mirror = sniffing_configuration.is_mirror(&interface.name);
+                let promiscuous = sniffing_configuration.is_promiscuous(&interface.name);
+                let forwarding = forwarding
+                    .clone()
+                    .filter(|_| sniffing_configuration.is_router(&interface.name));
+
+                thread::Builder::new()
+                    .name(name)
+                    .spawn(move || {
+                        let mut sniffer = Sniffer::new(interface, frames, promiscuous)
+                            .with_accounting_mode(accounting_mode)
+                            .with_ignored_scopes(ignored_scopes);
+                        if let Some(capture) = capture {
+                            sniffer = sniffer.with_capture(capture);
+                        }
+                        if let Some(decapsulation) = decapsulation {
+                            sniffer = sniffer.with_decapsulation(decapsulation);
+                        }
+                        if let Some(dns_cache) = dns_cache {
+                            sniffer = sniffer.with_dns_cache(dns_cache);
+                        }
+                        if mirror {
+                            sniffer = sniffer.with_mirror();
+                        }
+                        if let Some(forwarding) = forwarding {
+                            sniffer = sniffer.with_forwarding(forwarding);
+                        }
 
-                    loop {
-                        if let Some(segment) = sniffer.next() {
-                            network_utilization.lock().unwrap().update(segment);
+                        loop {
+                            match sniffer.next() {
+                                Some(segment) => network_utilization_accumulator.update(segment),
+                                None => network_utilization_accumulator.rotate(),
+                            }
+                            if let Some(interface_info) = sniffer.take_interface_info() {
+                                network_utilization_accumulator
+                                    .update_interface(sniffer.interface_name(), interface_info);
+                            }
                         }
-                    }
-                })
-                .unwrap()
-        })
+                    })
+                    .unwrap()
+            },
+        )
         .collect::<Vec<_>>();
     active_threads.extend(sniffer_threads);
 
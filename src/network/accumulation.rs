@@ -0,0 +1,295 @@
+use ::std::collections::HashMap;
+use ::std::sync::atomic::{AtomicUsize, Ordering};
+use ::std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use ::std::sync::Arc;
+use ::std::time::{Duration, Instant, SystemTime};
+
+use crate::network::{InterfaceInfo, ProcessCache, Segment, Utilization};
+
+/// Connections collected by a single accumulator up to the moment it noticed an epoch change.
+struct Snapshot {
+    accumulator_id: usize,
+    /// The epoch the first segment of the snapshot was collected in.
+    from_epoch: usize,
+    until_epoch: usize,
+    utilization: Utilization,
+}
+
+/// Collects segments of a single sniffer thread without any shared locking.
+///
+/// The accumulator hands off its local utilization to the [`Collector`] as soon as it notices
+/// that the collector started a new epoch.
+pub struct Accumulator {
+    id: usize,
+    epoch: Arc<AtomicUsize>,
+    current_epoch: usize,
+    utilization: Utilization,
+    snapshots: Sender<Snapshot>,
+    process_cache: Option<ProcessCache>,
+}
+
+impl Accumulator {
+    /// Attributes connections to the process owning their local socket the first time they are
+    /// seen, so processes that exit before the window closes are not lost.
+    pub fn with_process_cache(mut self, process_cache: ProcessCache) -> Self {
+        self.process_cache = Some(process_cache);
+        self
+    }
+    pub fn update(&mut self, segment: Segment) {
+        self.rotate();
+        let connection = segment.connection;
+        let first_sight = !self.utilization.connections.contains_key(&connection);
+        self.utilization.update(segment);
+        if let Some(process_cache) = self.process_cache.as_ref().filter(|_| first_sight) {
+            self.utilization
+                .resolve_process(&connection, process_cache, SystemTime::now());
+        }
+    }
+    pub fn update_interface(&mut self, interface_name: &str, info: InterfaceInfo) {
+        self.rotate();
+        self.utilization.update_interface(interface_name, info);
+    }
+    /// Hands off the local utilization if the epoch changed.
+    ///
+    /// Has to be called regularly, even if no segments arrive, so the collector does not need
+    /// to wait for idle interfaces.
+    pub fn rotate(&mut self) {
+        let epoch = self.epoch.load(Ordering::Acquire);
+        if epoch != self.current_epoch {
+            let snapshot = Snapshot {
+                accumulator_id: self.id,
+                from_epoch: self.current_epoch,
+                until_epoch: epoch,
+                utilization: std::mem::replace(&mut self.utilization, Utilization::new()),
+            };
+            self.current_epoch = epoch;
+            // The collector only disappears on shutdown, so there is nobody left to report to.
+            self.snapshots.send(snapshot).ok();
+        }
+    }
+}
+
+/// Merges the utilization of all accumulators at window boundaries.
+pub struct Collector {
+    epoch: Arc<AtomicUsize>,
+    snapshots_sender: Sender<Snapshot>,
+    snapshots: Receiver<Snapshot>,
+    handed_off_epochs: Vec<usize>,
+    /// Start of the epochs that accumulators may still hand off snapshots of.
+    epoch_starts: HashMap<usize, SystemTime>,
+    pending: Utilization,
+}
+
+impl Collector {
+    pub fn new() -> Self {
+        let (snapshots_sender, snapshots) = channel();
+        let pending = Utilization::new();
+        Collector {
+            epoch: Arc::new(AtomicUsize::new(0)),
+            snapshots_sender,
+            snapshots,
+            handed_off_epochs: vec![],
+            epoch_starts: HashMap::from([(0, pending.start)]),
+            pending,
+        }
+    }
+    pub fn accumulator(&mut self) -> Accumulator {
+        let id = self.handed_off_epochs.len();
+        let current_epoch = self.epoch.load(Ordering::Acquire);
+        self.handed_off_epochs.push(current_epoch);
+        Accumulator {
+            id,
+            epoch: self.epoch.clone(),
+            current_epoch,
+            utilization: Utilization::new(),
+            snapshots: self.snapshots_sender.clone(),
+            process_cache: None,
+        }
+    }
+    /// Closes the current window and returns the merged utilization of all accumulators.
+    ///
+    /// Accumulators that do not hand off within `timeout` are merged into the next window, so
+    /// totals are preserved even if a sniffer is stalled. That window then starts when the
+    /// earliest window merged into it did, so its totals still match its start and stop.
+    pub fn clone_and_reset(&mut self, timeout: Duration) -> Utilization {
+        let stop = SystemTime::now();
+        let closed_epoch = self.epoch.fetch_add(1, Ordering::AcqRel);
+        let deadline = Instant::now() + timeout;
+
+        while self
+            .handed_off_epochs
+            .iter()
+            .any(|&handed_off_epoch| handed_off_epoch <= closed_epoch)
+        {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.snapshots.recv_timeout(remaining) {
+                Ok(snapshot) => self.receive(snapshot),
+                Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        let mut utilization = std::mem::replace(&mut self.pending, Utilization::new());
+        utilization.stop = stop;
+        self.pending.start = stop;
+        self.pending.stop = stop;
+        self.epoch_starts.insert(closed_epoch + 1, stop);
+        let earliest_epoch = self
+            .handed_off_epochs
+            .iter()
+            .copied()
+            .min()
+            .unwrap_or(closed_epoch + 1);
+        self.epoch_starts
+            .retain(|&epoch, _| epoch >= earliest_epoch);
+        utilization
+    }
+    fn receive(&mut self, snapshot: Snapshot) {
+        self.handed_off_epochs[snapshot.accumulator_id] = snapshot.until_epoch;
+        if let Some(&start) = self.epoch_starts.get(&snapshot.from_epoch) {
+            self.pending.start = self.pending.start.min(start);
+        }
+        self.pending.merge(snapshot.utilization);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+
+    use crate::network::{fixtures, Connection, Direction, LocalSocket, Process, Protocol};
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn segment(port: u16, direction: Direction, data_length: u128) -> Segment {
+        Segment {
+            timestamp: SystemTime::now(),
+            ..fixtures::segment(
+                Connection::new(
+                    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 443),
+                    IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+                    port,
+                    Protocol::Tcp,
+                ),
+                direction,
+                data_length,
+            )
+        }
+    }
+
+    fn total_bytes(utilization: &Utilization) -> u128 {
+        utilization
+            .connections
+            .values()
+            .map(|info| info.total_bytes_downloaded + info.total_bytes_uploaded)
+            .sum()
+    }
+
+    #[test]
+    fn should_merge_all_accumulators_at_window_boundary() {
+        // given
+        let mut collector = Collector::new();
+        let mut first = collector.accumulator();
+        let mut second = collector.accumulator();
+        first.update(segment(1000, Direction::Download, 100));
+        second.update(segment(1000, Direction::Upload, 20));
+        second.update(segment(2000, Direction::Download, 3));
+
+        // when
+        let handle = thread::spawn(move || {
+            while first.current_epoch == 0 || second.current_epoch == 0 {
+                first.rotate();
+                second.rotate();
+            }
+            first
+        });
+        let result = collector.clone_and_reset(TIMEOUT);
+        let mut first = handle.join().unwrap();
+        first.update(segment(1000, Direction::Download, 7));
+        let next_result = collector.clone_and_reset(Duration::from_millis(10));
+
+        // then
+        assert_eq!(result.connections.len(), 2);
+        assert_eq!(total_bytes(&result), 123);
+        assert_eq!(total_bytes(&next_result), 0);
+        assert_eq!(next_result.start, result.stop);
+    }
+
+    #[test]
+    fn should_resolve_processes_when_connections_are_first_seen() {
+        // given
+        static EXITED: AtomicBool = AtomicBool::new(false);
+        fn socket_inodes() -> HashMap<LocalSocket, u64> {
+            if EXITED.load(Ordering::Acquire) {
+                return HashMap::new();
+            }
+            HashMap::from([(
+                segment(1000, Direction::Upload, 0).connection.local_socket,
+                7,
+            )])
+        }
+        fn inode_processes() -> HashMap<u64, Process> {
+            if EXITED.load(Ordering::Acquire) {
+                return HashMap::new();
+            }
+            HashMap::from([(
+                7,
+                Process {
+                    pid: 1234,
+                    name: "curl".to_string(),
+                    container: None,
+                    systemd_unit: None,
+                },
+            )])
+        }
+        let mut collector = Collector::new();
+        let mut accumulator = collector
+            .accumulator()
+            .with_process_cache(ProcessCache::new(socket_inodes, inode_processes));
+
+        // when
+        accumulator.update(segment(1000, Direction::Upload, 10));
+        EXITED.store(true, Ordering::Release);
+        accumulator.update(segment(1000, Direction::Download, 20));
+        let handle = thread::spawn(move || {
+            while accumulator.current_epoch == 0 {
+                accumulator.rotate();
+            }
+        });
+        let result = collector.clone_and_reset(TIMEOUT);
+        handle.join().unwrap();
+
+        // then
+        let info = result.connections.values().next().unwrap();
+        assert_eq!(info.process.as_ref().unwrap().pid, 1234);
+        assert_eq!(total_bytes(&result), 30);
+    }
+
+    #[test]
+    fn should_carry_over_late_hand_offs_into_next_window() {
+        // given
+        let mut collector = Collector::new();
+        let mut accumulator = collector.accumulator();
+        accumulator.update(segment(1000, Direction::Download, 42));
+
+        // when
+        let first_result = collector.clone_and_reset(Duration::from_millis(10));
+        accumulator.update(segment(1000, Direction::Download, 8));
+        let second_result = collector.clone_and_reset(Duration::from_millis(10));
+        accumulator.rotate();
+        let third_result = collector.clone_and_reset(Duration::from_millis(10));
+
+        // then
+        assert_eq!(total_bytes(&first_result), 0);
+        assert_eq!(total_bytes(&second_result), 42);
+        assert_eq!(second_result.start, first_result.start);
+        assert_eq!(third_result.start, first_result.stop);
+        assert_eq!(
+            total_bytes(&first_result) + total_bytes(&second_result) + total_bytes(&third_result),
+            50
+        );
+    }
+}
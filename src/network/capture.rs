@@ -0,0 +1,393 @@
+use ::std::collections::VecDeque;
+use ::std::fs::{self, File};
+use ::std::io::{BufWriter, Result, Write};
+use ::std::path::{Path, PathBuf};
+use ::std::sync::atomic::{AtomicUsize, Ordering};
+use ::std::sync::Arc;
+use ::std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ::ipnetwork::IpNetwork;
+use ::pcap_file::pcapng::blocks::enhanced_packet::EnhancedPacketBlock;
+use ::pcap_file::pcapng::blocks::interface_description::{
+    InterfaceDescriptionBlock, InterfaceDescriptionOption,
+};
+use ::pcap_file::pcapng::PcapNgWriter;
+
+use crate::network::{Frame, LinkType, Segment};
+
+const CAPTURE_FILE_EXTENSION: &str = "pcapng";
+const NANOSECOND_TIMESTAMP_RESOLUTION: u8 = 9;
+
+#[derive(Clone)]
+pub struct CaptureConfiguration {
+    pub directory: PathBuf,
+    pub snaplen: u32,
+    pub max_file_size: u64,
+    pub max_file_duration: Duration,
+    pub max_files: usize,
+    pub filter: CaptureFilter,
+}
+
+/// Restricts captured frames to segments matching all given criteria, an empty criterion
+/// matching everything.
+///
+/// Ports and networks match either the local or the remote side of a connection.
+#[derive(Clone, Default)]
+pub struct CaptureFilter {
+    pub ports: Vec<u16>,
+    pub networks: Vec<IpNetwork>,
+}
+
+impl CaptureFilter {
+    pub fn is_empty(&self) -> bool {
+        self.ports.is_empty() && self.networks.is_empty()
+    }
+    fn matches(&self, segment: Option<&Segment>) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let segment = match segment {
+            Some(segment) => segment,
+            None => return false,
+        };
+        let remote_socket = segment.connection.remote_socket;
+        let local_socket = segment.connection.local_socket;
+        (self.ports.is_empty()
+            || self.ports.contains(&remote_socket.port)
+            || self.ports.contains(&local_socket.port))
+            && (self.networks.is_empty()
+                || self.networks.iter().any(|network| {
+                    network.contains(remote_socket.ip) || network.contains(local_socket.ip)
+                }))
+    }
+}
+
+/// Requests all captures to close their current file, so the recent frames can be retrieved.
+#[derive(Clone, Default)]
+pub struct CaptureRotation {
+    generation: Arc<AtomicUsize>,
+}
+
+impl CaptureRotation {
+    pub fn request(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+    fn current(&self) -> usize {
+        self.generation.load(Ordering::Acquire)
+    }
+}
+
+struct CaptureFile {
+    writer: PcapNgWriter<BufWriter<File>>,
+    opened: SystemTime,
+    size: u64,
+}
+
+/// Keeps the most recent frames of a single interface in a ring of pcapng files.
+///
+/// Files are named `<interface>-<unix timestamp>-<sequence>.pcapng`, the oldest files are
+/// deleted once more than the configured number of files exist.
+pub struct RingCapture {
+    configuration: CaptureConfiguration,
+    interface_name: String,
+    link_type: LinkType,
+    rotation: CaptureRotation,
+    rotation_generation: usize,
+    sequence: u64,
+    current: Option<CaptureFile>,
+    files: VecDeque<PathBuf>,
+}
+
+impl RingCapture {
+    pub fn new(
+        configuration: CaptureConfiguration,
+        interface_name: &str,
+        link_type: LinkType,
+        rotation: CaptureRotation,
+    ) -> Result<Self> {
+        fs::create_dir_all(&configuration.directory)?;
+        let files = existing_files(&configuration.directory, interface_name)?;
+        let rotation_generation = rotation.current();
+        Ok(RingCapture {
+            configuration,
+            interface_name: interface_name.to_string(),
+            link_type,
+            rotation,
+            rotation_generation,
+            sequence: 0,
+            current: None,
+            files,
+        })
+    }
+    pub fn record(&mut self, frame: &Frame, segment: Option<&Segment>) -> Result<()> {
+        self.maintain(frame.timestamp)?;
+        if !self.configuration.filter.matches(segment) {
+            return Ok(());
+        }
+        if self.current.is_none() {
+            self.open(frame.timestamp)?;
+        }
+        let snaplen = self.configuration.snaplen as usize;
+        let current = self
+            .current
+            .as_mut()
+            .expect("capture file has just been opened");
+        let block = EnhancedPacketBlock {
+            interface_id: 0,
+            timestamp: frame
+                .timestamp
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+            original_len: frame.bytes.len() as u32,
+            data: frame.bytes[..frame.bytes.len().min(snaplen)].into(),
+            options: vec![],
+        };
+        current.size += current
+            .writer
+            .write_pcapng_block(block)
+            .map_err(pcap_error)? as u64;
+        Ok(())
+    }
+    /// Closes the current file if it is full, too old or a rotation has been requested.
+    pub fn maintain(&mut self, now: SystemTime) -> Result<()> {
+        let rotation_generation = self.rotation.current();
+        let rotation_requested = rotation_generation != self.rotation_generation;
+        self.rotation_generation = rotation_generation;
+        let expired = match &self.current {
+            None => false,
+            Some(current) => {
+                rotation_requested
+                    || current.size >= self.configuration.max_file_size
+                    || now.duration_since(current.opened).unwrap_or_default()
+                        >= self.configuration.max_file_duration
+            }
+        };
+        if expired {
+            self.close()?;
+        }
+        Ok(())
+    }
+    fn open(&mut self, now: SystemTime) -> Result<()> {
+        let path = self.configuration.directory.join(format!(
+            "{}-{}-{}.{}",
+            self.interface_name,
+            now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            self.sequence,
+            CAPTURE_FILE_EXTENSION
+        ));
+        self.sequence += 1;
+        let mut writer =
+            PcapNgWriter::new(BufWriter::new(File::create(&path)?)).map_err(pcap_error)?;
+        let mut interface =
+            InterfaceDescriptionBlock::new(self.link_type.data_link(), self.configuration.snaplen);
+        interface.options = vec![
+            InterfaceDescriptionOption::IfName(self.interface_name.clone().into()),
+            InterfaceDescriptionOption::IfTsResol(NANOSECOND_TIMESTAMP_RESOLUTION),
+        ];
+        let size = writer.write_pcapng_block(interface).map_err(pcap_error)? as u64;
+        self.files.push_back(path);
+        while self.files.len() > self.configuration.max_files {
+            if let Some(oldest) = self.files.pop_front() {
+                fs::remove_file(oldest).ok();
+            }
+        }
+        self.current = Some(CaptureFile {
+            writer,
+            opened: now,
+            size,
+        });
+        Ok(())
+    }
+    fn close(&mut self) -> Result<()> {
+        if let Some(current) = self.current.take() {
+            current.writer.into_inner().flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RingCapture {
+    fn drop(&mut self) {
+        self.close().ok();
+    }
+}
+
+/// Capture files of previous runs stay part of the ring, ordered by their timestamp.
+fn existing_files(directory: &Path, interface_name: &str) -> Result<VecDeque<PathBuf>> {
+    let prefix = format!("{}-", interface_name);
+    let mut files = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter_map(|path| {
+            let file_name = path.file_name()?.to_str()?;
+            let name = file_name
+                .strip_prefix(&prefix)?
+                .strip_suffix(CAPTURE_FILE_EXTENSION)?
+                .strip_suffix('.')?;
+            let (timestamp, sequence) = name.split_once('-')?;
+            let key = (
+                timestamp.parse::<u64>().ok()?,
+                sequence.parse::<u64>().ok()?,
+            );
+            Some((key, path))
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    Ok(files.into_iter().map(|(_, path)| path).collect())
+}
+
+fn pcap_error(error: pcap_file::PcapError) -> std::io::Error {
+    std::io::Error::other(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    use crate::network::Frames;
+    use crate::network::{fixtures, Connection, Direction, Protocol, RecordedFrames};
+
+    use super::*;
+
+    fn configuration(directory: &Path) -> CaptureConfiguration {
+        CaptureConfiguration {
+            directory: directory.to_path_buf(),
+            snaplen: 4,
+            max_file_size: 1024 * 1024,
+            max_file_duration: Duration::from_secs(60),
+            max_files: 2,
+            filter: CaptureFilter::default(),
+        }
+    }
+
+    fn test_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!(
+            "bandwhichd-agent-capture-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::remove_dir_all(&directory).ok();
+        directory
+    }
+
+    fn files(directory: &Path) -> Vec<String> {
+        let mut files = fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    fn segment(remote_port: u16) -> Segment {
+        fixtures::segment(
+            Connection::new(
+                SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), remote_port),
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+                40000,
+                Protocol::Tcp,
+            ),
+            Direction::Download,
+            0,
+        )
+    }
+
+    #[test]
+    fn should_write_truncated_frames_readable_as_recording() {
+        // given
+        let directory = test_directory("truncate");
+        let mut capture = RingCapture::new(
+            configuration(&directory),
+            "eth0",
+            LinkType::Ethernet,
+            CaptureRotation::default(),
+        )
+        .unwrap();
+        let timestamp = UNIX_EPOCH + Duration::from_nanos(1_651_850_091_742_237_280);
+
+        // when
+        capture
+            .record(
+                &Frame {
+                    timestamp,
+                    link_type: LinkType::Ethernet,
+                    bytes: &[1, 2, 3, 4, 5, 6],
+                },
+                None,
+            )
+            .unwrap();
+        drop(capture);
+
+        // then
+        let files = files(&directory);
+        assert_eq!(files, vec!["eth0-1651850091-0.pcapng".to_string()]);
+        let mut frames =
+            RecordedFrames::new(File::open(directory.join(&files[0])).unwrap()).unwrap();
+        let frame = frames.next().unwrap().unwrap();
+        assert_eq!(frame.timestamp, timestamp);
+        assert_eq!(frame.bytes, &[1, 2, 3, 4]);
+        fs::remove_dir_all(&directory).ok();
+    }
+
+    #[test]
+    fn should_rotate_and_keep_only_most_recent_files() {
+        // given
+        let directory = test_directory("rotate");
+        let rotation = CaptureRotation::default();
+        let mut capture = RingCapture::new(
+            configuration(&directory),
+            "eth0",
+            LinkType::Ethernet,
+            rotation.clone(),
+        )
+        .unwrap();
+        let frame = |seconds| Frame {
+            timestamp: UNIX_EPOCH + Duration::from_secs(seconds),
+            link_type: LinkType::Ethernet,
+            bytes: &[0; 8],
+        };
+
+        // when
+        capture.record(&frame(1000), None).unwrap();
+        capture.record(&frame(1030), None).unwrap();
+        capture.record(&frame(1060), None).unwrap();
+        rotation.request();
+        capture.record(&frame(1061), None).unwrap();
+        drop(capture);
+
+        // then
+        assert_eq!(
+            files(&directory),
+            vec![
+                "eth0-1060-1.pcapng".to_string(),
+                "eth0-1061-2.pcapng".to_string()
+            ]
+        );
+        fs::remove_dir_all(&directory).ok();
+    }
+
+    #[test]
+    fn should_filter_frames_by_segment() {
+        // given
+        let filter = CaptureFilter {
+            ports: vec![443],
+            networks: vec!["10.0.0.0/24".parse().unwrap()],
+        };
+        let local_filter = CaptureFilter {
+            ports: vec![],
+            networks: vec!["10.0.0.1/32".parse().unwrap()],
+        };
+        let other_filter = CaptureFilter {
+            ports: vec![],
+            networks: vec!["192.168.0.0/16".parse().unwrap()],
+        };
+
+        // then
+        assert!(filter.matches(Some(&segment(443))));
+        assert!(!filter.matches(Some(&segment(80))));
+        assert!(local_filter.matches(Some(&segment(80))));
+        assert!(!other_filter.matches(Some(&segment(80))));
+        assert!(!filter.matches(None));
+        assert!(CaptureFilter::default().matches(None));
+    }
+}
@@ -7,6 +7,25 @@ use ::std::net::SocketAddr;
 pub enum Protocol {
     Tcp,
     Udp,
+    /// ICMP flows have no ports, both sockets use port 0.
+    Icmp(IcmpKind),
+    Icmpv6(IcmpKind),
+    Sctp,
+    /// Any other IP protocol by its number, e.g. GRE or ESP. Both sockets use port 0.
+    Other(u8),
+}
+
+/// Identifies an ICMP flow instead of ports.
+#[derive(PartialEq, Hash, Eq, Clone, PartialOrd, Ord, Debug, Copy)]
+pub enum IcmpKind {
+    /// Echo requests and replies, so both directions of a ping share a connection.
+    Echo {
+        identifier: u16,
+    },
+    Message {
+        icmp_type: u8,
+        code: u8,
+    },
 }
 
 impl fmt::Display for Protocol {
@@ -14,6 +33,10 @@ impl fmt::Display for Protocol {
         match *self {
             Protocol::Tcp => write!(f, "tcp"),
             Protocol::Udp => write!(f, "udp"),
+            Protocol::Icmp(_) => write!(f, "icmp"),
+            Protocol::Icmpv6(_) => write!(f, "icmpv6"),
+            Protocol::Sctp => write!(f, "sctp"),
+            Protocol::Other(number) => write!(f, "ip-{}", number),
         }
     }
 }
@@ -47,6 +70,9 @@ impl From<LocalSocket> for SocketAddr {
 pub struct Connection {
     pub remote_socket: Socket,
     pub local_socket: LocalSocket,
+    /// The network identifier of the tunnel the connection has been decapsulated from, telling
+    /// apart identical flows of tenant networks using the same addresses.
+    pub vni: Option<u32>,
 }
 
 impl Connection {
@@ -66,6 +92,7 @@ impl Connection {
                 port: local_port,
                 protocol,
             },
+            vni: None,
         }
     }
 }
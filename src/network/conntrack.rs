@@ -0,0 +1,835 @@
+use ::std::collections::HashMap;
+use ::std::convert::TryFrom;
+use ::std::io;
+use ::std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use ::std::str::FromStr;
+use ::std::time::SystemTime;
+
+use ::pnet::datalink::NetworkInterface;
+
+use crate::network::{
+    Connection, ConnectionInfo, IcmpKind, InterfaceInfo, Protocol, Socket, TransitFlow, Utilization,
+};
+
+const NETLINK_HEADER_LENGTH: usize = 16;
+const NFGENMSG_LENGTH: usize = 4;
+const ATTRIBUTE_HEADER_LENGTH: usize = 4;
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+const NLM_F_REQUEST: u16 = 0x01;
+const NLM_F_DUMP: u16 = 0x300;
+const NFNL_SUBSYS_CTNETLINK: u16 = 1;
+const IPCTNL_MSG_CT_NEW: u16 = 0;
+const IPCTNL_MSG_CT_GET: u16 = 1;
+const NFNETLINK_V0: u8 = 0;
+const NLA_TYPE_MASK: u16 = 0x3fff;
+const CTA_TUPLE_ORIG: u16 = 1;
+const CTA_TUPLE_REPLY: u16 = 2;
+const CTA_COUNTERS_ORIG: u16 = 9;
+const CTA_COUNTERS_REPLY: u16 = 10;
+const CTA_TUPLE_IP: u16 = 1;
+const CTA_TUPLE_PROTO: u16 = 2;
+const CTA_IP_V4_SRC: u16 = 1;
+const CTA_IP_V4_DST: u16 = 2;
+const CTA_IP_V6_SRC: u16 = 3;
+const CTA_IP_V6_DST: u16 = 4;
+const CTA_PROTO_NUM: u16 = 1;
+const CTA_PROTO_SRC_PORT: u16 = 2;
+const CTA_PROTO_DST_PORT: u16 = 3;
+const CTA_PROTO_ICMP_ID: u16 = 4;
+const CTA_PROTO_ICMP_TYPE: u16 = 5;
+const CTA_PROTO_ICMP_CODE: u16 = 6;
+const CTA_PROTO_ICMPV6_ID: u16 = 7;
+const CTA_PROTO_ICMPV6_TYPE: u16 = 8;
+const CTA_PROTO_ICMPV6_CODE: u16 = 9;
+const CTA_COUNTERS_PACKETS: u16 = 1;
+const CTA_COUNTERS_BYTES: u16 = 2;
+const PROTOCOL_ICMP: u8 = 1;
+const PROTOCOL_TCP: u8 = 6;
+const PROTOCOL_UDP: u8 = 17;
+const PROTOCOL_ICMPV6: u8 = 58;
+const PROTOCOL_SCTP: u8 = 132;
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+/// Packets and bytes of one direction of a conntrack entry, counted at the IP layer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConntrackCounters {
+    pub packets: u128,
+    pub bytes: u128,
+}
+
+/// A connection tracked by netfilter, the reply tuple differing from the reversed original tuple
+/// if its addresses or ports are translated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConntrackEntry {
+    pub original: TransitFlow,
+    pub reply: TransitFlow,
+    /// Only counted if `net.netfilter.nf_conntrack_acct` is enabled.
+    pub original_counters: Option<ConntrackCounters>,
+    pub reply_counters: Option<ConntrackCounters>,
+}
+
+/// Builds a ctnetlink request dumping all conntrack entries of all address families.
+pub fn conntrack_dump_request(sequence: u32) -> Vec<u8> {
+    let length = (NETLINK_HEADER_LENGTH + NFGENMSG_LENGTH) as u32;
+    let mut request = length.to_ne_bytes().to_vec();
+    request.extend_from_slice(&((NFNL_SUBSYS_CTNETLINK << 8) | IPCTNL_MSG_CT_GET).to_ne_bytes());
+    request.extend_from_slice(&(NLM_F_REQUEST | NLM_F_DUMP).to_ne_bytes());
+    request.extend_from_slice(&sequence.to_ne_bytes());
+    request.extend_from_slice(&0u32.to_ne_bytes());
+    // AF_UNSPEC, version, resource id
+    request.extend_from_slice(&[0, NFNETLINK_V0, 0, 0]);
+    request
+}
+
+/// Parses the conntrack entries of a buffer of netlink messages answering a dump request,
+/// returning whether the dump is done.
+pub fn parse_conntrack_messages(buffer: &[u8]) -> io::Result<(Vec<ConntrackEntry>, bool)> {
+    let mut entries = vec![];
+    let mut offset = 0;
+    while let Some(header) = buffer.get(offset..offset + NETLINK_HEADER_LENGTH) {
+        let length = u32::from_ne_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let message_type = u16::from_ne_bytes([header[4], header[5]]);
+        let message = buffer
+            .get(offset + NETLINK_HEADER_LENGTH..offset + length)
+            .filter(|_| length >= NETLINK_HEADER_LENGTH)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "truncated message"))?;
+        match message_type {
+            NLMSG_DONE => return Ok((entries, true)),
+            NLMSG_ERROR => {
+                let error = message.get(..4).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "truncated error message")
+                })?;
+                let errno = i32::from_ne_bytes([error[0], error[1], error[2], error[3]]);
+                if errno != 0 {
+                    return Err(io::Error::from_raw_os_error(-errno));
+                }
+            }
+            message_type if message_type == (NFNL_SUBSYS_CTNETLINK << 8) | IPCTNL_MSG_CT_NEW => {
+                if let Some(entry) = message.get(NFGENMSG_LENGTH..).and_then(parse_entry) {
+                    entries.push(entry);
+                }
+            }
+            _ => {}
+        }
+        offset += align(length);
+    }
+    Ok((entries, false))
+}
+
+fn align(length: usize) -> usize {
+    (length + 3) & !3
+}
+
+/// Iterates over the type and value of netlink attributes.
+fn attributes(mut buffer: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        let header = buffer.get(..ATTRIBUTE_HEADER_LENGTH)?;
+        let length = usize::from(u16::from_ne_bytes([header[0], header[1]]));
+        let attribute_type = u16::from_ne_bytes([header[2], header[3]]) & NLA_TYPE_MASK;
+        if length < ATTRIBUTE_HEADER_LENGTH {
+            return None;
+        }
+        let value = buffer.get(ATTRIBUTE_HEADER_LENGTH..length)?;
+        buffer = buffer.get(align(length)..).unwrap_or_default();
+        Some((attribute_type, value))
+    })
+}
+
+fn be_u16(value: &[u8]) -> Option<u16> {
+    Some(u16::from_be_bytes(<[u8; 2]>::try_from(value).ok()?))
+}
+
+fn be_u64(value: &[u8]) -> Option<u64> {
+    Some(u64::from_be_bytes(<[u8; 8]>::try_from(value).ok()?))
+}
+
+fn parse_entry(attributes_buffer: &[u8]) -> Option<ConntrackEntry> {
+    let mut original = None;
+    let mut reply = None;
+    let mut original_counters = None;
+    let mut reply_counters = None;
+    for (attribute_type, value) in attributes(attributes_buffer) {
+        match attribute_type {
+            CTA_TUPLE_ORIG => original = parse_tuple(value),
+            CTA_TUPLE_REPLY => reply = parse_tuple(value),
+            CTA_COUNTERS_ORIG => original_counters = parse_counters(value),
+            CTA_COUNTERS_REPLY => reply_counters = parse_counters(value),
+            _ => {}
+        }
+    }
+    Some(ConntrackEntry {
+        original: original?,
+        reply: reply?,
+        original_counters,
+        reply_counters,
+    })
+}
+
+fn parse_tuple(tuple: &[u8]) -> Option<TransitFlow> {
+    let mut source = None;
+    let mut destination = None;
+    let mut protocol_number = None;
+    let mut ports = (0, 0);
+    let (mut icmp_identifier, mut icmp_type, mut icmp_code) = (None, None, None);
+    for (attribute_type, value) in attributes(tuple) {
+        match attribute_type {
+            CTA_TUPLE_IP => {
+                for (attribute_type, value) in attributes(value) {
+                    match attribute_type {
+                        CTA_IP_V4_SRC => source = ipv4(value),
+                        CTA_IP_V4_DST => destination = ipv4(value),
+                        CTA_IP_V6_SRC => source = ipv6(value),
+                        CTA_IP_V6_DST => destination = ipv6(value),
+                        _ => {}
+                    }
+                }
+            }
+            CTA_TUPLE_PROTO => {
+                for (attribute_type, value) in attributes(value) {
+                    match attribute_type {
+                        CTA_PROTO_NUM => protocol_number = value.first().copied(),
+                        CTA_PROTO_SRC_PORT => ports.0 = be_u16(value)?,
+                        CTA_PROTO_DST_PORT => ports.1 = be_u16(value)?,
+                        CTA_PROTO_ICMP_ID | CTA_PROTO_ICMPV6_ID => icmp_identifier = be_u16(value),
+                        CTA_PROTO_ICMP_TYPE | CTA_PROTO_ICMPV6_TYPE => {
+                            icmp_type = value.first().copied()
+                        }
+                        CTA_PROTO_ICMP_CODE | CTA_PROTO_ICMPV6_CODE => {
+                            icmp_code = value.first().copied()
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    tuple_flow(
+        source?,
+        destination?,
+        protocol_number?,
+        ports,
+        (icmp_identifier, icmp_type, icmp_code),
+    )
+}
+
+fn ipv4(value: &[u8]) -> Option<IpAddr> {
+    Some(Ipv4Addr::from(<[u8; 4]>::try_from(value).ok()?).into())
+}
+
+fn ipv6(value: &[u8]) -> Option<IpAddr> {
+    Some(Ipv6Addr::from(<[u8; 16]>::try_from(value).ok()?).into())
+}
+
+fn parse_counters(counters: &[u8]) -> Option<ConntrackCounters> {
+    let mut packets = None;
+    let mut bytes = None;
+    for (attribute_type, value) in attributes(counters) {
+        match attribute_type {
+            CTA_COUNTERS_PACKETS => packets = be_u64(value),
+            CTA_COUNTERS_BYTES => bytes = be_u64(value),
+            _ => {}
+        }
+    }
+    Some(ConntrackCounters {
+        packets: u128::from(packets?),
+        bytes: u128::from(bytes?),
+    })
+}
+
+/// Keys a conntrack tuple the way the sniffer keys flows, without ports for protocols the
+/// sniffer does not read them of.
+fn tuple_flow(
+    source: IpAddr,
+    destination: IpAddr,
+    protocol_number: u8,
+    ports: (u16, u16),
+    (icmp_identifier, icmp_type, icmp_code): (Option<u16>, Option<u8>, Option<u8>),
+) -> Option<TransitFlow> {
+    let icmp_kind = |echo_request: u8, echo_reply: u8| -> Option<IcmpKind> {
+        let icmp_type = icmp_type?;
+        if icmp_type == echo_request || icmp_type == echo_reply {
+            Some(IcmpKind::Echo {
+                identifier: icmp_identifier?,
+            })
+        } else {
+            Some(IcmpKind::Message {
+                icmp_type,
+                code: icmp_code?,
+            })
+        }
+    };
+    let (protocol, ports) = match protocol_number {
+        PROTOCOL_TCP => (Protocol::Tcp, ports),
+        PROTOCOL_UDP => (Protocol::Udp, ports),
+        PROTOCOL_SCTP => (Protocol::Sctp, ports),
+        PROTOCOL_ICMP => (
+            Protocol::Icmp(icmp_kind(ICMP_ECHO_REQUEST, ICMP_ECHO_REPLY)?),
+            (0, 0),
+        ),
+        PROTOCOL_ICMPV6 => (
+            Protocol::Icmpv6(icmp_kind(ICMPV6_ECHO_REQUEST, ICMPV6_ECHO_REPLY)?),
+            (0, 0),
+        ),
+        other => (Protocol::Other(other), (0, 0)),
+    };
+    Some(TransitFlow::new(
+        SocketAddr::new(source, ports.0),
+        SocketAddr::new(destination, ports.1),
+        protocol,
+    ))
+}
+
+/// Parses the entries of `/proc/net/nf_conntrack`, whose lines list the fields of the original
+/// tuple before those of the reply tuple.
+pub fn parse_proc_conntrack(table: &str) -> Vec<ConntrackEntry> {
+    table
+        .lines()
+        .filter_map(parse_proc_conntrack_line)
+        .collect()
+}
+
+fn parse_proc_conntrack_line(line: &str) -> Option<ConntrackEntry> {
+    let mut fields = line.split_whitespace();
+    let protocol_number = fields.nth(3)?.parse::<u8>().ok()?;
+    // one set of fields per tuple, a repeated source starting the reply tuple
+    let mut tuples: Vec<HashMap<&str, &str>> = vec![];
+    for field in fields {
+        if let Some((key, value)) = field.split_once('=') {
+            if key == "src" || tuples.is_empty() {
+                tuples.push(HashMap::new());
+            }
+            tuples.last_mut()?.insert(key, value);
+        }
+    }
+    let tuple = |fields: &HashMap<&str, &str>| -> Option<TransitFlow> {
+        tuple_flow(
+            field(fields, "src")?,
+            field(fields, "dst")?,
+            protocol_number,
+            (
+                field(fields, "sport").unwrap_or(0),
+                field(fields, "dport").unwrap_or(0),
+            ),
+            (
+                field(fields, "id"),
+                field(fields, "type"),
+                field(fields, "code"),
+            ),
+        )
+    };
+    let counters = |fields: &HashMap<&str, &str>| -> Option<ConntrackCounters> {
+        Some(ConntrackCounters {
+            packets: field(fields, "packets")?,
+            bytes: field(fields, "bytes")?,
+        })
+    };
+    let original = tuples.first()?;
+    let reply = tuples.get(1)?;
+    Some(ConntrackEntry {
+        original: tuple(original)?,
+        reply: tuple(reply)?,
+        original_counters: counters(original),
+        reply_counters: counters(reply),
+    })
+}
+
+fn field<T: FromStr>(fields: &HashMap<&str, &str>, key: &str) -> Option<T> {
+    fields.get(key)?.parse().ok()
+}
+
+/// Whether the kernel counts the traffic of the entries, which it does not if
+/// `net.netfilter.nf_conntrack_acct` is disabled.
+pub fn are_counted(entries: &[ConntrackEntry]) -> bool {
+    entries.is_empty()
+        || entries
+            .iter()
+            .any(|entry| entry.original_counters.is_some() || entry.reply_counters.is_some())
+}
+
+fn reversed(flow: &TransitFlow) -> TransitFlow {
+    TransitFlow {
+        source: flow.destination,
+        destination: flow.source,
+        protocol: flow.protocol,
+    }
+}
+
+/// Conntrack entries looked up by the flows of either of their tuples.
+pub struct ConntrackTable {
+    entries: Vec<ConntrackEntry>,
+    index: HashMap<TransitFlow, usize>,
+}
+
+impl ConntrackTable {
+    pub fn new(entries: Vec<ConntrackEntry>) -> Self {
+        let mut index = HashMap::new();
+        for (position, entry) in entries.iter().enumerate() {
+            for flow in [
+                entry.original,
+                reversed(&entry.original),
+                entry.reply,
+                reversed(&entry.reply),
+            ] {
+                index.entry(flow).or_insert(position);
+            }
+        }
+        ConntrackTable { entries, index }
+    }
+    pub fn lookup(&self, connection: &Connection) -> Option<&ConntrackEntry> {
+        let flow = TransitFlow {
+            source: Socket {
+                ip: connection.local_socket.ip,
+                port: connection.local_socket.port,
+            },
+            destination: connection.remote_socket,
+            protocol: connection.local_socket.protocol,
+        };
+        self.index
+            .get(&flow)
+            .map(|position| &self.entries[*position])
+    }
+}
+
+/// Measures the utilization of the connections of this host by the differences of conntrack
+/// counters, instead of capturing traffic.
+///
+/// Entries destroyed between two measurements lose their traffic since the earlier one, entries
+/// created and destroyed between them are not reported at all. Forwarded connections are not
+/// reported either. Connections translated to an address of this host
+/// are reported by their reply tuple, which has the local socket.
+///
+/// Entries are only counted if `net.netfilter.nf_conntrack_acct` is enabled.
+pub struct ConntrackMeter {
+    interfaces: Vec<(IpAddr, String)>,
+    counters: HashMap<TransitFlow, (ConntrackCounters, ConntrackCounters)>,
+    last_measurement: SystemTime,
+}
+
+impl ConntrackMeter {
+    /// Starts measuring from the given entries, so traffic before is not reported.
+    pub fn new(
+        network_interfaces: &[NetworkInterface],
+        entries: &[ConntrackEntry],
+        now: SystemTime,
+    ) -> Self {
+        let mut meter = ConntrackMeter {
+            interfaces: network_interfaces
+                .iter()
+                .flat_map(|interface| {
+                    interface
+                        .ips
+                        .iter()
+                        .map(move |ip_network| (ip_network.ip(), interface.name.clone()))
+                })
+                .collect(),
+            counters: HashMap::new(),
+            last_measurement: now,
+        };
+        // uncounted entries are only reported as such by later measurements
+        meter.measure(entries, now).ok();
+        meter
+    }
+    /// Fails without changing the state of the meter if none of the entries are counted.
+    pub fn measure(
+        &mut self,
+        entries: &[ConntrackEntry],
+        now: SystemTime,
+    ) -> Result<Utilization, failure::Error> {
+        if !are_counted(entries) {
+            failure::bail!(
+                "conntrack entries are not counted, net.netfilter.nf_conntrack_acct is disabled"
+            );
+        }
+        let mut utilization = Utilization::starting_at(self.last_measurement);
+        utilization.stop = now;
+        self.last_measurement = now;
+        let mut counters = HashMap::with_capacity(entries.len());
+        for entry in entries {
+            let current = match (entry.original_counters, entry.reply_counters) {
+                (None, None) => continue,
+                (original, reply) => (original.unwrap_or_default(), reply.unwrap_or_default()),
+            };
+            let previous = self.counters.get(&entry.original).copied();
+            counters.insert(entry.original, current);
+            let (original, reply) = match previous {
+                Some(previous) => (
+                    difference(current.0, previous.0),
+                    difference(current.1, previous.1),
+                ),
+                None => current,
+            };
+            if original.packets == 0 && reply.packets == 0 {
+                continue;
+            }
+            let (local, remote, interface_name, sent, received) = match (
+                self.interface(&entry.original.source.ip),
+                self.interface(&entry.reply.source.ip),
+                self.interface(&entry.original.destination.ip),
+            ) {
+                (Some(interface_name), _, _) => (
+                    entry.original.source,
+                    entry.original.destination,
+                    interface_name,
+                    original,
+                    reply,
+                ),
+                // the destination may have been translated to this host
+                (None, Some(interface_name), _) => (
+                    entry.reply.source,
+                    entry.reply.destination,
+                    interface_name,
+                    reply,
+                    original,
+                ),
+                (None, None, Some(interface_name)) => (
+                    entry.original.destination,
+                    entry.original.source,
+                    interface_name,
+                    reply,
+                    original,
+                ),
+                (None, None, None) => continue,
+            };
+            let connection =
+                Connection::new(remote.into(), local.ip, local.port, entry.original.protocol);
+            let info = utilization
+                .connections
+                .entry(connection)
+                .or_insert_with(|| ConnectionInfo {
+                    interface_name: interface_name.to_string(),
+                    ..ConnectionInfo::default()
+                });
+            info.total_bytes_uploaded += sent.bytes;
+            info.total_packets_uploaded += sent.packets;
+            info.total_bytes_downloaded += received.bytes;
+            info.total_packets_downloaded += received.packets;
+            utilization.update_interface(
+                interface_name,
+                InterfaceInfo {
+                    total_packets_downloaded: received.packets,
+                    total_packets_uploaded: sent.packets,
+                    ..InterfaceInfo::default()
+                },
+            );
+        }
+        self.counters = counters;
+        Ok(utilization)
+    }
+    fn interface(&self, ip: &IpAddr) -> Option<&str> {
+        self.interfaces
+            .iter()
+            .find(|(interface_ip, _)| interface_ip == ip)
+            .map(|(_, interface_name)| interface_name.as_str())
+    }
+}
+
+/// Counters lower than before belong to a new entry reusing the tuple.
+fn difference(current: ConntrackCounters, previous: ConntrackCounters) -> ConntrackCounters {
+    if current.packets < previous.packets || current.bytes < previous.bytes {
+        current
+    } else {
+        ConntrackCounters {
+            packets: current.packets - previous.packets,
+            bytes: current.bytes - previous.bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::ipnetwork::{IpNetwork, Ipv4Network};
+
+    use super::*;
+
+    const NLA_F_NESTED: u16 = 0x8000;
+
+    fn attribute(attribute_type: u16, value: &[u8]) -> Vec<u8> {
+        let mut attribute = ((ATTRIBUTE_HEADER_LENGTH + value.len()) as u16)
+            .to_ne_bytes()
+            .to_vec();
+        attribute.extend_from_slice(&attribute_type.to_ne_bytes());
+        attribute.extend_from_slice(value);
+        attribute.resize(align(attribute.len()), 0);
+        attribute
+    }
+
+    fn nested(attribute_type: u16, attributes: &[Vec<u8>]) -> Vec<u8> {
+        attribute(attribute_type | NLA_F_NESTED, &attributes.concat())
+    }
+
+    fn tuple(source: [u8; 4], destination: [u8; 4], ports: (u16, u16)) -> Vec<Vec<u8>> {
+        vec![
+            nested(
+                CTA_TUPLE_IP,
+                &[
+                    attribute(CTA_IP_V4_SRC, &source),
+                    attribute(CTA_IP_V4_DST, &destination),
+                ],
+            ),
+            nested(
+                CTA_TUPLE_PROTO,
+                &[
+                    attribute(CTA_PROTO_NUM, &[PROTOCOL_TCP]),
+                    attribute(CTA_PROTO_SRC_PORT, &ports.0.to_be_bytes()),
+                    attribute(CTA_PROTO_DST_PORT, &ports.1.to_be_bytes()),
+                ],
+            ),
+        ]
+    }
+
+    fn counters(packets: u64, bytes: u64) -> Vec<Vec<u8>> {
+        vec![
+            attribute(CTA_COUNTERS_PACKETS, &packets.to_be_bytes()),
+            attribute(CTA_COUNTERS_BYTES, &bytes.to_be_bytes()),
+        ]
+    }
+
+    fn message(message_type: u16, payload: &[u8]) -> Vec<u8> {
+        let mut message = ((NETLINK_HEADER_LENGTH + payload.len()) as u32)
+            .to_ne_bytes()
+            .to_vec();
+        message.extend_from_slice(&message_type.to_ne_bytes());
+        message.extend_from_slice(&[0; 10]);
+        message.extend_from_slice(payload);
+        message.resize(align(message.len()), 0);
+        message
+    }
+
+    fn flow(source: &str, destination: &str) -> TransitFlow {
+        TransitFlow::new(
+            source.parse().unwrap(),
+            destination.parse().unwrap(),
+            Protocol::Tcp,
+        )
+    }
+
+    fn masqueraded_entry(original: (u128, u128), reply: (u128, u128)) -> ConntrackEntry {
+        ConntrackEntry {
+            original: flow("192.168.1.10:40000", "93.184.216.34:443"),
+            reply: flow("93.184.216.34:443", "203.0.113.1:61000"),
+            original_counters: Some(ConntrackCounters {
+                packets: original.0,
+                bytes: original.1,
+            }),
+            reply_counters: Some(ConntrackCounters {
+                packets: reply.0,
+                bytes: reply.1,
+            }),
+        }
+    }
+
+    #[test]
+    fn should_parse_entries_of_netlink_dump() {
+        // given
+        let entry = [
+            vec![2, NFNETLINK_V0, 0, 0],
+            nested(
+                CTA_TUPLE_ORIG,
+                &tuple([192, 168, 1, 10], [93, 184, 216, 34], (40000, 443)),
+            ),
+            nested(
+                CTA_TUPLE_REPLY,
+                &tuple([93, 184, 216, 34], [203, 0, 113, 1], (443, 61000)),
+            ),
+            nested(CTA_COUNTERS_ORIG, &counters(10, 1200)),
+            nested(CTA_COUNTERS_REPLY, &counters(8, 5000)),
+        ]
+        .concat();
+        let buffer = [
+            message((NFNL_SUBSYS_CTNETLINK << 8) | IPCTNL_MSG_CT_NEW, &entry),
+            message(NLMSG_DONE, &[0; 4]),
+        ]
+        .concat();
+
+        // when
+        let (entries, done) = parse_conntrack_messages(&buffer).unwrap();
+
+        // then
+        assert!(done);
+        assert_eq!(entries, vec![masqueraded_entry((10, 1200), (8, 5000))]);
+    }
+
+    #[test]
+    fn should_report_error_of_netlink_dump() {
+        // given
+        let buffer = message(NLMSG_ERROR, &(-libc::EPERM).to_ne_bytes());
+
+        // when
+        let result = parse_conntrack_messages(&buffer);
+
+        // then
+        assert_eq!(result.unwrap_err().raw_os_error(), Some(libc::EPERM));
+    }
+
+    #[test]
+    fn should_parse_entries_of_proc_conntrack() {
+        // given
+        let table = "\
+ipv4     2 tcp      6 431999 ESTABLISHED src=192.168.1.10 dst=93.184.216.34 sport=40000 dport=443 packets=10 bytes=1200 src=93.184.216.34 dst=203.0.113.1 sport=443 dport=61000 packets=8 bytes=5000 [ASSURED] mark=0 zone=0 use=2
+ipv4     2 icmp     1 29 src=10.0.0.1 dst=10.0.0.2 type=8 code=0 id=1234 src=10.0.0.2 dst=10.0.0.1 type=0 code=0 id=1234 mark=0 zone=0 use=2
+";
+
+        // when
+        let entries = parse_proc_conntrack(table);
+
+        // then
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0], masqueraded_entry((10, 1200), (8, 5000)));
+        assert_eq!(
+            entries[1].original.protocol,
+            Protocol::Icmp(IcmpKind::Echo { identifier: 1234 })
+        );
+        assert_eq!(entries[1].reply.source.ip, IpAddr::from([10, 0, 0, 2]));
+        assert_eq!(entries[1].original_counters, None);
+    }
+
+    #[test]
+    fn should_look_up_entries_by_either_tuple() {
+        // given
+        let table = ConntrackTable::new(vec![masqueraded_entry((0, 0), (0, 0))]);
+        let remote = "93.184.216.34:443".parse().unwrap();
+
+        // when
+        let client = table.lookup(&Connection::new(
+            remote,
+            IpAddr::from([192, 168, 1, 10]),
+            40000,
+            Protocol::Tcp,
+        ));
+        let router = table.lookup(&Connection::new(
+            remote,
+            IpAddr::from([203, 0, 113, 1]),
+            61000,
+            Protocol::Tcp,
+        ));
+        let unrelated = table.lookup(&Connection::new(
+            remote,
+            IpAddr::from([203, 0, 113, 1]),
+            61001,
+            Protocol::Tcp,
+        ));
+
+        // then
+        assert!(client.is_some());
+        assert!(router.is_some());
+        assert!(unrelated.is_none());
+    }
+
+    #[test]
+    fn should_measure_differences_of_counters_of_local_connections() {
+        // given
+        let network_interface = NetworkInterface {
+            name: "eth0".to_string(),
+            description: "".to_string(),
+            index: 0,
+            mac: None,
+            ips: vec![IpNetwork::V4(
+                Ipv4Network::new(Ipv4Addr::new(192, 168, 1, 10), 24).unwrap(),
+            )],
+            flags: 0,
+        };
+        let forwarded = ConntrackEntry {
+            original: flow("192.168.1.20:40000", "93.184.216.34:443"),
+            ..masqueraded_entry((1, 100), (1, 100))
+        };
+        let start = SystemTime::UNIX_EPOCH;
+        let mut meter = ConntrackMeter::new(
+            &[network_interface],
+            &[masqueraded_entry((10, 1200), (8, 5000))],
+            start,
+        );
+
+        // when
+        let utilization = meter
+            .measure(
+                &[masqueraded_entry((12, 1300), (11, 9000)), forwarded],
+                start + std::time::Duration::from_secs(10),
+            )
+            .unwrap();
+
+        // then
+        assert_eq!(utilization.start, start);
+        assert_eq!(utilization.connections.len(), 1);
+        let (connection, info) = utilization.connections.iter().next().unwrap();
+        assert_eq!(connection.local_socket.port, 40000);
+        assert_eq!(info.interface_name, "eth0");
+        assert_eq!(info.total_bytes_uploaded, 100);
+        assert_eq!(info.total_packets_uploaded, 2);
+        assert_eq!(info.total_bytes_downloaded, 4000);
+        assert_eq!(info.total_packets_downloaded, 3);
+        assert_eq!(utilization.interfaces["eth0"].total_packets_downloaded, 3);
+    }
+
+    #[test]
+    fn should_measure_connections_translated_to_local_address_by_reply_tuple() {
+        // given
+        let network_interface = NetworkInterface {
+            name: "eth0".to_string(),
+            description: "".to_string(),
+            index: 0,
+            mac: None,
+            ips: vec![IpNetwork::V4(
+                Ipv4Network::new(Ipv4Addr::new(192, 168, 1, 10), 24).unwrap(),
+            )],
+            flags: 0,
+        };
+        let destination_translated = ConntrackEntry {
+            original: flow("198.51.100.7:50000", "10.96.0.10:80"),
+            reply: flow("192.168.1.10:8080", "198.51.100.7:50000"),
+            original_counters: Some(ConntrackCounters {
+                packets: 3,
+                bytes: 300,
+            }),
+            reply_counters: Some(ConntrackCounters {
+                packets: 2,
+                bytes: 2000,
+            }),
+        };
+        let start = SystemTime::UNIX_EPOCH;
+        let mut meter = ConntrackMeter::new(&[network_interface], &[], start);
+
+        // when
+        let utilization = meter
+            .measure(
+                &[destination_translated],
+                start + std::time::Duration::from_secs(10),
+            )
+            .unwrap();
+
+        // then
+        assert_eq!(utilization.connections.len(), 1);
+        let (connection, info) = utilization.connections.iter().next().unwrap();
+        assert_eq!(connection.local_socket.port, 8080);
+        assert_eq!(connection.remote_socket.port, 50000);
+        assert_eq!(info.total_bytes_downloaded, 300);
+        assert_eq!(info.total_bytes_uploaded, 2000);
+    }
+
+    #[test]
+    fn should_fail_to_measure_uncounted_entries() {
+        // given
+        let uncounted = ConntrackEntry {
+            original_counters: None,
+            reply_counters: None,
+            ..masqueraded_entry((0, 0), (0, 0))
+        };
+        let start = SystemTime::UNIX_EPOCH;
+        let mut meter = ConntrackMeter::new(&[], &[], start);
+
+        // when
+        let result = meter.measure(&[uncounted], start + std::time::Duration::from_secs(10));
+
+        // then
+        assert!(result
+            .err()
+            .unwrap()
+            .to_string()
+            .contains("nf_conntrack_acct is disabled"));
+        assert_eq!(meter.last_measurement, start);
+    }
+}
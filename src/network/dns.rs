@@ -0,0 +1,417 @@
+use ::std::collections::HashMap;
+use ::std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use ::std::sync::{Arc, Mutex};
+use ::std::time::{Duration, SystemTime};
+
+use crate::network::{ExpiringMap, Protocol};
+
+const DNS_PORT: u16 = 53;
+const UDP_HEADER_LENGTH: usize = 8;
+const TCP_LENGTH_PREFIX_LENGTH: usize = 2;
+const HEADER_LENGTH: usize = 12;
+const QUESTION_FIELDS_LENGTH: usize = 4;
+const RECORD_FIELDS_LENGTH: usize = 10;
+const RESPONSE_FLAG: u8 = 0x80;
+const RESPONSE_CODE_MASK: u8 = 0x0f;
+const TYPE_A: u16 = 1;
+const TYPE_CNAME: u16 = 5;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const LABEL_POINTER: u8 = 0xc0;
+const MAXIMUM_NAME_LENGTH: usize = 255;
+const MAXIMUM_NUMBER_OF_POINTERS: usize = 16;
+const MAXIMUM_CNAME_CHAIN_LENGTH: usize = 16;
+/// Upper bound of addresses in the cache shared by all sniffers.
+const MAXIMUM_NUMBER_OF_ADDRESSES: usize = 65536;
+const MAXIMUM_NUMBER_OF_NAMES_PER_ADDRESS: usize = 8;
+/// Keeps expired names long enough for the utilization window they expired in to be resolved.
+const RETENTION_AFTER_EXPIRY: Duration = Duration::from_secs(60);
+
+/// An address announced by a DNS response, along with the name queried for it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnsAnswer {
+    pub address: IpAddr,
+    pub name: String,
+    pub ttl: Duration,
+}
+
+enum DnsRecord {
+    Address {
+        owner: String,
+        address: IpAddr,
+        ttl: u32,
+    },
+    Alias {
+        owner: String,
+        target: String,
+        ttl: u32,
+    },
+}
+
+/// Returns the DNS message carried by a UDP datagram or TCP segment sent from port 53.
+///
+/// Messages over TCP are only returned if they are contained in a single segment.
+pub fn dns_response(protocol: Protocol, source_port: u16, transport: &[u8]) -> Option<&[u8]> {
+    if source_port != DNS_PORT {
+        return None;
+    }
+    match protocol {
+        Protocol::Udp => transport.get(UDP_HEADER_LENGTH..),
+        Protocol::Tcp => {
+            let header_length = usize::from(*transport.get(12)? >> 4) * 4;
+            let payload = transport.get(header_length..)?;
+            let length = usize::from(u16::from_be_bytes([*payload.first()?, *payload.get(1)?]));
+            payload.get(TCP_LENGTH_PREFIX_LENGTH..TCP_LENGTH_PREFIX_LENGTH + length)
+        }
+        _ => None,
+    }
+}
+
+/// Extracts the A and AAAA records of a successful response, RFC 1035 and RFC 3596.
+///
+/// Addresses reached through a CNAME chain are attributed to the queried name, with the lowest
+/// TTL along the chain. Records following a truncated or malformed record are ignored.
+pub fn parse_dns_response(message: &[u8]) -> Vec<DnsAnswer> {
+    let mut query = None;
+    let mut records = vec![];
+    if parse_records(message, &mut query, &mut records).is_none() && records.is_empty() {
+        return vec![];
+    }
+    let aliases = records
+        .iter()
+        .filter_map(|record| match record {
+            DnsRecord::Alias { owner, target, ttl } => {
+                Some((target.as_str(), (owner.as_str(), *ttl)))
+            }
+            DnsRecord::Address { .. } => None,
+        })
+        .collect::<HashMap<_, _>>();
+    records
+        .iter()
+        .filter_map(|record| match record {
+            DnsRecord::Address {
+                owner,
+                address,
+                ttl,
+            } => {
+                let (name, ttl) = match &query {
+                    Some(query) => queried_name(query, owner, *ttl, &aliases),
+                    None => (owner.as_str(), *ttl),
+                };
+                Some(DnsAnswer {
+                    address: *address,
+                    name: name.to_string(),
+                    ttl: Duration::from_secs(u64::from(ttl)),
+                })
+            }
+            DnsRecord::Alias { .. } => None,
+        })
+        .collect()
+}
+
+/// Follows the CNAME chain from the owner of an address back to the queried name.
+fn queried_name<'a>(
+    query: &'a str,
+    owner: &'a str,
+    ttl: u32,
+    aliases: &HashMap<&str, (&'a str, u32)>,
+) -> (&'a str, u32) {
+    let mut name = owner;
+    let mut chain_ttl = ttl;
+    for _ in 0..MAXIMUM_CNAME_CHAIN_LENGTH {
+        if name == query {
+            return (query, chain_ttl);
+        }
+        match aliases.get(name) {
+            Some((alias, alias_ttl)) => {
+                name = alias;
+                chain_ttl = chain_ttl.min(*alias_ttl);
+            }
+            None => break,
+        }
+    }
+    (owner, ttl)
+}
+
+fn parse_records(
+    message: &[u8],
+    query: &mut Option<String>,
+    records: &mut Vec<DnsRecord>,
+) -> Option<()> {
+    let header = message.get(..HEADER_LENGTH)?;
+    if header[2] & RESPONSE_FLAG == 0 || header[3] & RESPONSE_CODE_MASK != 0 {
+        return None;
+    }
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+    let answers = u16::from_be_bytes([header[6], header[7]]);
+    let mut offset = HEADER_LENGTH;
+    for _ in 0..questions {
+        let (name, end) = read_name(message, offset)?;
+        query.get_or_insert(name);
+        offset = end + QUESTION_FIELDS_LENGTH;
+    }
+    for _ in 0..answers {
+        let (owner, end) = read_name(message, offset)?;
+        let fields = message.get(end..end + RECORD_FIELDS_LENGTH)?;
+        let record_type = u16::from_be_bytes([fields[0], fields[1]]);
+        let class = u16::from_be_bytes([fields[2], fields[3]]);
+        let ttl = u32::from_be_bytes([fields[4], fields[5], fields[6], fields[7]]);
+        let data_length = usize::from(u16::from_be_bytes([fields[8], fields[9]]));
+        let data_offset = end + RECORD_FIELDS_LENGTH;
+        let data = message.get(data_offset..data_offset + data_length)?;
+        offset = data_offset + data_length;
+        if class != CLASS_IN {
+            continue;
+        }
+        let address = match (record_type, data.len()) {
+            (TYPE_A, 4) => IpAddr::V4(Ipv4Addr::new(data[0], data[1], data[2], data[3])),
+            (TYPE_AAAA, 16) => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(data);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            (TYPE_CNAME, _) => {
+                let (target, _) = read_name(message, data_offset)?;
+                records.push(DnsRecord::Alias { owner, target, ttl });
+                continue;
+            }
+            _ => continue,
+        };
+        records.push(DnsRecord::Address {
+            owner,
+            address,
+            ttl,
+        });
+    }
+    Some(())
+}
+
+/// Reads a possibly compressed domain name, returning it in lower case along with the offset
+/// following it.
+fn read_name(message: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let length = *message.get(offset)?;
+        if length & LABEL_POINTER == LABEL_POINTER {
+            pointers += 1;
+            if pointers > MAXIMUM_NUMBER_OF_POINTERS {
+                return None;
+            }
+            end.get_or_insert(offset + 2);
+            offset = usize::from(u16::from_be_bytes([
+                length & !LABEL_POINTER,
+                *message.get(offset + 1)?,
+            ]));
+        } else if length & LABEL_POINTER != 0 {
+            return None;
+        } else if length == 0 {
+            return Some((name, end.unwrap_or(offset + 1)));
+        } else {
+            let label = message.get(offset + 1..offset + 1 + usize::from(length))?;
+            if !name.is_empty() {
+                name.push('.');
+            }
+            name.extend(
+                label
+                    .iter()
+                    .map(|byte| char::from(byte.to_ascii_lowercase())),
+            );
+            if name.len() > MAXIMUM_NAME_LENGTH {
+                return None;
+            }
+            offset += 1 + usize::from(length);
+        }
+    }
+}
+
+struct CachedName {
+    name: String,
+    expires: SystemTime,
+}
+
+/// Names of the addresses announced in DNS responses seen on any interface.
+///
+/// Clones share the same cache. Names expire with the TTL of their records, while the cache is
+/// full answers for new addresses are dropped.
+///
+/// Addresses are seen until the last of their names expires, so they are retained for the
+/// retention time afterwards.
+#[derive(Clone)]
+pub struct DnsCache {
+    addresses: Arc<Mutex<ExpiringMap<IpAddr, Vec<CachedName>>>>,
+}
+
+impl Default for DnsCache {
+    fn default() -> Self {
+        DnsCache {
+            addresses: Arc::new(Mutex::new(ExpiringMap::new(
+                MAXIMUM_NUMBER_OF_ADDRESSES,
+                RETENTION_AFTER_EXPIRY,
+            ))),
+        }
+    }
+}
+
+impl DnsCache {
+    pub fn insert(&self, answers: Vec<DnsAnswer>, timestamp: SystemTime) {
+        if answers.is_empty() {
+            return;
+        }
+        let mut addresses = self.addresses.lock().unwrap();
+        addresses.expire(timestamp, drop);
+        for answer in answers {
+            let expires = timestamp + answer.ttl;
+            let names = match addresses.get_or_insert_with(answer.address, expires, Vec::new) {
+                Some(names) => names,
+                None => continue,
+            };
+            names.retain(|cached| cached.expires + RETENTION_AFTER_EXPIRY > timestamp);
+            match names.iter_mut().find(|cached| cached.name == answer.name) {
+                Some(cached) => cached.expires = cached.expires.max(expires),
+                None => {
+                    if names.len() >= MAXIMUM_NUMBER_OF_NAMES_PER_ADDRESS {
+                        names.sort_by_key(|cached| cached.expires);
+                        names.remove(0);
+                    }
+                    names.push(CachedName {
+                        name: answer.name,
+                        expires,
+                    });
+                }
+            }
+        }
+    }
+    /// Returns the sorted names of the address whose records had not expired at the given time.
+    pub fn names(&self, address: &IpAddr, at: SystemTime) -> Vec<String> {
+        let addresses = self.addresses.lock().unwrap();
+        let mut names = addresses
+            .get(address)
+            .map(|names| {
+                names
+                    .iter()
+                    .filter(|cached| cached.expires >= at)
+                    .map(|cached| cached.name.clone())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        names.sort();
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::network::fixtures::at;
+
+    use super::*;
+
+    /// Response to `www.example.com A`, aliased to `cdn.example.net` using a compressed CNAME.
+    fn response_with_cname_chain() -> Vec<u8> {
+        [
+            &[0x12, 0x34, 0x81, 0x80, 0, 1, 0, 2, 0, 0, 0, 0][..],
+            // question at offset 12
+            &[3, b'w', b'w', b'w', 7][..],
+            b"example",
+            &[3, b'c', b'o', b'm', 0, 0, 1, 0, 1],
+            // www.example.com CNAME cdn.example.net, TTL 300, target at offset 45
+            &[0xc0, 12, 0, 5, 0, 1, 0, 0, 0x01, 0x2c, 0, 17],
+            &[3, b'c', b'd', b'n', 7][..],
+            b"example",
+            &[3, b'n', b'e', b't', 0],
+            // cdn.example.net A 93.184.216.34, TTL 60
+            &[0xc0, 45, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 93, 184, 216, 34],
+        ]
+        .concat()
+    }
+
+    fn answer(address: [u8; 4], name: &str, ttl: u64) -> DnsAnswer {
+        DnsAnswer {
+            address: IpAddr::V4(Ipv4Addr::from(address)),
+            name: name.to_string(),
+            ttl: Duration::from_secs(ttl),
+        }
+    }
+
+    #[test]
+    fn should_attribute_addresses_of_cname_chain_to_queried_name() {
+        // when
+        let answers = parse_dns_response(&response_with_cname_chain());
+
+        // then
+        assert_eq!(
+            answers,
+            vec![answer([93, 184, 216, 34], "www.example.com", 60)]
+        );
+    }
+
+    #[test]
+    fn should_ignore_queries_and_failed_responses() {
+        // given
+        let mut query = response_with_cname_chain();
+        query[2] = 0x01;
+        let mut failed = response_with_cname_chain();
+        failed[3] = 0x83;
+
+        // then
+        assert_eq!(parse_dns_response(&query), vec![]);
+        assert_eq!(parse_dns_response(&failed), vec![]);
+    }
+
+    #[test]
+    fn should_reject_compression_loops() {
+        // given
+        let message = [&[0, 0, 0x81, 0x80, 0, 1, 0, 0, 0, 0, 0, 0][..], &[0xc0, 12]].concat();
+
+        // then
+        assert_eq!(read_name(&message, 12), None);
+    }
+
+    #[test]
+    fn should_extract_dns_messages_of_tcp_segments() {
+        // given
+        let message = response_with_cname_chain();
+        let segment = [
+            &[
+                0, 53, 0xa8, 0xf6, 0, 0, 0, 1, 0, 0, 0, 1, 0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0,
+            ][..],
+            &(message.len() as u16).to_be_bytes(),
+            &message,
+        ]
+        .concat();
+
+        // when
+        let result = dns_response(Protocol::Tcp, 53, &segment);
+
+        // then
+        assert_eq!(result, Some(&message[..]));
+        assert_eq!(dns_response(Protocol::Tcp, 53, &segment[..40]), None);
+    }
+
+    #[test]
+    fn should_expire_names_with_ttl() {
+        // given
+        let cache = DnsCache::default();
+        let address = IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34));
+        cache.insert(
+            vec![answer([93, 184, 216, 34], "www.example.com", 60)],
+            at(100),
+        );
+        cache.insert(
+            vec![answer([93, 184, 216, 34], "example.com", 300)],
+            at(100),
+        );
+
+        // then
+        assert_eq!(
+            cache.names(&address, at(150)),
+            vec!["example.com".to_string(), "www.example.com".to_string()]
+        );
+        assert_eq!(
+            cache.names(&address, at(170)),
+            vec!["example.com".to_string()]
+        );
+        assert_eq!(cache.names(&address, at(401)), Vec::<String>::new());
+    }
+}
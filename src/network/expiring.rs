@@ -0,0 +1,209 @@
+use ::std::collections::{HashMap, VecDeque};
+use ::std::hash::Hash;
+use ::std::time::{Duration, SystemTime};
+
+/// Expired entries are looked for at most this often, as that takes a scan of all entries.
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Entry<V> {
+    value: V,
+    last_seen: SystemTime,
+    insertion: u64,
+}
+
+/// Bounded map forgetting entries not seen within a timeout, the state kept per sniffer or
+/// shared between sniffers for tracking traffic.
+///
+/// Entries are seen when inserted or looked up with a timestamp, which only ever moves their
+/// last seen time forward.
+pub struct ExpiringMap<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    /// Keys in the order of insertion, possibly of entries removed since.
+    insertions: VecDeque<(K, u64)>,
+    next_insertion: u64,
+    capacity: usize,
+    timeout: Duration,
+    last_expiry: SystemTime,
+}
+
+impl<K: Clone + Eq + Hash, V> ExpiringMap<K, V> {
+    pub fn new(capacity: usize, timeout: Duration) -> Self {
+        ExpiringMap {
+            entries: HashMap::new(),
+            insertions: VecDeque::new(),
+            next_insertion: 0,
+            capacity,
+            timeout,
+            last_expiry: SystemTime::UNIX_EPOCH,
+        }
+    }
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_full(&self) -> bool {
+        self.entries.len() >= self.capacity
+    }
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.entries.keys()
+    }
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(key).map(|entry| &entry.value)
+    }
+    pub fn get_mut(&mut self, key: &K, now: SystemTime) -> Option<&mut V> {
+        let entry = self.entries.get_mut(key)?;
+        entry.last_seen = entry.last_seen.max(now);
+        Some(&mut entry.value)
+    }
+    /// Returns the entry of the key, inserting one unless the map is full.
+    pub fn get_or_insert_with(
+        &mut self,
+        key: K,
+        now: SystemTime,
+        value: impl FnOnce() -> V,
+    ) -> Option<&mut V> {
+        if !self.entries.contains_key(&key) {
+            if self.is_full() {
+                return None;
+            }
+            self.insert_new(key.clone(), value(), now);
+        }
+        self.get_mut(&key, now)
+    }
+    /// Inserts or replaces the entry of the key, returning `false` if the map is full.
+    pub fn insert(&mut self, key: K, value: V, now: SystemTime) -> bool {
+        if let Some(existing) = self.get_mut(&key, now) {
+            *existing = value;
+        } else if self.is_full() {
+            return false;
+        } else {
+            self.insert_new(key, value, now);
+        }
+        true
+    }
+    /// Inserts or replaces the entry of the key, making room by evicting the earliest inserted
+    /// entry if the map is full.
+    ///
+    /// Returns the replaced and the evicted value.
+    pub fn insert_evicting(&mut self, key: K, value: V, now: SystemTime) -> (Option<V>, Option<V>) {
+        if let Some(existing) = self.get_mut(&key, now) {
+            return (Some(std::mem::replace(existing, value)), None);
+        }
+        let evicted = if self.is_full() {
+            self.evict_earliest()
+        } else {
+            None
+        };
+        self.insert_new(key, value, now);
+        (None, evicted)
+    }
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.entries.remove(key).map(|entry| entry.value)
+    }
+    /// Removes the entries not seen within the timeout, unless that has been done less than a
+    /// second ago.
+    ///
+    /// Returns whether entries were looked for.
+    pub fn expire(&mut self, now: SystemTime, mut expired: impl FnMut(V)) -> bool {
+        if now.duration_since(self.last_expiry).unwrap_or_default() < EXPIRY_INTERVAL {
+            return false;
+        }
+        self.last_expiry = now;
+        let timeout = self.timeout;
+        let expired_keys = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| now.duration_since(entry.last_seen).unwrap_or_default() >= timeout)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in expired_keys {
+            if let Some(value) = self.remove(&key) {
+                expired(value);
+            }
+        }
+        true
+    }
+    fn insert_new(&mut self, key: K, value: V, now: SystemTime) {
+        let insertion = self.next_insertion;
+        self.next_insertion += 1;
+        self.insertions.push_back((key.clone(), insertion));
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                last_seen: now,
+                insertion,
+            },
+        );
+        if self.insertions.len() > 2 * self.capacity.max(self.entries.len()) {
+            let entries = &self.entries;
+            self.insertions.retain(|(key, insertion)| {
+                entries
+                    .get(key)
+                    .is_some_and(|entry| entry.insertion == *insertion)
+            });
+        }
+    }
+    fn evict_earliest(&mut self) -> Option<V> {
+        while let Some((key, insertion)) = self.insertions.pop_front() {
+            if self
+                .entries
+                .get(&key)
+                .is_some_and(|entry| entry.insertion == insertion)
+            {
+                return self.remove(&key);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::network::fixtures::at;
+
+    use super::*;
+
+    #[test]
+    fn should_expire_entries_not_seen_within_timeout() {
+        // given
+        let mut map = ExpiringMap::new(2, Duration::from_secs(10));
+        map.insert("idle", 1, at(100));
+        map.insert("active", 2, at(100));
+        map.get_mut(&"active", at(105));
+
+        // when
+        let mut expired = vec![];
+        map.expire(at(110), |value| expired.push(value));
+        let repeated = map.expire(at(110), |_| {});
+
+        // then
+        assert_eq!(expired, vec![1]);
+        assert!(!repeated);
+        assert_eq!(map.get(&"active"), Some(&2));
+    }
+
+    #[test]
+    fn should_refuse_or_evict_earliest_insertion_when_full() {
+        // given
+        let mut map = ExpiringMap::new(2, Duration::from_secs(10));
+        map.insert("first", 1, at(100));
+        map.insert("second", 2, at(100));
+        map.remove(&"first");
+        map.insert("first", 3, at(101));
+
+        // when
+        let refused = map.insert("third", 4, at(102));
+        let (replaced, evicted) = map.insert_evicting("third", 4, at(102));
+
+        // then
+        assert!(!refused);
+        assert_eq!(replaced, None);
+        assert_eq!(evicted, Some(2));
+        assert_eq!(map.get(&"first"), Some(&3));
+        assert_eq!(map.len(), 2);
+    }
+}
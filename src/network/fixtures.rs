@@ -0,0 +1,154 @@
+//! Builders for raw frames and segments used as test input.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, SystemTime};
+
+use crate::network::{Connection, Direction, Protocol, Scope, Segment};
+
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_VLAN: u16 = 0x8100;
+pub const ETHERTYPE_QINQ: u16 = 0x88a8;
+pub const ETHERTYPE_IPV6: u16 = 0x86dd;
+pub const PROTOCOL_ICMP: u8 = 1;
+pub const PROTOCOL_TCP: u8 = 6;
+pub const PROTOCOL_UDP: u8 = 17;
+pub const PROTOCOL_IPV6_FRAGMENT: u8 = 44;
+pub const PROTOCOL_GRE: u8 = 47;
+pub const PROTOCOL_ICMPV6: u8 = 58;
+pub const PROTOCOL_IPV6_OPTIONS: u8 = 60;
+pub const PROTOCOL_SCTP: u8 = 132;
+
+pub fn ethernet(ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 1];
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// The payload of a VLAN tagged ethertype, starting with the tag control information.
+pub fn vlan(vlan_id: u16, ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    let mut packet = vlan_id.to_be_bytes().to_vec();
+    packet.extend_from_slice(&ethertype.to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+pub fn ipv4(source: Ipv4Addr, destination: Ipv4Addr, protocol: u8, payload: &[u8]) -> Vec<u8> {
+    let total_length = (20 + payload.len()) as u16;
+    let mut packet = vec![0x45, 0];
+    packet.extend_from_slice(&total_length.to_be_bytes());
+    packet.extend_from_slice(&[0, 0, 0, 0, 64, protocol, 0, 0]);
+    packet.extend_from_slice(&source.octets());
+    packet.extend_from_slice(&destination.octets());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+pub fn ipv6(source: Ipv6Addr, destination: Ipv6Addr, next_header: u8, payload: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0x60, 0, 0, 0];
+    packet.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    packet.extend_from_slice(&[next_header, 64]);
+    packet.extend_from_slice(&source.octets());
+    packet.extend_from_slice(&destination.octets());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// An extension header in the common format, `options` padding it to a multiple of 8 bytes.
+pub fn ipv6_extension_header(next_header: u8, options: &[u8], payload: &[u8]) -> Vec<u8> {
+    let header_extension_length = ((2 + options.len()) / 8 - 1) as u8;
+    let mut packet = vec![next_header, header_extension_length];
+    packet.extend_from_slice(options);
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// An ICMP or ICMPv6 message, `rest` following the checksum.
+pub fn icmp(icmp_type: u8, code: u8, rest: &[u8]) -> Vec<u8> {
+    let mut message = vec![icmp_type, code, 0, 0];
+    message.extend_from_slice(rest);
+    message
+}
+
+pub fn udp(source_port: u16, destination_port: u16, payload: &[u8]) -> Vec<u8> {
+    let mut datagram = source_port.to_be_bytes().to_vec();
+    datagram.extend_from_slice(&destination_port.to_be_bytes());
+    datagram.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(payload);
+    datagram
+}
+
+/// A TCP segment without options.
+pub fn tcp(
+    source_port: u16,
+    destination_port: u16,
+    sequence_number: u32,
+    acknowledgement_number: u32,
+    flags: u8,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut segment = source_port.to_be_bytes().to_vec();
+    segment.extend_from_slice(&destination_port.to_be_bytes());
+    segment.extend_from_slice(&sequence_number.to_be_bytes());
+    segment.extend_from_slice(&acknowledgement_number.to_be_bytes());
+    segment.extend_from_slice(&[0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+    segment.extend_from_slice(payload);
+    segment
+}
+
+/// A DNS response answering an A query for `name` with a single record.
+pub fn dns_a_response(name: &str, address: Ipv4Addr, ttl: u32) -> Vec<u8> {
+    let mut message = vec![0x12, 0x34, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0];
+    for label in name.split('.') {
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.extend_from_slice(&[0, 0, 1, 0, 1]);
+    message.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1]);
+    message.extend_from_slice(&ttl.to_be_bytes());
+    message.extend_from_slice(&[0, 4]);
+    message.extend_from_slice(&address.octets());
+    message
+}
+
+pub fn at(seconds: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+pub fn at_millis(milliseconds: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_millis(milliseconds)
+}
+
+/// A TCP connection from local port 40000 of 10.0.0.1 to 10.0.0.2:443.
+pub fn connection() -> Connection {
+    Connection::new(
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 443),
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+        40000,
+        Protocol::Tcp,
+    )
+}
+
+/// A single unicast packet on eth0 consisting of payload only, without any details.
+pub fn segment(connection: Connection, direction: Direction, data_length: u128) -> Segment {
+    Segment {
+        timestamp: SystemTime::UNIX_EPOCH,
+        interface_name: "eth0".to_string(),
+        vlan_ids: vec![],
+        tunnel: None,
+        tunnel_overhead_length: 0,
+        connection,
+        direction,
+        data_length,
+        payload_length: data_length,
+        packets: 1,
+        tcp_flags: None,
+        tcp_sample: None,
+        tls_client_hello: None,
+        quic: None,
+        scope: Scope::Unicast,
+        transit: None,
+        forwarded: None,
+    }
+}
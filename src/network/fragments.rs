@@ -0,0 +1,304 @@
+use ::std::net::IpAddr;
+use ::std::time::{Duration, SystemTime};
+
+use ::pnet::packet::ipv4::{Ipv4Flags, Ipv4Packet};
+
+use crate::network::{AccountedLength, ExpiringMap, Protocol};
+
+const MAXIMUM_NUMBER_OF_DATAGRAMS: usize = 4096;
+/// Same as the default of `net.ipv4.ipfrag_time`.
+const DATAGRAM_TIMEOUT: Duration = Duration::from_secs(30);
+const IPV6_FRAGMENT_OFFSET_MASK: u16 = 0xfff8;
+const IPV6_MORE_FRAGMENTS: u16 = 0x0001;
+
+/// Position of a packet within a fragmented datagram.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fragment {
+    pub identification: u32,
+    /// Offset in units of 8 bytes.
+    pub offset: u16,
+    pub more_fragments: bool,
+}
+
+impl Fragment {
+    /// Returns `None` for unfragmented packets.
+    pub fn of_ipv4(ip_packet: &Ipv4Packet) -> Option<Self> {
+        let more_fragments = ip_packet.get_flags() & Ipv4Flags::MoreFragments != 0;
+        let offset = ip_packet.get_fragment_offset();
+        if !more_fragments && offset == 0 {
+            return None;
+        }
+        Some(Fragment {
+            identification: u32::from(ip_packet.get_identification()),
+            offset,
+            more_fragments,
+        })
+    }
+    /// Parses the body of an IPv6 fragment header, starting at its reserved byte.
+    pub fn of_ipv6_fragment_header(header: &[u8]) -> Option<Self> {
+        let offset_and_flags = u16::from_be_bytes([*header.get(2)?, *header.get(3)?]);
+        let identification = header.get(4..8)?;
+        let identification = u32::from_be_bytes([
+            identification[0],
+            identification[1],
+            identification[2],
+            identification[3],
+        ]);
+        Some(Fragment {
+            identification,
+            offset: (offset_and_flags & IPV6_FRAGMENT_OFFSET_MASK) >> 3,
+            more_fragments: offset_and_flags & IPV6_MORE_FRAGMENTS != 0,
+        })
+    }
+    pub fn is_first(&self) -> bool {
+        self.offset == 0
+    }
+}
+
+/// Identifies a fragmented datagram, see RFC 791 and RFC 8200.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FragmentKey {
+    pub source: IpAddr,
+    pub destination: IpAddr,
+    /// Only set for IPv4, as the next header of IPv6 fragment headers differs between the first
+    /// and subsequent fragments if further extension headers follow.
+    pub protocol: Option<u8>,
+    pub identification: u32,
+}
+
+/// The transport flow announced by the first fragment of a datagram.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FragmentedFlow {
+    pub protocol: Protocol,
+    pub source_port: u16,
+    pub destination_port: u16,
+}
+
+/// Fragments whose datagram could not be attributed to a flow before it timed out or had to
+/// make room for other datagrams.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OrphanedFragments {
+    pub fragments: u128,
+    pub bytes: u128,
+}
+
+impl OrphanedFragments {
+    pub fn is_empty(&self) -> bool {
+        self.fragments == 0
+    }
+}
+
+/// Subsequent fragments of a datagram that arrived before its first fragment.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PendingFragments {
+    pub fragments: u128,
+    pub length: AccountedLength,
+}
+
+enum Datagram {
+    Resolved(FragmentedFlow),
+    Pending(PendingFragments),
+}
+
+/// Bounded table of fragmented datagrams, attributing all fragments to the flow of the first.
+pub struct Fragments {
+    datagrams: ExpiringMap<FragmentKey, Datagram>,
+    orphaned: OrphanedFragments,
+}
+
+impl Fragments {
+    pub fn new() -> Self {
+        Fragments {
+            datagrams: ExpiringMap::new(MAXIMUM_NUMBER_OF_DATAGRAMS, DATAGRAM_TIMEOUT),
+            orphaned: OrphanedFragments::default(),
+        }
+    }
+    /// Remembers the flow of a datagram, returning its fragments seen earlier.
+    pub fn first_fragment(
+        &mut self,
+        key: FragmentKey,
+        flow: FragmentedFlow,
+        timestamp: SystemTime,
+    ) -> PendingFragments {
+        self.maintain(timestamp);
+        let (replaced, evicted) =
+            self.datagrams
+                .insert_evicting(key, Datagram::Resolved(flow), timestamp);
+        if let Some(evicted) = evicted {
+            orphan(&mut self.orphaned, evicted);
+        }
+        match replaced {
+            Some(Datagram::Pending(pending)) => pending,
+            _ => PendingFragments::default(),
+        }
+    }
+    /// Returns the flow of a subsequent fragment, if its first fragment has been seen already.
+    pub fn subsequent_fragment(
+        &mut self,
+        key: FragmentKey,
+        length: AccountedLength,
+        timestamp: SystemTime,
+    ) -> Option<FragmentedFlow> {
+        self.maintain(timestamp);
+        if !self.datagrams.contains_key(&key) {
+            let pending = Datagram::Pending(PendingFragments::default());
+            if let (_, Some(evicted)) = self.datagrams.insert_evicting(key, pending, timestamp) {
+                orphan(&mut self.orphaned, evicted);
+            }
+        }
+        match self.datagrams.get_mut(&key, timestamp)? {
+            Datagram::Resolved(flow) => Some(*flow),
+            Datagram::Pending(pending) => {
+                pending.fragments += 1;
+                pending.length += length;
+                None
+            }
+        }
+    }
+    /// Expires datagrams not seen within the reassembly timeout.
+    pub fn maintain(&mut self, now: SystemTime) {
+        let orphaned = &mut self.orphaned;
+        self.datagrams
+            .expire(now, |datagram| orphan(orphaned, datagram));
+    }
+    /// Returns and resets the orphaned fragments counted so far.
+    pub fn take_orphaned(&mut self) -> OrphanedFragments {
+        std::mem::take(&mut self.orphaned)
+    }
+}
+
+fn orphan(orphaned: &mut OrphanedFragments, datagram: Datagram) {
+    if let Datagram::Pending(pending) = datagram {
+        orphaned.fragments += pending.fragments;
+        orphaned.bytes += pending.length.data_length;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::network::fixtures::at;
+
+    use super::*;
+
+    const FLOW: FragmentedFlow = FragmentedFlow {
+        protocol: Protocol::Udp,
+        source_port: 53,
+        destination_port: 40000,
+    };
+
+    fn key(identification: u32) -> FragmentKey {
+        FragmentKey {
+            source: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            destination: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            protocol: Some(17),
+            identification,
+        }
+    }
+
+    fn length(bytes: u128) -> AccountedLength {
+        AccountedLength {
+            data_length: bytes,
+            payload_length: bytes,
+        }
+    }
+
+    #[test]
+    fn should_attribute_subsequent_fragments_to_first_fragment() {
+        // given
+        let mut fragments = Fragments::new();
+
+        // when
+        let earlier = fragments.first_fragment(key(1), FLOW, at(100));
+        let flow = fragments.subsequent_fragment(key(1), length(1480), at(100));
+        let other_flow = fragments.subsequent_fragment(key(2), length(1480), at(100));
+
+        // then
+        assert_eq!(earlier, PendingFragments::default());
+        assert_eq!(flow, Some(FLOW));
+        assert_eq!(other_flow, None);
+    }
+
+    #[test]
+    fn should_attribute_fragments_arriving_before_first_fragment() {
+        // given
+        let mut fragments = Fragments::new();
+        fragments.subsequent_fragment(key(1), length(1480), at(100));
+        fragments.subsequent_fragment(key(1), length(20), at(100));
+
+        // when
+        let earlier = fragments.first_fragment(key(1), FLOW, at(101));
+
+        // then
+        assert_eq!(
+            earlier,
+            PendingFragments {
+                fragments: 2,
+                length: length(1500)
+            }
+        );
+        assert!(fragments.take_orphaned().is_empty());
+    }
+
+    #[test]
+    fn should_count_orphaned_fragments_after_timeout() {
+        // given
+        let mut fragments = Fragments::new();
+        fragments.subsequent_fragment(key(1), length(1480), at(100));
+        fragments.first_fragment(key(2), FLOW, at(100));
+
+        // when
+        fragments.maintain(at(130));
+
+        // then
+        assert_eq!(
+            fragments.take_orphaned(),
+            OrphanedFragments {
+                fragments: 1,
+                bytes: 1480
+            }
+        );
+        assert!(fragments.take_orphaned().is_empty());
+        assert_eq!(
+            fragments.subsequent_fragment(key(2), length(8), at(131)),
+            None
+        );
+    }
+
+    #[test]
+    fn should_evict_oldest_datagram_when_full() {
+        // given
+        let mut fragments = Fragments::new();
+        fragments.subsequent_fragment(key(0), length(8), at(100));
+        for identification in 1..=MAXIMUM_NUMBER_OF_DATAGRAMS as u32 {
+            fragments.first_fragment(key(identification), FLOW, at(101));
+        }
+
+        // then
+        assert_eq!(fragments.datagrams.len(), MAXIMUM_NUMBER_OF_DATAGRAMS);
+        assert_eq!(
+            fragments.take_orphaned(),
+            OrphanedFragments {
+                fragments: 1,
+                bytes: 8
+            }
+        );
+    }
+
+    #[test]
+    fn should_parse_ipv6_fragment_header() {
+        // when
+        let fragment = Fragment::of_ipv6_fragment_header(&[17, 0, 0x00, 0xb9, 0, 0, 0, 42]);
+
+        // then
+        assert_eq!(
+            fragment,
+            Some(Fragment {
+                identification: 42,
+                offset: 23,
+                more_fragments: true
+            })
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use ::std::io::{self, Chain, Cursor, Read, Result};
+use ::std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ::pcap_file::pcap::PcapReader;
+use ::pcap_file::pcapng::blocks::interface_description::InterfaceDescriptionOption;
+use ::pcap_file::pcapng::{Block, PcapNgReader};
+use ::pnet::datalink::{DataLinkReceiver, NetworkInterface};
+
+use crate::network::LinkType;
+use crate::os::shared::get_datalink_channel;
+
+const PCAP_MAGIC_NUMBERS: [[u8; 4]; 4] = [
+    [0xa1, 0xb2, 0xc3, 0xd4],
+    [0xd4, 0xc3, 0xb2, 0xa1],
+    [0xa1, 0xb2, 0x3c, 0x4d],
+    [0x4d, 0x3c, 0xb2, 0xa1],
+];
+const PCAPNG_MAGIC_NUMBER: [u8; 4] = [0x0a, 0x0d, 0x0d, 0x0a];
+const PCAPNG_DEFAULT_TIMESTAMP_RESOLUTION: u8 = 6;
+
+pub struct Frame<'a> {
+    pub timestamp: SystemTime,
+    pub link_type: LinkType,
+    pub bytes: &'a [u8],
+}
+
+/// A source of link layer frames, either sniffed live or read from a recording.
+pub trait Frames: Send {
+    /// Returns the next frame or `None` if the source is exhausted.
+    fn next(&mut self) -> Result<Option<Frame<'_>>>;
+    fn reset(&mut self, network_interface: &NetworkInterface) -> Result<()>;
+    /// Whether the frames are read from a recording, which cannot be read any further once a
+    /// frame is unreadable.
+    fn is_recorded(&self) -> bool;
+}
+
+pub struct LiveFrames {
+    receiver: Box<dyn DataLinkReceiver>,
+    link_type: LinkType,
+    promiscuous: bool,
+}
+
+impl LiveFrames {
+    pub fn new(
+        receiver: Box<dyn DataLinkReceiver>,
+        link_type: LinkType,
+        promiscuous: bool,
+    ) -> Self {
+        LiveFrames {
+            receiver,
+            link_type,
+            promiscuous,
+        }
+    }
+}
+
+impl Frames for LiveFrames {
+    fn next(&mut self) -> Result<Option<Frame<'_>>> {
+        let bytes = self.receiver.next()?;
+        Ok(Some(Frame {
+            timestamp: SystemTime::now(),
+            link_type: self.link_type,
+            bytes,
+        }))
+    }
+    fn reset(&mut self, network_interface: &NetworkInterface) -> Result<()> {
+        self.receiver = get_datalink_channel(network_interface, self.promiscuous)
+            .map_err(|_| io::Error::other("Interface not available"))?;
+        Ok(())
+    }
+    fn is_recorded(&self) -> bool {
+        false
+    }
+}
+
+enum Recording<R: Read> {
+    Pcap(PcapReader<Chain<Cursor<[u8; 4]>, R>>),
+    PcapNg(PcapNgReader<Chain<Cursor<[u8; 4]>, R>>),
+}
+
+/// Frames read from a pcap or pcapng file, using the capture timestamps.
+pub struct RecordedFrames<R: Read> {
+    recording: Recording<R>,
+    buffer: Vec<u8>,
+}
+
+struct RecordedFrame {
+    timestamp: SystemTime,
+    link_type: LinkType,
+}
+
+impl<R: Read> RecordedFrames<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut magic_number = [0; 4];
+        reader.read_exact(&mut magic_number)?;
+        let reader = Cursor::new(magic_number).chain(reader);
+        let recording = if PCAP_MAGIC_NUMBERS.contains(&magic_number) {
+            Recording::Pcap(PcapReader::new(reader).map_err(invalid_data)?)
+        } else if magic_number == PCAPNG_MAGIC_NUMBER {
+            Recording::PcapNg(PcapNgReader::new(reader).map_err(invalid_data)?)
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Neither a pcap nor a pcapng file",
+            ));
+        };
+        Ok(RecordedFrames {
+            recording,
+            buffer: vec![],
+        })
+    }
+    fn read_next(&mut self) -> Result<Option<RecordedFrame>> {
+        match &mut self.recording {
+            Recording::Pcap(reader) => {
+                let link_type = LinkType::from(reader.header().datalink);
+                match reader.next_packet() {
+                    None => Ok(None),
+                    Some(packet) => {
+                        let packet = packet.map_err(invalid_data)?;
+                        self.buffer.clear();
+                        self.buffer.extend_from_slice(&packet.data);
+                        Ok(Some(RecordedFrame {
+                            timestamp: UNIX_EPOCH + packet.timestamp,
+                            link_type,
+                        }))
+                    }
+                }
+            }
+            Recording::PcapNg(reader) => loop {
+                let interface_id = match reader.next_block() {
+                    None => return Ok(None),
+                    Some(block) => match block.map_err(invalid_data)? {
+                        Block::EnhancedPacket(packet) => {
+                            self.buffer.clear();
+                            self.buffer.extend_from_slice(&packet.data);
+                            Some((packet.interface_id, packet.timestamp))
+                        }
+                        _ => None,
+                    },
+                };
+                if let Some((interface_id, raw_timestamp)) = interface_id {
+                    let interface = reader.interfaces().get(interface_id as usize);
+                    let timestamp_resolution = interface
+                        .and_then(|interface| {
+                            interface.options.iter().find_map(|option| match option {
+                                InterfaceDescriptionOption::IfTsResol(resolution) => {
+                                    Some(*resolution)
+                                }
+                                _ => None,
+                            })
+                        })
+                        .unwrap_or(PCAPNG_DEFAULT_TIMESTAMP_RESOLUTION);
+                    return Ok(Some(RecordedFrame {
+                        timestamp: UNIX_EPOCH
+                            + pcapng_timestamp(raw_timestamp, timestamp_resolution),
+                        link_type: interface
+                            .map(|interface| LinkType::from(interface.linktype))
+                            .unwrap_or(LinkType::Unsupported),
+                    }));
+                }
+            },
+        }
+    }
+}
+
+impl<R: Read + Send> Frames for RecordedFrames<R> {
+    fn next(&mut self) -> Result<Option<Frame<'_>>> {
+        Ok(self.read_next()?.map(move |recorded_frame| Frame {
+            timestamp: recorded_frame.timestamp,
+            link_type: recorded_frame.link_type,
+            bytes: &self.buffer,
+        }))
+    }
+    fn reset(&mut self, _network_interface: &NetworkInterface) -> Result<()> {
+        Ok(())
+    }
+    fn is_recorded(&self) -> bool {
+        true
+    }
+}
+
+fn invalid_data(error: pcap_file::PcapError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// The pcapng reader interprets raw timestamps as nanoseconds, while the actual unit is defined
+/// by the `if_tsresol` option of the interface.
+fn pcapng_timestamp(raw_timestamp: Duration, resolution: u8) -> Duration {
+    let units = raw_timestamp.as_nanos();
+    let units_per_second: u128 = if resolution & 0x80 == 0 {
+        10u128.pow(u32::from(resolution))
+    } else {
+        1u128 << (resolution & 0x7f)
+    };
+    let seconds = units / units_per_second;
+    let nanos = (units % units_per_second) * 1_000_000_000 / units_per_second;
+    Duration::new(seconds as u64, nanos as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_convert_pcapng_timestamps_by_resolution() {
+        // given
+        let raw_timestamp = Duration::from_nanos(1_651_850_091_742_237);
+
+        // when
+        let microseconds = pcapng_timestamp(raw_timestamp, 6);
+        let nanoseconds = pcapng_timestamp(raw_timestamp, 9);
+        let binary = pcapng_timestamp(Duration::from_nanos(3 << 19), 0x80 | 20);
+
+        // then
+        assert_eq!(microseconds, Duration::new(1_651_850_091, 742_237_000));
+        assert_eq!(nanoseconds, Duration::new(1_651_850, 91_742_237));
+        assert_eq!(binary, Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn should_reject_unknown_file_formats() {
+        // when
+        let result = RecordedFrames::new(Cursor::new(b"GIF89a".to_vec()));
+
+        // then
+        assert_eq!(
+            result.err().map(|error| error.kind()),
+            Some(io::ErrorKind::InvalidData)
+        );
+    }
+}
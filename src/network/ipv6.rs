@@ -0,0 +1,243 @@
+use ::pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use ::pnet::packet::ipv6::Ipv6Packet;
+use ::pnet::packet::Packet;
+
+use crate::network::Fragment;
+
+const IPV6_HEADER_LENGTH: usize = 40;
+const FRAGMENT_HEADER_LENGTH: usize = 8;
+
+/// The first header following the IPv6 extension headers and the bytes it spans.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Ipv6UpperLayer<'a> {
+    pub protocol: IpNextHeaderProtocol,
+    pub payload: &'a [u8],
+    /// Set if the packet carries a fragment header.
+    pub fragment: Option<Fragment>,
+}
+
+/// Skips all extension headers of the packet.
+///
+/// Traversal stops at the first header that is not a known extension header, at `No Next Header`
+/// and after fragment headers of non-first fragments, whose payload does not start with an upper
+/// layer header. The returned protocol is the one announced by the last traversed header, even
+/// if it is not one decoded any further.
+pub fn ipv6_upper_layer(packet: &[u8]) -> Option<Ipv6UpperLayer<'_>> {
+    let ip_packet = Ipv6Packet::new(packet)?;
+    let mut protocol = ip_packet.get_next_header();
+    let mut payload =
+        packet.get(IPV6_HEADER_LENGTH..IPV6_HEADER_LENGTH + ip_packet.payload().len())?;
+    let mut fragment = None;
+    loop {
+        let header_length = match protocol {
+            IpNextHeaderProtocols::Hopopt
+            | IpNextHeaderProtocols::Ipv6Route
+            | IpNextHeaderProtocols::Ipv6Opts
+            | IpNextHeaderProtocols::MobilityHeader
+            | IpNextHeaderProtocols::Hip
+            | IpNextHeaderProtocols::Shim6 => (usize::from(*payload.get(1)?) + 1) * 8,
+            IpNextHeaderProtocols::Ah => (usize::from(*payload.get(1)?) + 2) * 4,
+            IpNextHeaderProtocols::Ipv6Frag => {
+                let fragment_header = Fragment::of_ipv6_fragment_header(payload)?;
+                fragment = Some(fragment_header);
+                if !fragment_header.is_first() {
+                    return Some(Ipv6UpperLayer {
+                        protocol: IpNextHeaderProtocol(*payload.first()?),
+                        payload: payload.get(FRAGMENT_HEADER_LENGTH..)?,
+                        fragment,
+                    });
+                }
+                FRAGMENT_HEADER_LENGTH
+            }
+            _ => {
+                return Some(Ipv6UpperLayer {
+                    protocol,
+                    payload,
+                    fragment,
+                })
+            }
+        };
+        protocol = IpNextHeaderProtocol(*payload.first()?);
+        payload = payload.get(header_length..)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use crate::network::fixtures::*;
+
+    use super::*;
+
+    const SOURCE: Ipv6Addr = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 2);
+    const DESTINATION: Ipv6Addr = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1);
+
+    fn upper_layer(next_header: u8, payload: &[u8]) -> Option<(IpNextHeaderProtocol, Vec<u8>)> {
+        fragmented_upper_layer(next_header, payload)
+            .map(|(protocol, payload, _)| (protocol, payload))
+    }
+
+    fn fragmented_upper_layer(
+        next_header: u8,
+        payload: &[u8],
+    ) -> Option<(IpNextHeaderProtocol, Vec<u8>, Option<Fragment>)> {
+        let packet = ipv6(SOURCE, DESTINATION, next_header, payload);
+        ipv6_upper_layer(&packet).map(|upper_layer| {
+            (
+                upper_layer.protocol,
+                upper_layer.payload.to_vec(),
+                upper_layer.fragment,
+            )
+        })
+    }
+
+    fn datagram() -> Vec<u8> {
+        udp(53, 40000, &[0; 12])
+    }
+
+    #[test]
+    fn should_return_upper_layer_without_extension_headers() {
+        // when
+        let result = upper_layer(PROTOCOL_UDP, &datagram());
+
+        // then
+        assert_eq!(result, Some((IpNextHeaderProtocols::Udp, datagram())));
+    }
+
+    #[test]
+    fn should_skip_hop_by_hop_options() {
+        // when
+        let result = upper_layer(
+            0,
+            &ipv6_extension_header(PROTOCOL_UDP, &[0; 14], &datagram()),
+        );
+
+        // then
+        assert_eq!(result, Some((IpNextHeaderProtocols::Udp, datagram())));
+    }
+
+    #[test]
+    fn should_skip_routing_header() {
+        // when
+        let result = upper_layer(
+            43,
+            &ipv6_extension_header(PROTOCOL_UDP, &[0; 22], &datagram()),
+        );
+
+        // then
+        assert_eq!(result, Some((IpNextHeaderProtocols::Udp, datagram())));
+    }
+
+    #[test]
+    fn should_skip_destination_options() {
+        // when
+        let result = upper_layer(
+            60,
+            &ipv6_extension_header(PROTOCOL_UDP, &[0; 6], &datagram()),
+        );
+
+        // then
+        assert_eq!(result, Some((IpNextHeaderProtocols::Udp, datagram())));
+    }
+
+    #[test]
+    fn should_skip_fragment_header_of_first_fragment() {
+        // given
+        let fragment_header = [PROTOCOL_UDP, 0, 0x00, 0x01, 0, 0, 0, 42];
+
+        // when
+        let result = fragmented_upper_layer(44, &[&fragment_header[..], &datagram()].concat());
+
+        // then
+        assert_eq!(
+            result,
+            Some((
+                IpNextHeaderProtocols::Udp,
+                datagram(),
+                Some(Fragment {
+                    identification: 42,
+                    offset: 0,
+                    more_fragments: true
+                })
+            ))
+        );
+    }
+
+    #[test]
+    fn should_stop_after_fragment_header_of_subsequent_fragments() {
+        // given
+        let fragment_header = [PROTOCOL_UDP, 0, 0x00, 0xb8, 0, 0, 0, 42];
+
+        // when
+        let result = fragmented_upper_layer(44, &[&fragment_header[..], &[0; 16]].concat());
+
+        // then
+        assert_eq!(
+            result,
+            Some((
+                IpNextHeaderProtocols::Udp,
+                vec![0; 16],
+                Some(Fragment {
+                    identification: 42,
+                    offset: 23,
+                    more_fragments: false
+                })
+            ))
+        );
+    }
+
+    #[test]
+    fn should_skip_authentication_header() {
+        // given
+        let authentication_header = [&[PROTOCOL_UDP, 4, 0, 0][..], &[0; 20]].concat();
+
+        // when
+        let result = upper_layer(51, &[&authentication_header[..], &datagram()].concat());
+
+        // then
+        assert_eq!(result, Some((IpNextHeaderProtocols::Udp, datagram())));
+    }
+
+    #[test]
+    fn should_skip_chained_extension_headers() {
+        // when
+        let result = upper_layer(
+            0,
+            &ipv6_extension_header(
+                60,
+                &[0; 6],
+                &ipv6_extension_header(PROTOCOL_UDP, &[0; 6], &datagram()),
+            ),
+        );
+
+        // then
+        assert_eq!(result, Some((IpNextHeaderProtocols::Udp, datagram())));
+    }
+
+    #[test]
+    fn should_return_unknown_payloads() {
+        // when
+        let no_next_header = upper_layer(0, &ipv6_extension_header(59, &[0; 6], &[1, 2, 3]));
+        let encapsulating_security_payload = upper_layer(50, &[0; 16]);
+
+        // then
+        assert_eq!(
+            no_next_header,
+            Some((IpNextHeaderProtocols::Ipv6NoNxt, vec![1, 2, 3]))
+        );
+        assert_eq!(
+            encapsulating_security_payload,
+            Some((IpNextHeaderProtocols::Esp, vec![0; 16]))
+        );
+    }
+
+    #[test]
+    fn should_reject_truncated_extension_headers() {
+        // when
+        let result = upper_layer(43, &[PROTOCOL_UDP, 2, 0, 0, 0, 0, 0, 0]);
+
+        // then
+        assert_eq!(result, None);
+    }
+}
@@ -0,0 +1,241 @@
+use ::pcap_file::DataLink;
+use ::pnet::packet::ethernet::{EtherType, EtherTypes, EthernetPacket};
+use ::pnet::packet::vlan::VlanPacket;
+
+const ETHERNET_HEADER_LENGTH: usize = 14;
+const LINUX_SLL_HEADER_LENGTH: usize = 16;
+const LINUX_SLL_PROTOCOL_OFFSET: usize = 14;
+const LINUX_SLL2_HEADER_LENGTH: usize = 20;
+const LINUX_SLL2_PROTOCOL_OFFSET: usize = 0;
+const NULL_HEADER_LENGTH: usize = 4;
+const VLAN_TAG_LENGTH: usize = 4;
+/// Single 802.1Q tags and double tags (QinQ, 802.1ad) are supported.
+const MAXIMUM_NUMBER_OF_VLAN_TAGS: usize = 2;
+
+/// The framing of packets delivered by a capture channel or recording.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkType {
+    Ethernet,
+    /// IPv4 or IPv6 packets without any link layer header, e.g. tun devices.
+    RawIp,
+    /// Linux cooked capture v1, used by captures on the "any" device.
+    LinuxSll,
+    /// Linux cooked capture v2.
+    LinuxSll2,
+    /// BSD loopback, a 4 byte address family header.
+    Null,
+    /// Zeroed Ethernet header in front of IP packets, as pnet delivers BPF loopback and
+    /// point-to-point devices on macOS.
+    ZeroedEthernet,
+    Unsupported,
+}
+
+impl LinkType {
+    /// Length of the header a capture puts in front of packets in place of the header sent on
+    /// the link, which is not link layer traffic.
+    pub fn pseudo_header_length(self) -> usize {
+        match self {
+            LinkType::LinuxSll => LINUX_SLL_HEADER_LENGTH,
+            LinkType::LinuxSll2 => LINUX_SLL2_HEADER_LENGTH,
+            _ => 0,
+        }
+    }
+    pub fn data_link(self) -> DataLink {
+        match self {
+            LinkType::Ethernet | LinkType::ZeroedEthernet | LinkType::Unsupported => {
+                DataLink::ETHERNET
+            }
+            LinkType::RawIp => DataLink::RAW,
+            LinkType::LinuxSll => DataLink::LINUX_SLL,
+            LinkType::LinuxSll2 => DataLink::LINUX_SLL2,
+            LinkType::Null => DataLink::NULL,
+        }
+    }
+}
+
+impl From<DataLink> for LinkType {
+    fn from(data_link: DataLink) -> Self {
+        match data_link {
+            DataLink::ETHERNET => LinkType::Ethernet,
+            DataLink::RAW | DataLink::IPV4 | DataLink::IPV6 => LinkType::RawIp,
+            DataLink::LINUX_SLL => LinkType::LinuxSll,
+            DataLink::LINUX_SLL2 => LinkType::LinuxSll2,
+            DataLink::NULL | DataLink::LOOP => LinkType::Null,
+            _ => LinkType::Unsupported,
+        }
+    }
+}
+
+/// Strips the link layer header, returning the network layer protocol and its packet.
+///
+/// VLAN ids of all stripped tags are appended to `vlan_ids`, the outermost first.
+pub fn decode_link_layer<'a>(
+    link_type: LinkType,
+    bytes: &'a [u8],
+    vlan_ids: &mut Vec<u16>,
+) -> Option<(EtherType, &'a [u8])> {
+    match link_type {
+        LinkType::Ethernet => {
+            let ethernet_packet = EthernetPacket::new(bytes)?;
+            strip_vlan_tags(
+                ethernet_packet.get_ethertype(),
+                &bytes[ETHERNET_HEADER_LENGTH..],
+                vlan_ids,
+            )
+        }
+        LinkType::RawIp => raw_ip(bytes),
+        LinkType::LinuxSll => strip_vlan_tags(
+            ethertype_at(bytes, LINUX_SLL_PROTOCOL_OFFSET)?,
+            bytes.get(LINUX_SLL_HEADER_LENGTH..)?,
+            vlan_ids,
+        ),
+        LinkType::LinuxSll2 => strip_vlan_tags(
+            ethertype_at(bytes, LINUX_SLL2_PROTOCOL_OFFSET)?,
+            bytes.get(LINUX_SLL2_HEADER_LENGTH..)?,
+            vlan_ids,
+        ),
+        LinkType::Null => raw_ip(bytes.get(NULL_HEADER_LENGTH..)?),
+        LinkType::ZeroedEthernet => raw_ip(bytes.get(ETHERNET_HEADER_LENGTH..)?),
+        LinkType::Unsupported => None,
+    }
+}
+
+fn raw_ip(bytes: &[u8]) -> Option<(EtherType, &[u8])> {
+    match bytes.first()? >> 4 {
+        4 => Some((EtherTypes::Ipv4, bytes)),
+        6 => Some((EtherTypes::Ipv6, bytes)),
+        _ => None,
+    }
+}
+
+fn ethertype_at(bytes: &[u8], offset: usize) -> Option<EtherType> {
+    let protocol = bytes.get(offset..offset + 2)?;
+    Some(EtherType(u16::from_be_bytes([protocol[0], protocol[1]])))
+}
+
+/// Skips 802.1Q and 802.1ad tags, recording their VLAN ids.
+fn strip_vlan_tags<'a>(
+    mut ethertype: EtherType,
+    mut payload: &'a [u8],
+    vlan_ids: &mut Vec<u16>,
+) -> Option<(EtherType, &'a [u8])> {
+    while matches!(
+        ethertype,
+        EtherTypes::Vlan | EtherTypes::PBridge | EtherTypes::QinQ
+    ) {
+        if vlan_ids.len() == MAXIMUM_NUMBER_OF_VLAN_TAGS {
+            return None;
+        }
+        let vlan_packet = VlanPacket::new(payload)?;
+        vlan_ids.push(vlan_packet.get_vlan_identifier());
+        ethertype = vlan_packet.get_ethertype();
+        payload = &payload[VLAN_TAG_LENGTH..];
+    }
+    Some((ethertype, payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// IPv4 header of a DNS query from 192.168.10.87 to 192.168.10.34, as found after the link
+    /// layer header in all fixtures.
+    const IPV4_DNS_QUERY: [u8; 28] = [
+        0x45, 0x00, 0x00, 0x1c, 0x5c, 0x1e, 0x40, 0x00, 0x40, 0x11, 0x48, 0xb9, 0xc0, 0xa8, 0x0a,
+        0x57, 0xc0, 0xa8, 0x0a, 0x22, 0xa8, 0xf6, 0x00, 0x35, 0x00, 0x08, 0x95, 0x6b,
+    ];
+
+    fn captured(link_layer_header: &[u8]) -> Vec<u8> {
+        let mut frame = link_layer_header.to_vec();
+        frame.extend_from_slice(&IPV4_DNS_QUERY);
+        frame
+    }
+
+    fn assert_ipv4_dns_query(link_type: LinkType, frame: &[u8]) {
+        let mut vlan_ids = vec![];
+        let result = decode_link_layer(link_type, frame, &mut vlan_ids);
+        assert_eq!(result, Some((EtherTypes::Ipv4, &IPV4_DNS_QUERY[..])));
+    }
+
+    #[test]
+    fn should_decode_ethernet() {
+        assert_ipv4_dns_query(
+            LinkType::Ethernet,
+            &captured(&[
+                0x52, 0x54, 0x00, 0x12, 0x35, 0x02, 0x08, 0x00, 0x27, 0x8d, 0xc0, 0x4d, 0x08, 0x00,
+            ]),
+        );
+    }
+
+    #[test]
+    fn should_decode_raw_ip() {
+        assert_ipv4_dns_query(LinkType::RawIp, &IPV4_DNS_QUERY);
+    }
+
+    #[test]
+    fn should_decode_linux_cooked_capture() {
+        assert_ipv4_dns_query(
+            LinkType::LinuxSll,
+            &captured(&[
+                0x00, 0x04, 0x00, 0x01, 0x00, 0x06, 0x08, 0x00, 0x27, 0x8d, 0xc0, 0x4d, 0x00, 0x00,
+                0x08, 0x00,
+            ]),
+        );
+    }
+
+    #[test]
+    fn should_decode_linux_cooked_capture_v2() {
+        assert_ipv4_dns_query(
+            LinkType::LinuxSll2,
+            &captured(&[
+                0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01, 0x04, 0x06, 0x08, 0x00,
+                0x27, 0x8d, 0xc0, 0x4d, 0x00, 0x00,
+            ]),
+        );
+    }
+
+    #[test]
+    fn should_decode_bsd_loopback() {
+        assert_ipv4_dns_query(LinkType::Null, &captured(&[0x02, 0x00, 0x00, 0x00]));
+    }
+
+    #[test]
+    fn should_decode_zeroed_ethernet() {
+        assert_ipv4_dns_query(LinkType::ZeroedEthernet, &captured(&[0; 14]));
+    }
+
+    #[test]
+    fn should_not_guess_ip_in_ethernet_frames() {
+        // given
+        let frame = captured(&[
+            0x45, 0x54, 0x00, 0x12, 0x35, 0x02, 0x08, 0x00, 0x27, 0x8d, 0xc0, 0x4d, 0x08, 0x06,
+        ]);
+        let mut vlan_ids = vec![];
+
+        // when
+        let result = decode_link_layer(LinkType::Ethernet, &frame, &mut vlan_ids);
+
+        // then
+        assert_eq!(
+            result.map(|(ethertype, _)| ethertype),
+            Some(EtherTypes::Arp)
+        );
+    }
+
+    #[test]
+    fn should_strip_vlan_tags_of_linux_cooked_capture() {
+        // given
+        let frame = captured(&[
+            0x00, 0x00, 0x00, 0x01, 0x00, 0x06, 0x08, 0x00, 0x27, 0x8d, 0xc0, 0x4d, 0x00, 0x00,
+            0x81, 0x00, 0x00, 0x2a, 0x08, 0x00,
+        ]);
+        let mut vlan_ids = vec![];
+
+        // when
+        let result = decode_link_layer(LinkType::LinuxSll, &frame, &mut vlan_ids);
+
+        // then
+        assert_eq!(result, Some((EtherTypes::Ipv4, &IPV4_DNS_QUERY[..])));
+        assert_eq!(vlan_ids, vec![42]);
+    }
+}
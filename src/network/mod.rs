@@ -1,7 +1,43 @@
+mod accumulation;
+mod capture;
 mod connection;
+mod conntrack;
+mod dns;
+mod expiring;
+#[cfg(test)]
+pub mod fixtures;
+mod fragments;
+mod frames;
+mod ipv6;
+mod link;
+mod process;
+mod quic;
+mod router;
+mod scope;
 mod sniffer;
+mod tcp;
+mod tls;
+mod transit;
+mod tunnel;
 mod utilization;
 
+pub use accumulation::*;
+pub use capture::*;
 pub use connection::*;
+pub use conntrack::*;
+pub use dns::*;
+pub use expiring::*;
+pub use fragments::*;
+pub use frames::*;
+pub use ipv6::*;
+pub use link::*;
+pub use process::*;
+pub use quic::*;
+pub use router::*;
+pub use scope::*;
 pub use sniffer::*;
+pub use tcp::*;
+pub use tls::*;
+pub use transit::*;
+pub use tunnel::*;
 pub use utilization::*;
@@ -0,0 +1,319 @@
+use ::std::collections::HashMap;
+use ::std::fmt;
+use ::std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use ::std::path::PathBuf;
+use ::std::sync::{Arc, Mutex};
+use ::std::time::{Duration, SystemTime};
+
+use crate::network::LocalSocket;
+
+/// Misses refresh the socket and inode maps at most this often, so traffic of sockets without a
+/// process, e.g. of the kernel, does not cause a scan per connection. Hits of a socket map older
+/// than this refresh it as well.
+const MINIMUM_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Process {
+    pub pid: i32,
+    pub name: String,
+    /// Set for processes running in a container.
+    pub container: Option<Container>,
+    /// Set for processes in a cgroup managed by systemd.
+    pub systemd_unit: Option<SystemdUnit>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SystemdUnit {
+    /// A service or scope, e.g. `nginx.service`.
+    pub unit: String,
+    pub slice: Option<String>,
+    /// Set for processes of a login session, e.g. `2` of `session-2.scope`.
+    pub session: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Container {
+    pub id: String,
+    pub runtime: ContainerRuntime,
+    /// The remaining fields are only known if the runtime's metadata files are readable.
+    pub name: Option<String>,
+    pub pod_uid: Option<String>,
+    pub pod_name: Option<String>,
+    pub pod_namespace: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ContainerRuntime {
+    Docker,
+    Containerd,
+    CriO,
+    Podman,
+    /// Containers of the cgroupfs driver managed by the kubelet, whose cgroups do not name the
+    /// runtime.
+    Unknown,
+}
+
+impl fmt::Display for ContainerRuntime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ContainerRuntime::Docker => "docker",
+            ContainerRuntime::Containerd => "containerd",
+            ContainerRuntime::CriO => "cri-o",
+            ContainerRuntime::Podman => "podman",
+            ContainerRuntime::Unknown => "unknown",
+        })
+    }
+}
+
+/// Identity of a process beyond its name, which the kernel truncates to 15 characters.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProcessDetails {
+    pub process: Process,
+    pub parent_pid: Option<i32>,
+    pub executable: Option<PathBuf>,
+    /// Unredacted arguments, including the program.
+    pub command_line: Vec<String>,
+    pub uid: Option<u32>,
+    pub user_name: Option<String>,
+    pub start_time: Option<SystemTime>,
+}
+
+/// Maps local sockets to the processes owning them, shared by all threads resolving them.
+///
+/// Sockets are mapped to their inode by the cheap socket tables of the kernel, inodes to their
+/// process by scanning the file descriptors of all processes. Both maps are cached. The inode
+/// map is only refreshed if an inode is missing, the socket map also if it expired, as ephemeral
+/// ports are reused by sockets of other processes.
+#[derive(Clone)]
+pub struct ProcessCache {
+    read_socket_inodes: fn() -> HashMap<LocalSocket, u64>,
+    read_inode_processes: fn() -> HashMap<u64, Process>,
+    state: Arc<Mutex<ProcessCacheState>>,
+}
+
+struct ProcessCacheState {
+    socket_inodes: HashMap<LocalSocket, u64>,
+    inode_processes: HashMap<u64, Process>,
+    last_socket_refresh: Option<SystemTime>,
+    last_inode_refresh: Option<SystemTime>,
+}
+
+impl ProcessCache {
+    pub fn new(
+        read_socket_inodes: fn() -> HashMap<LocalSocket, u64>,
+        read_inode_processes: fn() -> HashMap<u64, Process>,
+    ) -> Self {
+        ProcessCache {
+            read_socket_inodes,
+            read_inode_processes,
+            state: Arc::new(Mutex::new(ProcessCacheState {
+                socket_inodes: HashMap::new(),
+                inode_processes: HashMap::new(),
+                last_socket_refresh: None,
+                last_inode_refresh: None,
+            })),
+        }
+    }
+    /// Resolves the process owning a local socket, or the wildcard socket it was accepted by.
+    ///
+    /// The maps are read without holding the lock, so other threads are not blocked by a scan.
+    pub fn resolve(&self, local_socket: &LocalSocket, now: SystemTime) -> Option<Process> {
+        let (known_inode, expired) = {
+            let state = self.state.lock().unwrap();
+            (
+                state.inode(local_socket),
+                due(state.last_socket_refresh, now),
+            )
+        };
+        let inode = match known_inode {
+            Some(inode) if !expired => inode,
+            _ => {
+                if !self.claim_refresh(|state| &mut state.last_socket_refresh, now) {
+                    return known_inode.and_then(|inode| self.process(inode));
+                }
+                let socket_inodes = (self.read_socket_inodes)();
+                let mut state = self.state.lock().unwrap();
+                state.socket_inodes = socket_inodes;
+                state.inode(local_socket)?
+            }
+        };
+        let known_process = self.process(inode);
+        if known_process.is_some() {
+            return known_process;
+        }
+        if !self.claim_refresh(|state| &mut state.last_inode_refresh, now) {
+            return None;
+        }
+        let inode_processes = (self.read_inode_processes)();
+        let mut state = self.state.lock().unwrap();
+        state.inode_processes = inode_processes;
+        state.inode_processes.get(&inode).cloned()
+    }
+    fn process(&self, inode: u64) -> Option<Process> {
+        self.state
+            .lock()
+            .unwrap()
+            .inode_processes
+            .get(&inode)
+            .cloned()
+    }
+    /// Records a refresh as done if it is due, so misses of other threads do not start another
+    /// one while it runs.
+    fn claim_refresh(
+        &self,
+        last_refresh: fn(&mut ProcessCacheState) -> &mut Option<SystemTime>,
+        now: SystemTime,
+    ) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let last_refresh = last_refresh(&mut state);
+        if !due(*last_refresh, now) {
+            return false;
+        }
+        *last_refresh = Some(now);
+        true
+    }
+}
+
+impl ProcessCacheState {
+    fn inode(&self, local_socket: &LocalSocket) -> Option<u64> {
+        candidate_ips(local_socket.ip)
+            .iter()
+            .find_map(|ip| {
+                self.socket_inodes.get(&LocalSocket {
+                    ip: *ip,
+                    port: local_socket.port,
+                    protocol: local_socket.protocol,
+                })
+            })
+            .copied()
+    }
+}
+
+/// The address itself, its IPv4-mapped form used by dual-stack sockets and the unspecified
+/// addresses of sockets bound to all addresses.
+fn candidate_ips(ip: IpAddr) -> Vec<IpAddr> {
+    match ip {
+        IpAddr::V4(ipv4) => vec![
+            ip,
+            IpAddr::V6(ipv4.to_ipv6_mapped()),
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        ],
+        IpAddr::V6(_) => vec![ip, IpAddr::V6(Ipv6Addr::UNSPECIFIED)],
+    }
+}
+
+fn due(last_refresh: Option<SystemTime>, now: SystemTime) -> bool {
+    match last_refresh {
+        None => true,
+        Some(last_refresh) => {
+            now.duration_since(last_refresh).unwrap_or_default() >= MINIMUM_REFRESH_INTERVAL
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use crate::network::Protocol;
+
+    use super::*;
+
+    static INODE_SCANS: AtomicUsize = AtomicUsize::new(0);
+
+    fn local_socket(ip: IpAddr, port: u16) -> LocalSocket {
+        LocalSocket {
+            ip,
+            port,
+            protocol: Protocol::Tcp,
+        }
+    }
+
+    fn socket_inodes() -> HashMap<LocalSocket, u64> {
+        HashMap::from([
+            (local_socket(IpAddr::from([10, 0, 0, 1]), 40000), 1),
+            (local_socket(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 443), 2),
+        ])
+    }
+
+    fn inode_processes() -> HashMap<u64, Process> {
+        INODE_SCANS.fetch_add(1, Ordering::AcqRel);
+        HashMap::from([
+            (
+                1,
+                Process {
+                    pid: 1234,
+                    name: "curl".to_string(),
+                    container: None,
+                    systemd_unit: None,
+                },
+            ),
+            (
+                2,
+                Process {
+                    pid: 99,
+                    name: "nginx".to_string(),
+                    container: None,
+                    systemd_unit: None,
+                },
+            ),
+        ])
+    }
+
+    #[test]
+    fn should_resolve_sockets_and_refresh_only_on_due_misses() {
+        // given
+        let process_cache = ProcessCache::new(socket_inodes, inode_processes);
+        let now = SystemTime::UNIX_EPOCH;
+
+        // when
+        let client = process_cache.resolve(&local_socket(IpAddr::from([10, 0, 0, 1]), 40000), now);
+        let server = process_cache.resolve(&local_socket(IpAddr::from([10, 0, 0, 1]), 443), now);
+        let unknown = process_cache.resolve(&local_socket(IpAddr::from([10, 0, 0, 1]), 8080), now);
+
+        // then
+        assert_eq!(client.unwrap().name, "curl");
+        assert_eq!(server.unwrap().pid, 99);
+        assert_eq!(unknown, None);
+        assert_eq!(INODE_SCANS.load(Ordering::Acquire), 1);
+    }
+
+    #[test]
+    fn should_refresh_expired_sockets_whose_port_was_reused() {
+        // given
+        static REUSED: AtomicBool = AtomicBool::new(false);
+        fn socket_inodes() -> HashMap<LocalSocket, u64> {
+            let inode = if REUSED.load(Ordering::Acquire) { 3 } else { 1 };
+            HashMap::from([(local_socket(IpAddr::from([10, 0, 0, 1]), 40000), inode)])
+        }
+        fn inode_processes() -> HashMap<u64, Process> {
+            let (inode, name) = if REUSED.load(Ordering::Acquire) {
+                (3, "wget")
+            } else {
+                (1, "curl")
+            };
+            HashMap::from([(
+                inode,
+                Process {
+                    pid: 4321,
+                    name: name.to_string(),
+                    container: None,
+                    systemd_unit: None,
+                },
+            )])
+        }
+        let process_cache = ProcessCache::new(socket_inodes, inode_processes);
+        let socket = local_socket(IpAddr::from([10, 0, 0, 1]), 40000);
+        let now = SystemTime::UNIX_EPOCH;
+
+        // when
+        let first = process_cache.resolve(&socket, now);
+        REUSED.store(true, Ordering::Release);
+        let second = process_cache.resolve(&socket, now + MINIMUM_REFRESH_INTERVAL);
+
+        // then
+        assert_eq!(first.unwrap().name, "curl");
+        assert_eq!(second.unwrap().name, "wget");
+    }
+}
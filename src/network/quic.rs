@@ -0,0 +1,644 @@
+use ::std::convert::TryFrom;
+use ::std::fmt;
+use ::std::time::{Duration, SystemTime};
+
+use ::ring::aead::{self, quic, Aad, LessSafeKey, Nonce, UnboundKey};
+use ::ring::hkdf;
+
+use crate::network::{Connection, Direction, ExpiringMap};
+
+const MAXIMUM_NUMBER_OF_PATHS: usize = 16384;
+const MAXIMUM_NUMBER_OF_CONNECTION_IDS: usize = 16384;
+const PATH_TIMEOUT: Duration = Duration::from_secs(300);
+const MAXIMUM_CONNECTION_ID_LENGTH: usize = 20;
+const LONG_HEADER: u8 = 0x80;
+const FIXED_BIT: u8 = 0x40;
+const VERSION_1: u32 = 0x0000_0001;
+const VERSION_2: u32 = 0x6b33_43cf;
+/// RFC 9001, section 5.2
+const VERSION_1_INITIAL_SALT: [u8; 20] = [
+    0x38, 0x76, 0x2c, 0xf7, 0xf5, 0x59, 0x34, 0xb3, 0x4d, 0x17, 0x9a, 0xe6, 0xa4, 0xc8, 0x0c, 0xad,
+    0xcc, 0xbb, 0x7f, 0x0a,
+];
+/// RFC 9369, section 3.3.1
+const VERSION_2_INITIAL_SALT: [u8; 20] = [
+    0x0d, 0xed, 0xe3, 0xde, 0xf7, 0x00, 0xa6, 0xdb, 0x81, 0x93, 0x81, 0xbe, 0x6e, 0x26, 0x9d, 0xcb,
+    0xf9, 0xbd, 0x2e, 0xd9,
+];
+const SAMPLE_OFFSET: usize = 4;
+const SAMPLE_LENGTH: usize = 16;
+const FRAME_PADDING: u64 = 0x00;
+const FRAME_PING: u64 = 0x01;
+const FRAME_ACK: u64 = 0x02;
+const FRAME_ACK_ECN: u64 = 0x03;
+const FRAME_CRYPTO: u64 = 0x06;
+
+/// A QUIC connection ID of up to 20 bytes.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConnectionId {
+    length: u8,
+    bytes: [u8; MAXIMUM_CONNECTION_ID_LENGTH],
+}
+
+impl ConnectionId {
+    pub fn new(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() > MAXIMUM_CONNECTION_ID_LENGTH {
+            return None;
+        }
+        let mut connection_id = ConnectionId {
+            length: bytes.len() as u8,
+            bytes: [0; MAXIMUM_CONNECTION_ID_LENGTH],
+        };
+        connection_id.bytes[..bytes.len()].copy_from_slice(bytes);
+        Some(connection_id)
+    }
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..usize::from(self.length)]
+    }
+}
+
+impl fmt::Debug for ConnectionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_bytes()
+            .iter()
+            .try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+/// What a QUIC packet revealed about its connection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QuicSample {
+    /// Only known from long header packets.
+    pub version: Option<u32>,
+    /// The connection ID chosen by the server, shared by all paths of a connection that keep
+    /// using it after migrating.
+    pub connection_id: Option<ConnectionId>,
+}
+
+impl QuicSample {
+    pub fn merge(&mut self, other: &QuicSample) {
+        self.version = other.version.or(self.version);
+        self.connection_id = other.connection_id.or(self.connection_id);
+    }
+}
+
+/// A QUIC datagram along with the CRYPTO frames of a client Initial packet, if decryptable.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct QuicObservation {
+    pub sample: QuicSample,
+    /// Offsets and data of the CRYPTO frames, ordered by offset.
+    pub crypto_frames: Vec<(u64, Vec<u8>)>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LongPacketType {
+    Initial,
+    ZeroRtt,
+    Handshake,
+    Retry,
+}
+
+struct LongHeader<'a> {
+    version: u32,
+    packet_type: LongPacketType,
+    destination_connection_id: &'a [u8],
+    source_connection_id: &'a [u8],
+    /// Offset of the packet number of Initial packets.
+    packet_number_offset: Option<usize>,
+    /// Length of the packet number and the payload of Initial packets.
+    length: Option<usize>,
+}
+
+struct QuicPath {
+    /// The direction of the first Initial packet, sent by the client.
+    client: Option<Direction>,
+    version: Option<u32>,
+    connection_id: Option<ConnectionId>,
+}
+
+/// Bounded table of QUIC paths, recognizing connections by the version of long header packets
+/// and following them to new paths by the connection ID of short header packets.
+///
+/// Short header packets do not carry the length of their connection ID, so only IDs learned
+/// from long header packets are recognized. Migrating clients switching to a connection ID
+/// announced in encrypted frames start a new connection from the observer's point of view.
+pub struct QuicTracker {
+    paths: ExpiringMap<Connection, QuicPath>,
+    connection_ids: ExpiringMap<ConnectionId, ()>,
+    /// Bit `n` is set if a known connection ID has a length of `n` bytes.
+    connection_id_lengths: u32,
+}
+
+impl QuicTracker {
+    pub fn new() -> Self {
+        QuicTracker {
+            paths: ExpiringMap::new(MAXIMUM_NUMBER_OF_PATHS, PATH_TIMEOUT),
+            connection_ids: ExpiringMap::new(MAXIMUM_NUMBER_OF_CONNECTION_IDS, PATH_TIMEOUT),
+            connection_id_lengths: 0,
+        }
+    }
+    /// Inspects the payload of a UDP datagram, returning `None` if it is not QUIC.
+    pub fn observe(
+        &mut self,
+        connection: &Connection,
+        direction: &Direction,
+        datagram: &[u8],
+        timestamp: SystemTime,
+    ) -> Option<QuicObservation> {
+        self.maintain(timestamp);
+        let first = *datagram.first()?;
+        if first & LONG_HEADER != 0 {
+            self.observe_long_header(connection, direction, datagram, timestamp)
+        } else if first & FIXED_BIT != 0 {
+            self.observe_short_header(connection, datagram, timestamp)
+        } else {
+            None
+        }
+    }
+    fn observe_long_header(
+        &mut self,
+        connection: &Connection,
+        direction: &Direction,
+        datagram: &[u8],
+        timestamp: SystemTime,
+    ) -> Option<QuicObservation> {
+        let header = LongHeader::parse(datagram)?;
+        let mut observation = QuicObservation {
+            sample: QuicSample {
+                version: Some(header.version),
+                connection_id: None,
+            },
+            crypto_frames: vec![],
+        };
+        let path = match self
+            .paths
+            .get_or_insert_with(*connection, timestamp, || QuicPath {
+                client: None,
+                version: None,
+                connection_id: None,
+            }) {
+            Some(path) => path,
+            None => return Some(observation),
+        };
+        path.version = Some(header.version);
+        if header.packet_type == LongPacketType::Initial && path.client.is_none() {
+            path.client = Some(direction.clone());
+        }
+        let sent_by_client = path.client.as_ref() == Some(direction);
+        if path.client.is_some() && !sent_by_client && header.packet_type != LongPacketType::Retry {
+            path.connection_id = ConnectionId::new(header.source_connection_id);
+        }
+        observation.sample.connection_id = path.connection_id;
+        if let Some(connection_id) = path.connection_id {
+            self.remember(connection_id, timestamp);
+        }
+        if sent_by_client && header.packet_type == LongPacketType::Initial {
+            observation.crypto_frames = decrypt_client_initial(&header, datagram)
+                .map(|payload| crypto_frames(&payload))
+                .unwrap_or_default();
+        }
+        Some(observation)
+    }
+    fn observe_short_header(
+        &mut self,
+        connection: &Connection,
+        datagram: &[u8],
+        timestamp: SystemTime,
+    ) -> Option<QuicObservation> {
+        if let Some(path) = self.paths.get_mut(connection, timestamp) {
+            if let Some(connection_id) = path.connection_id {
+                self.connection_ids.get_mut(&connection_id, timestamp);
+            }
+            return Some(QuicObservation {
+                sample: QuicSample {
+                    version: path.version,
+                    connection_id: path.connection_id,
+                },
+                crypto_frames: vec![],
+            });
+        }
+        if self.paths.is_full() {
+            return None;
+        }
+        let connection_id = (1..=MAXIMUM_CONNECTION_ID_LENGTH)
+            .filter(|length| self.connection_id_lengths & 1 << length != 0)
+            .filter_map(|length| ConnectionId::new(datagram.get(1..1 + length)?))
+            .find(|candidate| self.connection_ids.contains_key(candidate))?;
+        self.connection_ids.get_mut(&connection_id, timestamp);
+        self.paths.insert(
+            *connection,
+            QuicPath {
+                client: None,
+                version: None,
+                connection_id: Some(connection_id),
+            },
+            timestamp,
+        );
+        Some(QuicObservation {
+            sample: QuicSample {
+                version: None,
+                connection_id: Some(connection_id),
+            },
+            crypto_frames: vec![],
+        })
+    }
+    fn remember(&mut self, connection_id: ConnectionId, timestamp: SystemTime) {
+        if connection_id.length == 0 {
+            return;
+        }
+        if self.connection_ids.insert(connection_id, (), timestamp) {
+            self.connection_id_lengths |= 1 << connection_id.length;
+        }
+    }
+    /// Forgets paths and connection IDs not seen within the timeout.
+    pub fn maintain(&mut self, now: SystemTime) {
+        self.paths.expire(now, drop);
+        if !self.connection_ids.expire(now, drop) {
+            return;
+        }
+        self.connection_id_lengths = self
+            .connection_ids
+            .keys()
+            .fold(0, |lengths, connection_id| {
+                lengths | 1 << connection_id.length
+            });
+    }
+}
+
+impl<'a> LongHeader<'a> {
+    /// Parses the long header of QUIC version 1 (RFC 9000) and version 2 (RFC 9369) packets.
+    fn parse(packet: &'a [u8]) -> Option<Self> {
+        let first = *packet.first()?;
+        let version = u32::from_be_bytes([
+            *packet.get(1)?,
+            *packet.get(2)?,
+            *packet.get(3)?,
+            *packet.get(4)?,
+        ]);
+        let type_bits = (first >> 4) & 0x03;
+        let packet_type = match (version, type_bits) {
+            (VERSION_1, 0) | (VERSION_2, 1) => LongPacketType::Initial,
+            (VERSION_1, 1) | (VERSION_2, 2) => LongPacketType::ZeroRtt,
+            (VERSION_1, 2) | (VERSION_2, 3) => LongPacketType::Handshake,
+            (VERSION_1, 3) | (VERSION_2, 0) => LongPacketType::Retry,
+            _ => return None,
+        };
+        let mut offset = 5;
+        let destination_connection_id = connection_id_at(packet, &mut offset)?;
+        let source_connection_id = connection_id_at(packet, &mut offset)?;
+        let (packet_number_offset, length) = if packet_type == LongPacketType::Initial {
+            let token_length = variable_length_integer(packet, &mut offset)?;
+            offset = offset.checked_add(usize::try_from(token_length).ok()?)?;
+            let length = usize::try_from(variable_length_integer(packet, &mut offset)?).ok()?;
+            (Some(offset), Some(length))
+        } else {
+            (None, None)
+        };
+        Some(LongHeader {
+            version,
+            packet_type,
+            destination_connection_id,
+            source_connection_id,
+            packet_number_offset,
+            length,
+        })
+    }
+}
+
+fn connection_id_at<'a>(packet: &'a [u8], offset: &mut usize) -> Option<&'a [u8]> {
+    let length = usize::from(*packet.get(*offset)?);
+    if length > MAXIMUM_CONNECTION_ID_LENGTH {
+        return None;
+    }
+    let connection_id = packet.get(*offset + 1..*offset + 1 + length)?;
+    *offset += 1 + length;
+    Some(connection_id)
+}
+
+/// RFC 9000, section 16
+fn variable_length_integer(bytes: &[u8], offset: &mut usize) -> Option<u64> {
+    let first = *bytes.get(*offset)?;
+    let length = 1 << (first >> 6);
+    let encoded = bytes.get(*offset..*offset + length)?;
+    *offset += length;
+    Some(
+        encoded[1..]
+            .iter()
+            .fold(u64::from(first & 0x3f), |value, byte| {
+                value << 8 | u64::from(*byte)
+            }),
+    )
+}
+
+struct Length(usize);
+
+impl hkdf::KeyType for Length {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+/// RFC 8446, section 7.1, without context.
+fn hkdf_expand_label(secret: &hkdf::Prk, label: &[u8], output: &mut [u8]) -> Option<()> {
+    let output_length = (output.len() as u16).to_be_bytes();
+    let label_length = [(b"tls13 ".len() + label.len()) as u8];
+    let info = [&output_length[..], &label_length, b"tls13 ", label, &[0]];
+    secret
+        .expand(&info, Length(output.len()))
+        .ok()?
+        .fill(output)
+        .ok()
+}
+
+/// The packet protection key, IV and header protection key of client Initial packets.
+fn client_initial_key_material(
+    version: u32,
+    destination_connection_id: &[u8],
+) -> Option<([u8; 16], [u8; 12], [u8; 16])> {
+    let (salt, labels): (&[u8], [&[u8]; 3]) = match version {
+        VERSION_1 => (
+            &VERSION_1_INITIAL_SALT,
+            [b"quic key", b"quic iv", b"quic hp"],
+        ),
+        VERSION_2 => (
+            &VERSION_2_INITIAL_SALT,
+            [b"quicv2 key", b"quicv2 iv", b"quicv2 hp"],
+        ),
+        _ => return None,
+    };
+    let initial_secret =
+        hkdf::Salt::new(hkdf::HKDF_SHA256, salt).extract(destination_connection_id);
+    let mut client_secret = [0; 32];
+    hkdf_expand_label(&initial_secret, b"client in", &mut client_secret)?;
+    let client_secret = hkdf::Prk::new_less_safe(hkdf::HKDF_SHA256, &client_secret);
+    let mut key = [0; 16];
+    let mut iv = [0; 12];
+    let mut header_protection_key = [0; 16];
+    hkdf_expand_label(&client_secret, labels[0], &mut key)?;
+    hkdf_expand_label(&client_secret, labels[1], &mut iv)?;
+    hkdf_expand_label(&client_secret, labels[2], &mut header_protection_key)?;
+    Some((key, iv, header_protection_key))
+}
+
+/// Removes the header and packet protection of a client Initial packet, RFC 9001 section 5.
+fn decrypt_client_initial(header: &LongHeader, packet: &[u8]) -> Option<Vec<u8>> {
+    let packet_number_offset = header.packet_number_offset?;
+    let end = packet_number_offset.checked_add(header.length?)?;
+    let mut packet = packet.get(..end)?.to_vec();
+    let (key, iv, header_protection_key) =
+        client_initial_key_material(header.version, header.destination_connection_id)?;
+    let sample_offset = packet_number_offset + SAMPLE_OFFSET;
+    let sample = packet.get(sample_offset..sample_offset + SAMPLE_LENGTH)?;
+    let mask = quic::HeaderProtectionKey::new(&quic::AES_128, &header_protection_key)
+        .ok()?
+        .new_mask(sample)
+        .ok()?;
+    packet[0] ^= mask[0] & 0x0f;
+    let packet_number_length = usize::from(packet[0] & 0x03) + 1;
+    let mut packet_number = 0u64;
+    for index in 0..packet_number_length {
+        packet[packet_number_offset + index] ^= mask[1 + index];
+        packet_number = packet_number << 8 | u64::from(packet[packet_number_offset + index]);
+    }
+    let mut nonce = iv;
+    for (byte, packet_number_byte) in nonce[4..].iter_mut().zip(packet_number.to_be_bytes()) {
+        *byte ^= packet_number_byte;
+    }
+    let (header, payload) = packet.split_at_mut(packet_number_offset + packet_number_length);
+    let key = LessSafeKey::new(UnboundKey::new(&aead::AES_128_GCM, &key).ok()?);
+    let plaintext = key
+        .open_in_place(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(&header[..]),
+            payload,
+        )
+        .ok()?;
+    Some(plaintext.to_vec())
+}
+
+/// Collects the CRYPTO frames of a decrypted Initial packet, stopping at unexpected frames.
+fn crypto_frames(payload: &[u8]) -> Vec<(u64, Vec<u8>)> {
+    let mut frames = vec![];
+    let mut offset = 0;
+    while offset < payload.len() {
+        let frame_type = match variable_length_integer(payload, &mut offset) {
+            Some(frame_type) => frame_type,
+            None => break,
+        };
+        let parsed = match frame_type {
+            FRAME_PADDING | FRAME_PING => Some(()),
+            FRAME_ACK | FRAME_ACK_ECN => skip_ack(payload, &mut offset, frame_type),
+            FRAME_CRYPTO => crypto_frame(payload, &mut offset).map(|frame| frames.push(frame)),
+            _ => None,
+        };
+        if parsed.is_none() {
+            break;
+        }
+    }
+    frames.sort_by_key(|(offset, _)| *offset);
+    frames
+}
+
+fn skip_ack(payload: &[u8], offset: &mut usize, frame_type: u64) -> Option<()> {
+    variable_length_integer(payload, offset)?;
+    variable_length_integer(payload, offset)?;
+    let ranges = variable_length_integer(payload, offset)?;
+    variable_length_integer(payload, offset)?;
+    for _ in 0..ranges {
+        variable_length_integer(payload, offset)?;
+        variable_length_integer(payload, offset)?;
+    }
+    if frame_type == FRAME_ACK_ECN {
+        for _ in 0..3 {
+            variable_length_integer(payload, offset)?;
+        }
+    }
+    Some(())
+}
+
+fn crypto_frame(payload: &[u8], offset: &mut usize) -> Option<(u64, Vec<u8>)> {
+    let crypto_offset = variable_length_integer(payload, offset)?;
+    let length = usize::try_from(variable_length_integer(payload, offset)?).ok()?;
+    let data = payload.get(*offset..offset.checked_add(length)?)?;
+    *offset += length;
+    Some((crypto_offset, data.to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    use crate::network::Protocol;
+
+    use crate::network::fixtures::at;
+
+    use super::*;
+
+    /// The original destination connection ID of RFC 9001, appendix A.
+    const DESTINATION_CONNECTION_ID: [u8; 8] = [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
+
+    fn path(local_port: u16) -> Connection {
+        Connection::new(
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34)), 443),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            local_port,
+            Protocol::Udp,
+        )
+    }
+
+    /// A version 1 client Initial packet with a two byte packet number, protected like a client
+    /// would.
+    fn client_initial(frames: &[u8]) -> Vec<u8> {
+        let (key, iv, header_protection_key) =
+            client_initial_key_material(VERSION_1, &DESTINATION_CONNECTION_ID).unwrap();
+        let packet_number = [0, 2];
+        let mut payload = frames.to_vec();
+        payload.resize(1162, 0);
+        let mut packet = vec![0xc1, 0, 0, 0, 1, 8];
+        packet.extend_from_slice(&DESTINATION_CONNECTION_ID);
+        packet.extend_from_slice(&[0, 0]);
+        let length = (packet_number.len() + payload.len() + 16) as u16 | 0x4000;
+        packet.extend_from_slice(&length.to_be_bytes());
+        let packet_number_offset = packet.len();
+        packet.extend_from_slice(&packet_number);
+        let mut nonce = iv;
+        nonce[11] ^= packet_number[1];
+        LessSafeKey::new(UnboundKey::new(&aead::AES_128_GCM, &key).unwrap())
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(packet.clone()),
+                &mut payload,
+            )
+            .unwrap();
+        packet.extend_from_slice(&payload);
+        let sample_offset = packet_number_offset + SAMPLE_OFFSET;
+        let mask = quic::HeaderProtectionKey::new(&quic::AES_128, &header_protection_key)
+            .unwrap()
+            .new_mask(&packet[sample_offset..sample_offset + SAMPLE_LENGTH])
+            .unwrap();
+        packet[0] ^= mask[0] & 0x0f;
+        packet[packet_number_offset] ^= mask[1];
+        packet[packet_number_offset + 1] ^= mask[2];
+        packet
+    }
+
+    /// A version 1 server Initial packet choosing the given connection ID, with an opaque
+    /// payload.
+    fn server_initial(connection_id: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0xc1, 0, 0, 0, 1, 0, connection_id.len() as u8];
+        packet.extend_from_slice(connection_id);
+        packet.extend_from_slice(&[0, 0x40, 0x20]);
+        packet.extend_from_slice(&[0; 32]);
+        packet
+    }
+
+    #[test]
+    fn should_derive_client_initial_keys_of_rfc_9001() {
+        // when
+        let (key, iv, header_protection_key) =
+            client_initial_key_material(VERSION_1, &DESTINATION_CONNECTION_ID).unwrap();
+
+        // then
+        assert_eq!(
+            key,
+            [
+                0x1f, 0x36, 0x96, 0x13, 0xdd, 0x76, 0xd5, 0x46, 0x77, 0x30, 0xef, 0xcb, 0xe3, 0xb1,
+                0xa2, 0x2d
+            ]
+        );
+        assert_eq!(
+            iv,
+            [0xfa, 0x04, 0x4b, 0x2f, 0x42, 0xa3, 0xfd, 0x3b, 0x46, 0xfb, 0x25, 0x5c]
+        );
+        assert_eq!(
+            header_protection_key,
+            [
+                0x9f, 0x50, 0x44, 0x9e, 0x04, 0xa0, 0xe8, 0x10, 0x28, 0x3a, 0x1e, 0x99, 0x33, 0xad,
+                0xed, 0xd2
+            ]
+        );
+    }
+
+    #[test]
+    fn should_decrypt_crypto_frames_of_client_initial() {
+        // given
+        let mut tracker = QuicTracker::new();
+        let frames = [
+            &[0x01, 0x06, 0x03, 0x03][..],
+            b"llo",
+            &[0x00, 0x00, 0x06, 0x00, 0x03],
+            b"hel",
+        ]
+        .concat();
+
+        // when
+        let observation = tracker
+            .observe(
+                &path(40000),
+                &Direction::Upload,
+                &client_initial(&frames),
+                at(100),
+            )
+            .unwrap();
+
+        // then
+        assert_eq!(observation.sample.version, Some(VERSION_1));
+        assert_eq!(
+            observation.crypto_frames,
+            vec![(0, b"hel".to_vec()), (3, b"llo".to_vec())]
+        );
+    }
+
+    #[test]
+    fn should_follow_server_connection_id_to_migrated_paths() {
+        // given
+        let mut tracker = QuicTracker::new();
+        let server_connection_id = [0xc0, 0xff, 0xee, 0x01];
+        tracker.observe(
+            &path(40000),
+            &Direction::Upload,
+            &client_initial(&[]),
+            at(100),
+        );
+        tracker.observe(
+            &path(40000),
+            &Direction::Download,
+            &server_initial(&server_connection_id),
+            at(100),
+        );
+        let short_header = [&[0x41][..], &server_connection_id, &[0; 24]].concat();
+
+        // when
+        let migrated = tracker
+            .observe(&path(40001), &Direction::Upload, &short_header, at(110))
+            .unwrap();
+        let unrelated = tracker.observe(&path(40002), &Direction::Upload, &[0x41; 29], at(110));
+
+        // then
+        assert_eq!(
+            migrated.sample.connection_id.map(|id| id.to_string()),
+            Some("c0ffee01".to_string())
+        );
+        assert_eq!(unrelated, None);
+    }
+
+    #[test]
+    fn should_ignore_unknown_versions() {
+        // given
+        let mut tracker = QuicTracker::new();
+        let mut packet = server_initial(&[1, 2, 3, 4]);
+        packet[4] = 0x02;
+
+        // then
+        assert_eq!(
+            tracker.observe(&path(40000), &Direction::Download, &packet, at(100)),
+            None
+        );
+    }
+}
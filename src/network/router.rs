@@ -0,0 +1,413 @@
+use ::std::collections::hash_map::DefaultHasher;
+use ::std::collections::HashMap;
+use ::std::hash::{Hash, Hasher};
+use ::std::net::IpAddr;
+use ::std::sync::{Arc, Mutex};
+use ::std::time::{Duration, SystemTime};
+
+use crate::network::{ExpiringMap, IcmpKind, Protocol, TransitFlow};
+
+const TCP_SEQUENCE_OFFSET: usize = 4;
+const TCP_CHECKSUM_OFFSET: usize = 16;
+const TCP_MINIMUM_HEADER_LENGTH: usize = 20;
+const UDP_LENGTH_OFFSET: usize = 4;
+const UDP_CHECKSUM_OFFSET: usize = 6;
+const UDP_HEADER_LENGTH: usize = 8;
+const ICMP_SEQUENCE_OFFSET: usize = 6;
+const ICMP_HEADER_LENGTH: usize = 8;
+/// Payload bytes hashed into fingerprints, enough to tell apart packets of the same flow.
+const FINGERPRINT_PAYLOAD_LENGTH: usize = 64;
+/// How long a packet may take from one interface to the other.
+const MAXIMUM_FORWARDING_DELAY: Duration = Duration::from_secs(1);
+/// How long a translation is remembered after its last forwarded packet.
+const TRANSLATION_TIMEOUT: Duration = Duration::from_secs(300);
+const MAXIMUM_NUMBER_OF_SIGHTINGS: usize = 65536;
+const NUMBER_OF_SIGHTING_SHARDS: usize = 16;
+const MAXIMUM_NUMBER_OF_TRANSLATIONS: usize = 65536;
+
+/// Hashes the parts of a transport layer packet which routers leave untouched, which excludes
+/// the ports and checksums rewritten by NAT.
+///
+/// `None` for packets which can not be told apart reliably, like fragments or ICMP errors.
+pub fn packet_fingerprint(protocol: Protocol, transport: &[u8]) -> Option<u64> {
+    let mut hasher = DefaultHasher::new();
+    transport.len().hash(&mut hasher);
+    let payload = match protocol {
+        Protocol::Tcp => {
+            let header_length = usize::from(*transport.get(12)? >> 4) * 4;
+            if header_length < TCP_MINIMUM_HEADER_LENGTH {
+                return None;
+            }
+            transport
+                .get(TCP_SEQUENCE_OFFSET..TCP_CHECKSUM_OFFSET)?
+                .hash(&mut hasher);
+            transport.get(header_length..)?
+        }
+        Protocol::Udp => {
+            transport
+                .get(UDP_LENGTH_OFFSET..UDP_CHECKSUM_OFFSET)?
+                .hash(&mut hasher);
+            transport.get(UDP_HEADER_LENGTH..)?
+        }
+        Protocol::Icmp(IcmpKind::Echo { .. }) | Protocol::Icmpv6(IcmpKind::Echo { .. }) => {
+            // the identifier may be rewritten by NAT
+            transport.get(..2)?.hash(&mut hasher);
+            transport
+                .get(ICMP_SEQUENCE_OFFSET..ICMP_HEADER_LENGTH)?
+                .hash(&mut hasher);
+            transport.get(ICMP_HEADER_LENGTH..)?
+        }
+        _ => return None,
+    };
+    payload[..payload.len().min(FINGERPRINT_PAYLOAD_LENGTH)].hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+/// A packet forwarded from one interface to another, by the flows of both legs which differ if
+/// the router translated addresses or ports.
+#[derive(PartialEq, Hash, Eq, Clone, PartialOrd, Ord, Debug)]
+pub struct ForwardedFlow {
+    pub ingress_interface_name: String,
+    pub egress_interface_name: String,
+    pub ingress: TransitFlow,
+    pub egress: TransitFlow,
+}
+
+/// Forwarded traffic seen on one interface only, dropped by the router or leaving it through an
+/// interface not sniffed in router mode, and forwarded packets without fingerprint.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UnmatchedForwarding {
+    pub packets: u128,
+    pub bytes: u128,
+}
+
+impl UnmatchedForwarding {
+    pub fn is_empty(&self) -> bool {
+        self.packets == 0
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ForwardingObservation {
+    /// Traffic of the router itself, reported as a connection.
+    Local,
+    /// Waiting for the packet to be seen on another interface.
+    Pending,
+    /// Seen on both interfaces, reported once.
+    Forwarded(ForwardedFlow),
+}
+
+/// Correlates the legs of packets forwarded between the interfaces of a router.
+///
+/// Clones share the same state, so sniffers of all router interfaces can see each other's
+/// packets. Legs are matched by packet fingerprints, as addresses and ports change with NAT.
+/// Legs translated to an address of the router look like its own traffic until they have been
+/// matched once, which needs the other leg to be seen first, so the first packets of a
+/// translated flow may also be reported as connections.
+#[derive(Clone)]
+pub struct Forwarding {
+    local_ips: Arc<Vec<IpAddr>>,
+    /// Sightings locked per shard of fingerprints, so the sniffers of the router interfaces
+    /// rarely wait for each other.
+    sightings: Arc<Vec<Mutex<ExpiringMap<u64, Sighting>>>>,
+    /// Flows of legs translated to addresses of the router, in both directions.
+    translations: Arc<Mutex<ExpiringMap<TransitFlow, ()>>>,
+    unmatched: Arc<Mutex<HashMap<String, UnmatchedForwarding>>>,
+}
+
+/// A packet not involving the router itself waiting for its other leg.
+struct Sighting {
+    interface_name: String,
+    flow: TransitFlow,
+    timestamp: SystemTime,
+    data_length: u128,
+    /// Whether the flow involves an address of the router.
+    local: bool,
+}
+
+impl Forwarding {
+    /// Creates the state shared by the router interfaces, given all addresses of the router.
+    pub fn new(local_ips: Vec<IpAddr>) -> Self {
+        Forwarding {
+            local_ips: Arc::new(local_ips),
+            sightings: Arc::new(
+                (0..NUMBER_OF_SIGHTING_SHARDS)
+                    .map(|_| {
+                        Mutex::new(ExpiringMap::new(
+                            MAXIMUM_NUMBER_OF_SIGHTINGS / NUMBER_OF_SIGHTING_SHARDS,
+                            MAXIMUM_FORWARDING_DELAY,
+                        ))
+                    })
+                    .collect(),
+            ),
+            translations: Arc::new(Mutex::new(ExpiringMap::new(
+                MAXIMUM_NUMBER_OF_TRANSLATIONS,
+                TRANSLATION_TIMEOUT,
+            ))),
+            unmatched: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+    pub fn observe(
+        &self,
+        interface_name: &str,
+        flow: TransitFlow,
+        fingerprint: Option<u64>,
+        data_length: u128,
+        timestamp: SystemTime,
+    ) -> ForwardingObservation {
+        let local = self.local_ips.contains(&flow.source.ip)
+            || self.local_ips.contains(&flow.destination.ip);
+        let own = local && !self.is_translated(&flow, timestamp);
+        let fingerprint = match fingerprint {
+            Some(fingerprint) => fingerprint,
+            None if own => return ForwardingObservation::Local,
+            None => {
+                self.unmatched_on(interface_name, data_length);
+                return ForwardingObservation::Pending;
+            }
+        };
+        let shard = (fingerprint % NUMBER_OF_SIGHTING_SHARDS as u64) as usize;
+        let mut sightings = self.sightings[shard].lock().unwrap();
+        self.expire_sightings(&mut sightings, timestamp);
+        match sightings.remove(&fingerprint) {
+            Some(earlier)
+                if earlier.interface_name != interface_name
+                    && timestamp
+                        .duration_since(earlier.timestamp)
+                        .unwrap_or_default()
+                        <= MAXIMUM_FORWARDING_DELAY =>
+            {
+                drop(sightings);
+                if earlier.local {
+                    self.translate(earlier.flow, timestamp);
+                }
+                if local {
+                    self.translate(flow, timestamp);
+                }
+                // sniffers may process the legs in a different order than they were captured
+                let this = (interface_name.to_string(), flow);
+                let other = (earlier.interface_name, earlier.flow);
+                let ((ingress_interface_name, ingress), (egress_interface_name, egress)) =
+                    if timestamp < earlier.timestamp {
+                        (this, other)
+                    } else {
+                        (other, this)
+                    };
+                ForwardingObservation::Forwarded(ForwardedFlow {
+                    ingress_interface_name,
+                    egress_interface_name,
+                    ingress,
+                    egress,
+                })
+            }
+            earlier => {
+                if let Some(earlier) = earlier {
+                    self.unmatched_on(&earlier.interface_name, earlier.data_length);
+                }
+                // traffic of the router itself would only push forwarded packets out
+                if own {
+                    return ForwardingObservation::Local;
+                }
+                let inserted = sightings.insert(
+                    fingerprint,
+                    Sighting {
+                        interface_name: interface_name.to_string(),
+                        flow,
+                        timestamp,
+                        data_length,
+                        local,
+                    },
+                    timestamp,
+                );
+                if !inserted {
+                    self.unmatched_on(interface_name, data_length);
+                }
+                ForwardingObservation::Pending
+            }
+        }
+    }
+    /// Returns the forwarded traffic of the interface not matched since the last call.
+    pub fn take_unmatched(&self, interface_name: &str) -> UnmatchedForwarding {
+        self.unmatched
+            .lock()
+            .unwrap()
+            .remove(interface_name)
+            .unwrap_or_default()
+    }
+    pub fn maintain(&self, now: SystemTime) {
+        for sightings in self.sightings.iter() {
+            self.expire_sightings(&mut sightings.lock().unwrap(), now);
+        }
+        self.translations.lock().unwrap().expire(now, drop);
+    }
+    fn expire_sightings(&self, sightings: &mut ExpiringMap<u64, Sighting>, now: SystemTime) {
+        sightings.expire(now, |sighting| {
+            self.unmatched_on(&sighting.interface_name, sighting.data_length)
+        });
+    }
+    fn unmatched_on(&self, interface_name: &str, data_length: u128) {
+        let mut unmatched = self.unmatched.lock().unwrap();
+        let unmatched = unmatched.entry(interface_name.to_string()).or_default();
+        unmatched.packets += 1;
+        unmatched.bytes += data_length;
+    }
+    fn is_translated(&self, flow: &TransitFlow, now: SystemTime) -> bool {
+        let mut translations = self.translations.lock().unwrap();
+        translations.expire(now, drop);
+        translations.contains_key(flow)
+    }
+    fn translate(&self, flow: TransitFlow, timestamp: SystemTime) {
+        let reversed = TransitFlow {
+            source: flow.destination,
+            destination: flow.source,
+            protocol: flow.protocol,
+        };
+        let mut translations = self.translations.lock().unwrap();
+        for flow in [flow, reversed] {
+            translations.insert(flow, (), timestamp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::std::net::{Ipv4Addr, SocketAddr};
+
+    use crate::network::fixtures::{at_millis, tcp};
+
+    use super::*;
+
+    const ROUTER: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 1);
+    const CLIENT: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 10);
+    const SERVER: Ipv4Addr = Ipv4Addr::new(93, 184, 216, 34);
+
+    fn flow(source: (Ipv4Addr, u16), destination: (Ipv4Addr, u16)) -> TransitFlow {
+        TransitFlow::new(
+            SocketAddr::new(source.0.into(), source.1),
+            SocketAddr::new(destination.0.into(), destination.1),
+            Protocol::Tcp,
+        )
+    }
+
+    #[test]
+    fn should_fingerprint_packets_regardless_of_ports_and_checksum() {
+        // given
+        let original = tcp(40000, 443, 1, 1, 0x18, b"GET / HTTP/1.1");
+        let mut translated = tcp(61000, 443, 1, 1, 0x18, b"GET / HTTP/1.1");
+        translated[16] = 0xab;
+
+        // when
+        let original = packet_fingerprint(Protocol::Tcp, &original);
+        let translated = packet_fingerprint(Protocol::Tcp, &translated);
+        let other = packet_fingerprint(Protocol::Tcp, &tcp(40000, 443, 15, 1, 0x18, b"Host"));
+
+        // then
+        assert!(original.is_some());
+        assert_eq!(original, translated);
+        assert_ne!(original, other);
+    }
+
+    #[test]
+    fn should_correlate_legs_of_translated_flows() {
+        // given
+        let forwarding = Forwarding::new(vec![ROUTER.into()]);
+        let lan = flow((CLIENT, 40000), (SERVER, 443));
+        let wan = flow((ROUTER, 61000), (SERVER, 443));
+
+        // when
+        let ingress = forwarding.observe("lan0", lan, Some(1), 60, at_millis(0));
+        let egress = forwarding.observe("wan0", wan, Some(1), 60, at_millis(1));
+        let reply_ingress = forwarding.observe(
+            "wan0",
+            flow((SERVER, 443), (ROUTER, 61000)),
+            Some(2),
+            60,
+            at_millis(20),
+        );
+        let reply_egress = forwarding.observe(
+            "lan0",
+            flow((SERVER, 443), (CLIENT, 40000)),
+            Some(2),
+            60,
+            at_millis(21),
+        );
+
+        // then
+        assert_eq!(ingress, ForwardingObservation::Pending);
+        assert_eq!(
+            egress,
+            ForwardingObservation::Forwarded(ForwardedFlow {
+                ingress_interface_name: "lan0".to_string(),
+                egress_interface_name: "wan0".to_string(),
+                ingress: lan,
+                egress: wan,
+            })
+        );
+        assert_eq!(reply_ingress, ForwardingObservation::Pending);
+        assert_eq!(
+            reply_egress,
+            ForwardingObservation::Forwarded(ForwardedFlow {
+                ingress_interface_name: "wan0".to_string(),
+                egress_interface_name: "lan0".to_string(),
+                ingress: flow((SERVER, 443), (ROUTER, 61000)),
+                egress: flow((SERVER, 443), (CLIENT, 40000)),
+            })
+        );
+    }
+
+    #[test]
+    fn should_count_forwarded_packets_without_other_leg_as_unmatched() {
+        // given
+        let forwarding = Forwarding::new(vec![ROUTER.into()]);
+        forwarding.observe(
+            "lan0",
+            flow((CLIENT, 40000), (SERVER, 443)),
+            Some(1),
+            60,
+            at_millis(0),
+        );
+        let own = forwarding.observe(
+            "wan0",
+            flow((ROUTER, 50000), (SERVER, 443)),
+            Some(2),
+            60,
+            at_millis(0),
+        );
+
+        // when
+        forwarding.maintain(at_millis(5000));
+
+        // then
+        assert_eq!(own, ForwardingObservation::Local);
+        assert_eq!(
+            forwarding.take_unmatched("lan0"),
+            UnmatchedForwarding {
+                packets: 1,
+                bytes: 60
+            }
+        );
+        assert!(forwarding.take_unmatched("wan0").is_empty());
+    }
+
+    #[test]
+    fn should_not_keep_sightings_of_own_traffic() {
+        // given
+        let forwarding = Forwarding::new(vec![ROUTER.into()]);
+
+        // when
+        let own = forwarding.observe(
+            "wan0",
+            flow((ROUTER, 50000), (SERVER, 443)),
+            Some(2),
+            60,
+            at_millis(0),
+        );
+
+        // then
+        assert_eq!(own, ForwardingObservation::Local);
+        assert!(forwarding
+            .sightings
+            .iter()
+            .all(|sightings| sightings.lock().unwrap().len() == 0));
+    }
+}
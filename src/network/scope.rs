@@ -0,0 +1,136 @@
+use ::std::fmt;
+use ::std::net::IpAddr;
+use ::std::str::FromStr;
+
+use ::ipnetwork::IpNetwork;
+
+const IPV6_LINK_LOCAL_MASK: u16 = 0xffc0;
+const IPV6_LINK_LOCAL_PREFIX: u16 = 0xfe80;
+
+/// Who a packet is exchanged with, derived from its destination address if that addresses a group
+/// of hosts and from the remote address of its connection otherwise.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Scope {
+    #[default]
+    Unicast,
+    /// The limited broadcast address or the broadcast address of an interface network.
+    Broadcast,
+    Multicast,
+    /// Link-local unicast addresses, link-local multicast being `Multicast`.
+    LinkLocal,
+    Loopback,
+}
+
+impl Scope {
+    pub fn of(destination: IpAddr, remote: IpAddr, network_interface_ips: &[IpNetwork]) -> Self {
+        match destination {
+            IpAddr::V4(ip) if ip.is_multicast() => Scope::Multicast,
+            IpAddr::V4(ip)
+                if ip.is_broadcast()
+                    || network_interface_ips
+                        .iter()
+                        .any(|ip_network| match ip_network {
+                            IpNetwork::V4(network) => {
+                                network.prefix() < 31 && network.broadcast() == ip
+                            }
+                            IpNetwork::V6(_) => false,
+                        }) =>
+            {
+                Scope::Broadcast
+            }
+            IpAddr::V6(ip) if ip.is_multicast() => Scope::Multicast,
+            _ => match remote {
+                IpAddr::V4(ip) if ip.is_loopback() => Scope::Loopback,
+                IpAddr::V4(ip) if ip.is_link_local() => Scope::LinkLocal,
+                IpAddr::V6(ip) if ip.is_loopback() => Scope::Loopback,
+                IpAddr::V6(ip)
+                    if ip.segments()[0] & IPV6_LINK_LOCAL_MASK == IPV6_LINK_LOCAL_PREFIX =>
+                {
+                    Scope::LinkLocal
+                }
+                _ => Scope::Unicast,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Scope::Unicast => "unicast",
+            Scope::Broadcast => "broadcast",
+            Scope::Multicast => "multicast",
+            Scope::LinkLocal => "link-local",
+            Scope::Loopback => "loopback",
+        })
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "unicast" => Ok(Scope::Unicast),
+            "broadcast" => Ok(Scope::Broadcast),
+            "multicast" => Ok(Scope::Multicast),
+            "link-local" => Ok(Scope::LinkLocal),
+            "loopback" => Ok(Scope::Loopback),
+            _ => Err(format!(
+                "expected unicast, broadcast, multicast, link-local or loopback, got {}",
+                value
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope(destination: &str, remote: &str) -> Scope {
+        Scope::of(
+            destination.parse().unwrap(),
+            remote.parse().unwrap(),
+            &["192.168.10.87/24".parse().unwrap()],
+        )
+    }
+
+    fn upload_scope(destination: &str) -> Scope {
+        scope(destination, destination)
+    }
+
+    #[test]
+    fn should_classify_destinations() {
+        assert_eq!(upload_scope("93.184.216.34"), Scope::Unicast);
+        assert_eq!(upload_scope("192.168.10.34"), Scope::Unicast);
+        assert_eq!(upload_scope("255.255.255.255"), Scope::Broadcast);
+        assert_eq!(upload_scope("192.168.10.255"), Scope::Broadcast);
+        assert_eq!(upload_scope("224.0.0.251"), Scope::Multicast);
+        assert_eq!(upload_scope("169.254.169.254"), Scope::LinkLocal);
+        assert_eq!(upload_scope("127.0.0.53"), Scope::Loopback);
+        assert_eq!(upload_scope("2606:2800:220:1::1"), Scope::Unicast);
+        assert_eq!(upload_scope("ff02::fb"), Scope::Multicast);
+        assert_eq!(upload_scope("fe80::1"), Scope::LinkLocal);
+        assert_eq!(upload_scope("::1"), Scope::Loopback);
+    }
+
+    #[test]
+    fn should_classify_downloads_by_remote_unless_addressed_to_group() {
+        assert_eq!(scope("192.168.10.87", "169.254.169.254"), Scope::LinkLocal);
+        assert_eq!(scope("2001:db8::87", "fe80::1"), Scope::LinkLocal);
+        assert_eq!(scope("fe80::87", "2606:2800:220:1::1"), Scope::Unicast);
+        assert_eq!(scope("224.0.0.251", "192.168.10.34"), Scope::Multicast);
+        assert_eq!(scope("192.168.10.255", "192.168.10.34"), Scope::Broadcast);
+    }
+
+    #[test]
+    fn should_parse_scopes() {
+        // when
+        let result = "Link-Local".parse::<Scope>();
+
+        // then
+        assert_eq!(result, Ok(Scope::LinkLocal));
+        assert!("anycast".parse::<Scope>().is_err());
+    }
+}
@@ -1,7 +1,7 @@
 use ::std::boxed::Box;
 
 use ::pnet::datalink::{DataLinkReceiver, NetworkInterface};
-use ::pnet::packet::ethernet::{EtherTypes, EthernetPacket};
+use ::pnet::packet::ethernet::{EtherType, EtherTypes};
 use ::pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
 use ::pnet::packet::ipv4::Ipv4Packet;
 use ::pnet::packet::ipv6::Ipv6Packet;
@@ -10,22 +10,168 @@ use ::pnet::packet::udp::UdpPacket;
 use ::pnet::packet::Packet;
 
 use ::ipnetwork::IpNetwork;
+use ::std::fmt;
 use ::std::io::{self, Result};
 use ::std::net::{IpAddr, SocketAddr};
+use ::std::ops::AddAssign;
+use ::std::str::FromStr;
 use ::std::thread::park_timeout;
+use ::std::time::SystemTime;
 
-use crate::network::{Connection, Protocol};
-use crate::os::shared::get_datalink_channel;
+use crate::network::{
+    decode_link_layer, dns_response, ipv6_upper_layer, packet_fingerprint, parse_dns_response,
+    Connection, Decapsulation, DnsCache, ForwardedFlow, Forwarding, ForwardingObservation,
+    Fragment, FragmentKey, FragmentedFlow, Fragments, Frame, Frames, IcmpKind, InterfaceInfo,
+    LiveFrames, Protocol, QuicSample, QuicTracker, RingCapture, Scope, TcpSample, TcpTracker,
+    TlsClientHello, TlsInspector, TransitFlow, Tunnel,
+};
+use crate::os::shared::get_link_type;
 
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+const SCTP_COMMON_HEADER_LENGTH: usize = 12;
+const UDP_HEADER_LENGTH: u128 = 8;
+const IPV6_HEADER_LENGTH: usize = 40;
+const ICMP_HEADER_LENGTH: u128 = 8;
 const PACKET_WAIT_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(10);
 const CHANNEL_RESET_DELAY: std::time::Duration = std::time::Duration::from_millis(1000);
 
 #[derive(Debug)]
 pub struct Segment {
+    pub timestamp: SystemTime,
     pub interface_name: String,
+    /// VLAN ids of all tags, the outermost first.
+    pub vlan_ids: Vec<u16>,
+    /// Set if the connection has been decapsulated from tunnel traffic.
+    pub tunnel: Option<Tunnel>,
+    /// Bytes of the tunnel headers, not part of `data_length`.
+    pub tunnel_overhead_length: u128,
     pub connection: Connection,
     pub direction: Direction,
+    /// Bytes accounted according to the sniffer's `AccountingMode`.
     pub data_length: u128,
+    /// Bytes of `data_length` following the transport layer header.
+    pub payload_length: u128,
+    /// More than one if earlier fragments of the datagram are attributed along with it.
+    pub packets: u128,
+    /// Control flags of TCP segments.
+    pub tcp_flags: Option<u16>,
+    /// Set for TCP segments of connections tracked by the sniffer.
+    pub tcp_sample: Option<TcpSample>,
+    /// Set for the uploaded segment completing the ClientHello of a TLS connection.
+    pub tls_client_hello: Option<TlsClientHello>,
+    /// Set for UDP datagrams recognized as QUIC.
+    pub quic: Option<QuicSample>,
+    /// Scope of the destination address of the reported flow.
+    pub scope: Scope,
+    /// Set for traffic between other hosts seen by a sniffer in mirror mode, whose `connection`
+    /// and `direction` are meaningless.
+    pub transit: Option<TransitFlow>,
+    /// Set for the later seen leg of packets forwarded by a sniffer in router mode, whose
+    /// `connection` and `direction` are meaningless.
+    pub forwarded: Option<ForwardedFlow>,
+}
+
+/// The layer whose length traffic is accounted by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AccountingMode {
+    /// Length of the link layer frame, comparable to interface and switch port counters. Pseudo
+    /// headers of Linux cooked captures are not counted.
+    L2,
+    /// Total length of the IP packet.
+    L3,
+    /// Length of the IP payload.
+    #[default]
+    L4,
+}
+
+impl fmt::Display for AccountingMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AccountingMode::L2 => "l2",
+            AccountingMode::L3 => "l3",
+            AccountingMode::L4 => "l4",
+        })
+    }
+}
+
+impl FromStr for AccountingMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "l2" => Ok(AccountingMode::L2),
+            "l3" => Ok(AccountingMode::L3),
+            "l4" => Ok(AccountingMode::L4),
+            _ => Err("expected one of l2, l3 or l4".to_string()),
+        }
+    }
+}
+
+/// The bytes of a packet accounted to its connection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AccountedLength {
+    pub data_length: u128,
+    /// Bytes of `data_length` following the transport layer header.
+    pub payload_length: u128,
+}
+
+impl AddAssign for AccountedLength {
+    fn add_assign(&mut self, other: AccountedLength) {
+        self.data_length += other.data_length;
+        self.payload_length += other.payload_length;
+    }
+}
+
+/// Accounts the IP packets of a single frame.
+#[derive(Clone, Copy)]
+struct Accounting {
+    mode: AccountingMode,
+    /// `None` for packets decapsulated from a tunnel, which have no frame of their own.
+    frame_length: Option<u128>,
+}
+
+impl Accounting {
+    fn length(
+        &self,
+        network_length: u128,
+        transport_length: u128,
+        transport_header_length: u128,
+    ) -> AccountedLength {
+        AccountedLength {
+            data_length: match self.mode {
+                AccountingMode::L2 => self.frame_length.unwrap_or(network_length),
+                AccountingMode::L3 => network_length,
+                AccountingMode::L4 => transport_length,
+            },
+            payload_length: transport_length.saturating_sub(transport_header_length),
+        }
+    }
+}
+
+/// The transport payload of a single IP packet, as carried by the network layer.
+struct IpPayload<'a> {
+    source: IpAddr,
+    destination: IpAddr,
+    next_header: IpNextHeaderProtocol,
+    payload: &'a [u8],
+    fragment: Option<Fragment>,
+    /// The length of the packet at the network layer.
+    network_length: u128,
+}
+
+/// The transport flow of a single IP packet, in the direction of the packet.
+struct IpFlow<'a> {
+    protocol: Protocol,
+    source: SocketAddr,
+    destination: SocketAddr,
+    length: AccountedLength,
+    /// More than one if earlier fragments of the datagram are attributed along with it.
+    packets: u128,
+    /// The complete transport layer packet, `None` for fragments.
+    transport: Option<&'a [u8]>,
 }
 
 #[derive(PartialEq, Hash, Eq, Debug, Clone, PartialOrd)]
@@ -47,152 +193,1261 @@ impl Direction {
     }
 }
 
-trait NextLevelProtocol {
-    fn get_next_level_protocol(&self) -> IpNextHeaderProtocol;
-}
-
-impl NextLevelProtocol for Ipv6Packet<'_> {
-    fn get_next_level_protocol(&self) -> IpNextHeaderProtocol {
-        self.get_next_header()
-    }
-}
-
+/// Evaluates to the protocol, the ports and the header length of a transport layer packet.
 macro_rules! extract_transport_protocol {
-    (  $ip_packet: ident ) => {{
-        match $ip_packet.get_next_level_protocol() {
+    (  $protocol: expr, $payload: expr ) => {{
+        let payload = $payload;
+        match $protocol {
             IpNextHeaderProtocols::Tcp => {
-                let message = TcpPacket::new($ip_packet.payload())?;
+                let message = TcpPacket::new(payload)?;
                 (
                     Protocol::Tcp,
                     message.get_source(),
                     message.get_destination(),
-                    $ip_packet.payload().len() as u128,
+                    u128::from(message.get_data_offset()) * 4,
                 )
             }
             IpNextHeaderProtocols::Udp => {
-                let datagram = UdpPacket::new($ip_packet.payload())?;
+                let datagram = UdpPacket::new(payload)?;
                 (
                     Protocol::Udp,
                     datagram.get_source(),
                     datagram.get_destination(),
-                    $ip_packet.payload().len() as u128,
+                    UDP_HEADER_LENGTH,
+                )
+            }
+            IpNextHeaderProtocols::Icmp => (
+                Protocol::Icmp(icmp_kind(payload, ICMP_ECHO_REQUEST, ICMP_ECHO_REPLY)?),
+                0,
+                0,
+                ICMP_HEADER_LENGTH,
+            ),
+            IpNextHeaderProtocols::Icmpv6 => (
+                Protocol::Icmpv6(icmp_kind(payload, ICMPV6_ECHO_REQUEST, ICMPV6_ECHO_REPLY)?),
+                0,
+                0,
+                ICMP_HEADER_LENGTH,
+            ),
+            IpNextHeaderProtocols::Sctp => {
+                let (source_port, destination_port) = sctp_ports(payload)?;
+                (
+                    Protocol::Sctp,
+                    source_port,
+                    destination_port,
+                    SCTP_COMMON_HEADER_LENGTH as u128,
                 )
             }
-            _ => return None,
+            other => (Protocol::Other(other.0), 0, 0, 0),
         }
     }};
 }
 
+/// Keys echo messages by their identifier and all other messages by type and code.
+fn icmp_kind(message: &[u8], echo_request: u8, echo_reply: u8) -> Option<IcmpKind> {
+    let icmp_type = *message.first()?;
+    if icmp_type == echo_request || icmp_type == echo_reply {
+        let identifier = message.get(4..6)?;
+        Some(IcmpKind::Echo {
+            identifier: u16::from_be_bytes([identifier[0], identifier[1]]),
+        })
+    } else {
+        Some(IcmpKind::Message {
+            icmp_type,
+            code: *message.get(1)?,
+        })
+    }
+}
+
+/// Reads the ports of the SCTP common header.
+fn sctp_ports(packet: &[u8]) -> Option<(u16, u16)> {
+    let header = packet.get(..SCTP_COMMON_HEADER_LENGTH)?;
+    Some((
+        u16::from_be_bytes([header[0], header[1]]),
+        u16::from_be_bytes([header[2], header[3]]),
+    ))
+}
+
+/// Decodes the frames of a sniffer into segments, keeping the state tracked across frames.
+struct Decoder {
+    decapsulation: Option<Decapsulation>,
+    accounting_mode: AccountingMode,
+    mirror: bool,
+    forwarding: Option<Forwarding>,
+    fragments: Fragments,
+    tcp_tracker: TcpTracker,
+    tls_inspector: TlsInspector,
+    quic_tracker: QuicTracker,
+    dns_cache: Option<DnsCache>,
+}
+
 pub struct Sniffer {
     network_interface: NetworkInterface,
-    network_frames: Box<dyn DataLinkReceiver>,
+    network_frames: Box<dyn Frames>,
+    exhausted: bool,
+    capture: Option<RingCapture>,
+    ignored_scopes: Vec<Scope>,
+    decoder: Decoder,
+}
+
+impl Decoder {
+    fn new() -> Self {
+        Decoder {
+            decapsulation: None,
+            accounting_mode: AccountingMode::default(),
+            mirror: false,
+            forwarding: None,
+            fragments: Fragments::new(),
+            tcp_tracker: TcpTracker::new(),
+            tls_inspector: TlsInspector::new(),
+            quic_tracker: QuicTracker::new(),
+            dns_cache: None,
+        }
+    }
+    fn maintain(&mut self, now: SystemTime) {
+        self.fragments.maintain(now);
+        self.tcp_tracker.maintain(now);
+        self.tls_inspector.maintain(now);
+        self.quic_tracker.maintain(now);
+        if let Some(forwarding) = &self.forwarding {
+            forwarding.maintain(now);
+        }
+    }
+    fn decode(&mut self, frame: &Frame, network_interface: &NetworkInterface) -> Option<Segment> {
+        let &mut Decoder {
+            decapsulation,
+            accounting_mode,
+            mirror,
+            ref forwarding,
+            ref mut fragments,
+            ref mut tcp_tracker,
+            ref mut tls_inspector,
+            ref mut quic_tracker,
+            ref dns_cache,
+        } = self;
+        let mut vlan_ids = vec![];
+        let (ethertype, payload) = decode_link_layer(frame.link_type, frame.bytes, &mut vlan_ids)?;
+        let accounting = Accounting {
+            mode: accounting_mode,
+            frame_length: Some(
+                frame
+                    .bytes
+                    .len()
+                    .saturating_sub(frame.link_type.pseudo_header_length()) as u128,
+            ),
+        };
+        let outer = Sniffer::decode_ip(ethertype, payload, accounting, fragments, frame.timestamp)?;
+        let direction = Direction::new(&network_interface.ips, outer.source.ip());
+
+        let decapsulated = decapsulation.and_then(|decapsulation| {
+            let decapsulated = decapsulation.decapsulate(
+                outer.protocol,
+                outer.destination.port(),
+                outer.transport?,
+            )?;
+            let inner = Sniffer::decode_ip(
+                decapsulated.ethertype,
+                decapsulated.payload,
+                Accounting {
+                    mode: accounting_mode,
+                    frame_length: None,
+                },
+                fragments,
+                frame.timestamp,
+            )?;
+            let remote_endpoint = match direction {
+                Direction::Download => outer.source.ip(),
+                Direction::Upload => outer.destination.ip(),
+            };
+            let tunnel = Tunnel {
+                kind: decapsulated.kind,
+                remote_endpoint,
+                vni: decapsulated.vni,
+            };
+            Some((inner, tunnel))
+        });
+        let (flow, tunnel, tunnel_overhead_length) = match decapsulated {
+            Some((inner, tunnel)) => {
+                let tunnel_overhead_length = outer
+                    .length
+                    .data_length
+                    .saturating_sub(inner.length.data_length);
+                (inner, Some(tunnel), tunnel_overhead_length)
+            }
+            None => (outer, None, 0),
+        };
+
+        let remote = match direction {
+            Direction::Download => flow.source.ip(),
+            Direction::Upload => flow.destination.ip(),
+        };
+        let scope = Scope::of(flow.destination.ip(), remote, &network_interface.ips);
+        let is_local = |ip: IpAddr| {
+            network_interface
+                .ips
+                .iter()
+                .any(|ip_network| ip_network.ip() == ip)
+        };
+        let transit_flow = TransitFlow::new(flow.source, flow.destination, flow.protocol);
+        let untracked = if mirror && !is_local(flow.source.ip()) && !is_local(flow.destination.ip())
+        {
+            Some((Some(transit_flow), None))
+        } else {
+            match forwarding {
+                Some(forwarding) if scope == Scope::Unicast => match forwarding.observe(
+                    &network_interface.name,
+                    transit_flow,
+                    flow.transport
+                        .and_then(|transport| packet_fingerprint(flow.protocol, transport)),
+                    flow.length.data_length,
+                    frame.timestamp,
+                ) {
+                    ForwardingObservation::Local => None,
+                    ForwardingObservation::Pending => return None,
+                    ForwardingObservation::Forwarded(forwarded) => Some((None, Some(forwarded))),
+                },
+                _ => None,
+            }
+        };
+        if let Some((transit, forwarded)) = untracked {
+            return Some(Segment {
+                timestamp: frame.timestamp,
+                interface_name: network_interface.name.clone(),
+                vlan_ids,
+                tunnel,
+                tunnel_overhead_length,
+                connection: Connection::new(
+                    flow.source,
+                    flow.destination.ip(),
+                    flow.destination.port(),
+                    flow.protocol,
+                ),
+                direction,
+                data_length: flow.length.data_length,
+                payload_length: flow.length.payload_length,
+                packets: flow.packets,
+                tcp_flags: None,
+                tcp_sample: None,
+                tls_client_hello: None,
+                quic: None,
+                scope,
+                transit,
+                forwarded,
+            });
+        }
+        let mut connection = match direction {
+            Direction::Download => Connection::new(
+                flow.source,
+                flow.destination.ip(),
+                flow.destination.port(),
+                flow.protocol,
+            ),
+            Direction::Upload => Connection::new(
+                flow.destination,
+                flow.source.ip(),
+                flow.source.port(),
+                flow.protocol,
+            ),
+        };
+        connection.vni = tunnel.and_then(|tunnel| tunnel.vni);
+        if let Some(dns_cache) = dns_cache {
+            if let Some(message) = flow
+                .transport
+                .and_then(|transport| dns_response(flow.protocol, flow.source.port(), transport))
+            {
+                dns_cache.insert(parse_dns_response(message), frame.timestamp);
+            }
+        }
+        let tcp_segment = match flow.protocol {
+            Protocol::Tcp => flow.transport.and_then(TcpPacket::new),
+            _ => None,
+        };
+        let tcp_flags = tcp_segment.as_ref().map(TcpPacket::get_flags);
+        let quic_observation = match (flow.protocol, flow.transport) {
+            (Protocol::Udp, Some(transport)) => transport
+                .get(UDP_HEADER_LENGTH as usize..)
+                .and_then(|datagram| {
+                    quic_tracker.observe(&connection, &direction, datagram, frame.timestamp)
+                }),
+            _ => None,
+        };
+        let tls_client_hello = match (&tcp_segment, &quic_observation, &direction) {
+            (Some(tcp_segment), _, Direction::Upload) => tls_inspector.observe(
+                &connection,
+                tcp_segment.get_sequence(),
+                tcp_segment.payload(),
+                frame.timestamp,
+            ),
+            (_, Some(quic_observation), _) => tls_inspector.observe_crypto_frames(
+                &connection,
+                &quic_observation.crypto_frames,
+                frame.timestamp,
+            ),
+            _ => None,
+        };
+        let tcp_sample = tcp_segment.map(|tcp_segment| {
+            tcp_tracker.observe(&connection, &direction, &tcp_segment, frame.timestamp)
+        });
+        Some(Segment {
+            timestamp: frame.timestamp,
+            interface_name: network_interface.name.clone(),
+            vlan_ids,
+            tunnel,
+            tunnel_overhead_length,
+            connection,
+            direction,
+            data_length: flow.length.data_length,
+            payload_length: flow.length.payload_length,
+            packets: flow.packets,
+            tcp_flags,
+            tcp_sample,
+            tls_client_hello,
+            quic: quic_observation.map(|quic_observation| quic_observation.sample),
+            scope,
+            transit: None,
+            forwarded: None,
+        })
+    }
 }
 
 impl Sniffer {
+    /// Sniffs a live interface, whose channel is reopened in promiscuous mode if given.
     pub fn new(
         network_interface: NetworkInterface,
         network_frames: Box<dyn DataLinkReceiver>,
+        promiscuous: bool,
+    ) -> Self {
+        let link_type = get_link_type(&network_interface);
+        Sniffer::from_frames(
+            network_interface,
+            Box::new(LiveFrames::new(network_frames, link_type, promiscuous)),
+        )
+    }
+    pub fn from_frames(
+        network_interface: NetworkInterface,
+        network_frames: Box<dyn Frames>,
     ) -> Self {
         Sniffer {
             network_interface,
             network_frames,
+            exhausted: false,
+            capture: None,
+            ignored_scopes: vec![],
+            decoder: Decoder::new(),
         }
     }
+    /// Additionally records all sniffed frames into the given capture.
+    pub fn with_capture(mut self, capture: RingCapture) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+    /// Reports the inner flows of tunnel traffic instead of the tunnel itself.
+    pub fn with_decapsulation(mut self, decapsulation: Decapsulation) -> Self {
+        self.decoder.decapsulation = Some(decapsulation);
+        self
+    }
+    pub fn with_accounting_mode(mut self, accounting_mode: AccountingMode) -> Self {
+        self.decoder.accounting_mode = accounting_mode;
+        self
+    }
+    /// Records the answers of sniffed DNS responses into the given cache.
+    pub fn with_dns_cache(mut self, dns_cache: DnsCache) -> Self {
+        self.decoder.dns_cache = Some(dns_cache);
+        self
+    }
+    /// Drops segments of any of the given scopes; captures still record them.
+    pub fn with_ignored_scopes(mut self, ignored_scopes: Vec<Scope>) -> Self {
+        self.ignored_scopes = ignored_scopes;
+        self
+    }
+    /// Reports traffic not involving any address of the interface as transit flows, as seen on
+    /// mirror or SPAN ports.
+    pub fn with_mirror(mut self) -> Self {
+        self.decoder.mirror = true;
+        self
+    }
+    /// Reports packets forwarded between this and other interfaces sharing the given state once,
+    /// by the flows of both legs.
+    pub fn with_forwarding(mut self, forwarding: Forwarding) -> Self {
+        self.decoder.forwarding = Some(forwarding);
+        self
+    }
+    pub fn dns_cache(&self) -> Option<&DnsCache> {
+        self.decoder.dns_cache.as_ref()
+    }
+    /// Whether a recorded frame source has been read completely.
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted
+    }
+    pub fn interface_name(&self) -> &str {
+        &self.network_interface.name
+    }
+    /// Returns the traffic not attributable to any connection since the last call, if any.
+    pub fn take_interface_info(&mut self) -> Option<InterfaceInfo> {
+        let orphaned_fragments = self.decoder.fragments.take_orphaned();
+        let unmatched_forwarding = self
+            .decoder
+            .forwarding
+            .as_ref()
+            .map(|forwarding| forwarding.take_unmatched(&self.network_interface.name))
+            .unwrap_or_default();
+        if orphaned_fragments.is_empty() && unmatched_forwarding.is_empty() {
+            return None;
+        }
+        Some(InterfaceInfo {
+            orphaned_fragments,
+            unmatched_forwarding,
+            ..InterfaceInfo::default()
+        })
+    }
     pub fn next(&mut self) -> Option<Segment> {
-        let bytes = match self.network_frames.next() {
-            Ok(bytes) => bytes,
+        let frame = match self.network_frames.next() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                self.exhausted = true;
+                return None;
+            }
             Err(err) => {
                 return match err.kind() {
                     io::ErrorKind::TimedOut => {
+                        self.decoder.maintain(SystemTime::now());
+                        if let Some(capture) = &mut self.capture {
+                            if let Err(err) = capture.maintain(SystemTime::now()) {
+                                eprintln!(
+                                    "Capture on {} stopped: {}",
+                                    self.network_interface.name, err
+                                );
+                                self.capture = None;
+                            }
+                        }
                         park_timeout(PACKET_WAIT_TIMEOUT);
                         None
                     }
+                    io::ErrorKind::InvalidData if self.network_frames.is_recorded() => {
+                        eprintln!(
+                            "Unreadable frames on {}: {}",
+                            self.network_interface.name, err
+                        );
+                        self.exhausted = true;
+                        None
+                    }
+                    // A malformed live frame is skipped, later ones can still be read.
+                    io::ErrorKind::InvalidData => None,
                     _ => {
                         park_timeout(CHANNEL_RESET_DELAY);
                         self.reset_channel().ok();
                         None
                     }
-                }
+                };
             }
         };
-        // See https://github.com/libpnet/libpnet/blob/master/examples/packetdump.rs
-        // VPN interfaces (such as utun0, utun1, etc) have POINT_TO_POINT bit set to 1
-        let payload_offset = if (self.network_interface.is_loopback()
-            || self.network_interface.is_point_to_point())
-            && cfg!(target_os = "macos")
-        {
-            // The pnet code for BPF loopback adds a zero'd out Ethernet header
-            14
+        let segment = self.decoder.decode(&frame, &self.network_interface);
+        if let Some(capture) = &mut self.capture {
+            if let Err(err) = capture.record(&frame, segment.as_ref()) {
+                eprintln!(
+                    "Capture on {} stopped: {}",
+                    self.network_interface.name, err
+                );
+                self.capture = None;
+            }
+        }
+        segment.filter(|segment| !self.ignored_scopes.contains(&segment.scope))
+    }
+    pub fn reset_channel(&mut self) -> Result<()> {
+        self.network_frames.reset(&self.network_interface)
+    }
+    fn decode_ip<'a>(
+        ethertype: EtherType,
+        packet: &'a [u8],
+        accounting: Accounting,
+        fragments: &mut Fragments,
+        timestamp: SystemTime,
+    ) -> Option<IpFlow<'a>> {
+        match ethertype {
+            EtherTypes::Ipv4 => Self::handle_v4(packet, accounting, fragments, timestamp),
+            EtherTypes::Ipv6 => Self::handle_v6(packet, accounting, fragments, timestamp),
+            _ => None,
+        }
+    }
+    /// Identifies the transport flow of a payload, fragments taking the flow of their first.
+    fn extract_transport(
+        fragments: &mut Fragments,
+        ip_payload: IpPayload,
+        timestamp: SystemTime,
+        accounting: Accounting,
+    ) -> Option<(Protocol, u16, u16, AccountedLength, u128)> {
+        let IpPayload {
+            source,
+            destination,
+            next_header,
+            payload,
+            fragment,
+            network_length,
+        } = ip_payload;
+        let transport_length = payload.len() as u128;
+        let fragment = match fragment {
+            Some(fragment) => fragment,
+            None => {
+                let (protocol, source_port, destination_port, header_length) =
+                    extract_transport_protocol!(next_header, payload);
+                let length = accounting.length(network_length, transport_length, header_length);
+                return Some((protocol, source_port, destination_port, length, 1));
+            }
+        };
+        let key = FragmentKey {
+            source,
+            destination,
+            protocol: Some(next_header.0).filter(|_| source.is_ipv4()),
+            identification: fragment.identification,
+        };
+        if fragment.is_first() {
+            let (protocol, source_port, destination_port, header_length) =
+                extract_transport_protocol!(next_header, payload);
+            let flow = FragmentedFlow {
+                protocol,
+                source_port,
+                destination_port,
+            };
+            let mut length = accounting.length(network_length, transport_length, header_length);
+            let earlier = fragments.first_fragment(key, flow, timestamp);
+            length += earlier.length;
+            Some((
+                protocol,
+                source_port,
+                destination_port,
+                length,
+                1 + earlier.fragments,
+            ))
         } else {
-            0
+            let length = accounting.length(network_length, transport_length, 0);
+            let flow = fragments.subsequent_fragment(key, length, timestamp)?;
+            Some((
+                flow.protocol,
+                flow.source_port,
+                flow.destination_port,
+                length,
+                1,
+            ))
+        }
+    }
+    fn handle_v6<'a>(
+        packet: &'a [u8],
+        accounting: Accounting,
+        fragments: &mut Fragments,
+        timestamp: SystemTime,
+    ) -> Option<IpFlow<'a>> {
+        let ip_packet = Ipv6Packet::new(packet)?;
+        let upper_layer = ipv6_upper_layer(packet)?;
+        let source = ip_packet.get_source().into();
+        let destination = ip_packet.get_destination().into();
+        let network_length = (IPV6_HEADER_LENGTH + ip_packet.payload().len()) as u128;
+        let (protocol, source_port, destination_port, length, packets) = Self::extract_transport(
+            fragments,
+            IpPayload {
+                source,
+                destination,
+                next_header: upper_layer.protocol,
+                payload: upper_layer.payload,
+                fragment: upper_layer.fragment,
+                network_length,
+            },
+            timestamp,
+            accounting,
+        )?;
+
+        Some(IpFlow {
+            protocol,
+            source: SocketAddr::new(source, source_port),
+            destination: SocketAddr::new(destination, destination_port),
+            length,
+            packets,
+            transport: match upper_layer.fragment {
+                None => Some(upper_layer.payload),
+                Some(_) => None,
+            },
+        })
+    }
+    fn handle_v4<'a>(
+        packet: &'a [u8],
+        accounting: Accounting,
+        fragments: &mut Fragments,
+        timestamp: SystemTime,
+    ) -> Option<IpFlow<'a>> {
+        let ip_packet = Ipv4Packet::new(packet)?;
+        let header_length = usize::from(ip_packet.get_header_length()) * 4;
+        let payload = packet.get(header_length..header_length + ip_packet.payload().len())?;
+        let fragment = Fragment::of_ipv4(&ip_packet);
+        let source = ip_packet.get_source().into();
+        let destination = ip_packet.get_destination().into();
+        let network_length = (header_length + payload.len()) as u128;
+        let (protocol, source_port, destination_port, length, packets) = Self::extract_transport(
+            fragments,
+            IpPayload {
+                source,
+                destination,
+                next_header: ip_packet.get_next_level_protocol(),
+                payload,
+                fragment,
+                network_length,
+            },
+            timestamp,
+            accounting,
+        )?;
+
+        Some(IpFlow {
+            protocol,
+            source: SocketAddr::new(source, source_port),
+            destination: SocketAddr::new(destination, destination_port),
+            length,
+            packets,
+            transport: match fragment {
+                None => Some(payload),
+                Some(_) => None,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use ipnetwork::Ipv4Network;
+
+    use crate::network::fixtures::*;
+    use crate::network::{LinkType, Socket, TunnelKind};
+
+    use super::*;
+
+    const LOCAL: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+    const REMOTE: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+    fn network_interface() -> NetworkInterface {
+        NetworkInterface {
+            name: "eth0".to_string(),
+            description: "".to_string(),
+            index: 0,
+            mac: None,
+            ips: vec![IpNetwork::V4(Ipv4Network::new(LOCAL, 24).unwrap())],
+            flags: 0,
+        }
+    }
+
+    fn decoder() -> Decoder {
+        Decoder {
+            decapsulation: Some(Decapsulation::default()),
+            accounting_mode: AccountingMode::L4,
+            ..Decoder::new()
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Segment> {
+        decode_with(&mut decoder(), bytes)
+    }
+
+    fn decode_with(decoder: &mut Decoder, bytes: &[u8]) -> Option<Segment> {
+        decoder.decode(
+            &Frame {
+                timestamp: SystemTime::UNIX_EPOCH,
+                link_type: LinkType::Ethernet,
+                bytes,
+            },
+            &network_interface(),
+        )
+    }
+
+    fn decode_accounted(accounting_mode: AccountingMode, bytes: &[u8]) -> Option<Segment> {
+        let mut decoder = Decoder {
+            accounting_mode,
+            ..decoder()
         };
-        let ip_packet = Ipv4Packet::new(&bytes[payload_offset..])?;
-        let version = ip_packet.get_version();
+        decode_with(&mut decoder, bytes)
+    }
 
-        match version {
-            4 => Self::handle_v4(ip_packet, &self.network_interface),
-            6 => Self::handle_v6(
-                Ipv6Packet::new(&bytes[payload_offset..])?,
-                &self.network_interface,
-            ),
-            _ => {
-                let pkg = EthernetPacket::new(bytes)?;
-                match pkg.get_ethertype() {
-                    EtherTypes::Ipv4 => {
-                        Self::handle_v4(Ipv4Packet::new(pkg.payload())?, &self.network_interface)
-                    }
-                    EtherTypes::Ipv6 => {
-                        Self::handle_v6(Ipv6Packet::new(pkg.payload())?, &self.network_interface)
-                    }
-                    _ => None,
-                }
+    fn accounted(accounting_mode: AccountingMode, bytes: &[u8]) -> (u128, u128) {
+        let segment = decode_accounted(accounting_mode, bytes).unwrap();
+        (segment.data_length, segment.payload_length)
+    }
+
+    fn udp_download() -> Vec<u8> {
+        ipv4(REMOTE, LOCAL, PROTOCOL_UDP, &udp(53, 40000, &[0; 12]))
+    }
+
+    #[test]
+    fn should_decode_untagged_frames() {
+        // when
+        let result = decode(&ethernet(ETHERTYPE_IPV4, &udp_download())).unwrap();
+
+        // then
+        assert_eq!(result.vlan_ids, Vec::<u16>::new());
+        assert_eq!(result.direction, Direction::Download);
+        assert_eq!(
+            result.connection.remote_socket,
+            Socket {
+                ip: REMOTE.into(),
+                port: 53
             }
+        );
+        assert_eq!(result.data_length, 20);
+    }
+
+    #[test]
+    fn should_account_by_configured_layer() {
+        // given
+        let frame = ethernet(ETHERTYPE_IPV4, &udp_download());
+
+        // when
+        let l2 = accounted(AccountingMode::L2, &frame);
+        let l3 = accounted(AccountingMode::L3, &frame);
+        let l4 = accounted(AccountingMode::L4, &frame);
+
+        // then
+        assert_eq!(l2, (54, 12));
+        assert_eq!(l3, (40, 12));
+        assert_eq!(l4, (20, 12));
+    }
+
+    #[test]
+    fn should_not_account_pseudo_headers_of_linux_cooked_captures() {
+        // given
+        let mut frame = vec![0; 14];
+        frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        frame.extend_from_slice(&udp_download());
+        let mut decoder = Decoder {
+            accounting_mode: AccountingMode::L2,
+            ..decoder()
+        };
+
+        // when
+        let result = decoder.decode(
+            &Frame {
+                timestamp: SystemTime::UNIX_EPOCH,
+                link_type: LinkType::LinuxSll,
+                bytes: &frame,
+            },
+            &network_interface(),
+        );
+
+        // then
+        assert_eq!(result.unwrap().data_length, 40);
+    }
+
+    #[test]
+    fn should_account_tunnel_headers_as_overhead_by_configured_layer() {
+        // given
+        let inner = ipv4(
+            Ipv4Addr::new(10, 244, 1, 7),
+            Ipv4Addr::new(10, 244, 2, 9),
+            PROTOCOL_UDP,
+            &udp(40000, 53, &[0; 12]),
+        );
+        let vxlan = [
+            &[0x08, 0, 0, 0, 0, 0, 0x01, 0][..],
+            &ethernet(ETHERTYPE_IPV4, &inner),
+        ]
+        .concat();
+        let outer = ethernet(
+            ETHERTYPE_IPV4,
+            &ipv4(LOCAL, REMOTE, PROTOCOL_UDP, &udp(54321, 4789, &vxlan)),
+        );
+
+        // when
+        let result = decode_accounted(AccountingMode::L2, &outer).unwrap();
+
+        // then
+        assert_eq!(result.data_length, 40);
+        assert_eq!(result.payload_length, 12);
+        assert_eq!(result.tunnel_overhead_length, 64);
+    }
+
+    #[test]
+    fn should_report_flags_of_tcp_segments() {
+        // given
+        let packet = ipv4(
+            REMOTE,
+            LOCAL,
+            PROTOCOL_TCP,
+            &tcp(443, 40000, 1000, 1, 0x12, &[]),
+        );
+
+        // when
+        let result = decode(&ethernet(ETHERTYPE_IPV4, &packet)).unwrap();
+
+        // then
+        assert_eq!(result.tcp_flags, Some(0x12));
+        assert_eq!(result.data_length, 20);
+        assert_eq!(result.payload_length, 0);
+    }
+
+    #[test]
+    fn should_record_dns_responses_into_cache() {
+        // given
+        let dns_cache = DnsCache::default();
+        let address = Ipv4Addr::new(93, 184, 216, 34);
+        let packet = ipv4(
+            REMOTE,
+            LOCAL,
+            PROTOCOL_UDP,
+            &udp(53, 40000, &dns_a_response("example.com", address, 60)),
+        );
+
+        // when
+        let mut decoder = Decoder {
+            dns_cache: Some(dns_cache.clone()),
+            ..Decoder::new()
+        };
+        decode_with(&mut decoder, &ethernet(ETHERTYPE_IPV4, &packet));
+
+        // then
+        assert_eq!(
+            dns_cache.names(&IpAddr::V4(address), SystemTime::UNIX_EPOCH),
+            vec!["example.com".to_string()]
+        );
+    }
+
+    struct PacketFrames {
+        packets: Vec<Vec<u8>>,
+        position: usize,
+    }
+
+    impl Frames for PacketFrames {
+        fn next(&mut self) -> Result<Option<Frame<'_>>> {
+            self.position += 1;
+            Ok(self.packets.get(self.position - 1).map(|bytes| Frame {
+                timestamp: SystemTime::UNIX_EPOCH,
+                link_type: LinkType::Ethernet,
+                bytes,
+            }))
+        }
+        fn reset(&mut self, _network_interface: &NetworkInterface) -> Result<()> {
+            Ok(())
+        }
+        fn is_recorded(&self) -> bool {
+            true
         }
     }
-    pub fn reset_channel(&mut self) -> Result<()> {
-        self.network_frames = get_datalink_channel(&self.network_interface)
-            .map_err(|_| io::Error::new(io::ErrorKind::Other, "Interface not available"))?;
-        Ok(())
+
+    /// Fails to read the first frame, then returns the same packet over and over.
+    struct UnreadableFirstFrame {
+        recorded: bool,
+        packet: Vec<u8>,
+        read: bool,
     }
-    fn handle_v6(ip_packet: Ipv6Packet, network_interface: &NetworkInterface) -> Option<Segment> {
-        let (protocol, source_port, destination_port, data_length) =
-            extract_transport_protocol!(ip_packet);
 
-        let interface_name = network_interface.name.clone();
-        let direction = Direction::new(&network_interface.ips, ip_packet.get_source().into());
-        let from = SocketAddr::new(ip_packet.get_source().into(), source_port);
-        let to = SocketAddr::new(ip_packet.get_destination().into(), destination_port);
+    impl Frames for UnreadableFirstFrame {
+        fn next(&mut self) -> Result<Option<Frame<'_>>> {
+            if !self.read {
+                self.read = true;
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated"));
+            }
+            Ok(Some(Frame {
+                timestamp: SystemTime::UNIX_EPOCH,
+                link_type: LinkType::Ethernet,
+                bytes: &self.packet,
+            }))
+        }
+        fn reset(&mut self, _network_interface: &NetworkInterface) -> Result<()> {
+            Ok(())
+        }
+        fn is_recorded(&self) -> bool {
+            self.recorded
+        }
+    }
 
-        let connection = match direction {
-            Direction::Download => Connection::new(from, to.ip(), destination_port, protocol),
-            Direction::Upload => Connection::new(to, from.ip(), source_port, protocol),
+    #[test]
+    fn should_skip_unreadable_live_frames_but_stop_reading_recordings() {
+        // given
+        let frames = |recorded| UnreadableFirstFrame {
+            recorded,
+            packet: ethernet(ETHERTYPE_IPV4, &udp_download()),
+            read: false,
         };
-        Some(Segment {
-            interface_name,
-            connection,
-            data_length,
-            direction,
-        })
+        let mut live = Sniffer::from_frames(network_interface(), Box::new(frames(false)));
+        let mut recorded = Sniffer::from_frames(network_interface(), Box::new(frames(true)));
+
+        // when
+        let live_segments = [live.next(), live.next()];
+        let recorded_segment = recorded.next();
+
+        // then
+        assert!(live_segments[0].is_none());
+        assert!(live_segments[1].is_some());
+        assert!(!live.is_exhausted());
+        assert!(recorded_segment.is_none());
+        assert!(recorded.is_exhausted());
     }
-    fn handle_v4(ip_packet: Ipv4Packet, network_interface: &NetworkInterface) -> Option<Segment> {
-        let (protocol, source_port, destination_port, data_length) =
-            extract_transport_protocol!(ip_packet);
 
-        let interface_name = network_interface.name.clone();
-        let direction = Direction::new(&network_interface.ips, ip_packet.get_source().into());
-        let from = SocketAddr::new(ip_packet.get_source().into(), source_port);
-        let to = SocketAddr::new(ip_packet.get_destination().into(), destination_port);
+    #[test]
+    fn should_classify_scope_of_destination_or_remote() {
+        // given
+        let mdns_query = ipv4(
+            REMOTE,
+            Ipv4Addr::new(224, 0, 0, 251),
+            PROTOCOL_UDP,
+            &udp(5353, 5353, &[0; 12]),
+        );
+        let subnet_broadcast = ipv4(
+            REMOTE,
+            Ipv4Addr::new(10, 0, 0, 255),
+            PROTOCOL_UDP,
+            &udp(138, 138, &[0; 12]),
+        );
+        let metadata_response = ipv4(
+            Ipv4Addr::new(169, 254, 169, 254),
+            LOCAL,
+            PROTOCOL_UDP,
+            &udp(80, 40000, &[0; 12]),
+        );
+
+        // when
+        let mdns_query = decode(&ethernet(ETHERTYPE_IPV4, &mdns_query)).unwrap();
+        let subnet_broadcast = decode(&ethernet(ETHERTYPE_IPV4, &subnet_broadcast)).unwrap();
+        let metadata_response = decode(&ethernet(ETHERTYPE_IPV4, &metadata_response)).unwrap();
+        let unicast = decode(&ethernet(ETHERTYPE_IPV4, &udp_download())).unwrap();
 
-        let connection = match direction {
-            Direction::Download => Connection::new(from, to.ip(), destination_port, protocol),
-            Direction::Upload => Connection::new(to, from.ip(), source_port, protocol),
+        // then
+        assert_eq!(mdns_query.scope, Scope::Multicast);
+        assert_eq!(subnet_broadcast.scope, Scope::Broadcast);
+        assert_eq!(metadata_response.scope, Scope::LinkLocal);
+        assert_eq!(unicast.scope, Scope::Unicast);
+    }
+
+    #[test]
+    fn should_drop_segments_of_ignored_scopes() {
+        // given
+        let mdns_query = ipv4(
+            REMOTE,
+            Ipv4Addr::new(224, 0, 0, 251),
+            PROTOCOL_UDP,
+            &udp(5353, 5353, &[0; 12]),
+        );
+        let frames = PacketFrames {
+            packets: vec![
+                ethernet(ETHERTYPE_IPV4, &mdns_query),
+                ethernet(ETHERTYPE_IPV4, &udp_download()),
+            ],
+            position: 0,
         };
+        let mut sniffer = Sniffer::from_frames(network_interface(), Box::new(frames))
+            .with_ignored_scopes(vec![Scope::Multicast]);
 
-        Some(Segment {
-            interface_name,
-            connection,
-            data_length,
-            direction,
-        })
+        // when
+        let multicast = sniffer.next();
+        let unicast = sniffer.next();
+
+        // then
+        assert!(multicast.is_none());
+        assert_eq!(unicast.unwrap().scope, Scope::Unicast);
+    }
+
+    #[test]
+    fn should_report_transit_flows_in_mirror_mode() {
+        // given
+        let transit = ipv4(
+            Ipv4Addr::new(10, 0, 0, 3),
+            REMOTE,
+            PROTOCOL_UDP,
+            &udp(40000, 53, &[0; 12]),
+        );
+        let mut decoder = Decoder {
+            accounting_mode: AccountingMode::L4,
+            mirror: true,
+            ..Decoder::new()
+        };
+
+        // when
+        let transit = decode_with(&mut decoder, &ethernet(ETHERTYPE_IPV4, &transit)).unwrap();
+        let local = decode_with(&mut decoder, &ethernet(ETHERTYPE_IPV4, &udp_download())).unwrap();
+
+        // then
+        assert_eq!(
+            transit.transit,
+            Some(TransitFlow::new(
+                SocketAddr::new(Ipv4Addr::new(10, 0, 0, 3).into(), 40000),
+                SocketAddr::new(REMOTE.into(), 53),
+                Protocol::Udp
+            ))
+        );
+        assert_eq!(local.transit, None);
+    }
+
+    #[test]
+    fn should_decode_ipv6_behind_extension_headers() {
+        // given
+        let remote = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 2);
+        let local = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1);
+        let packet = ipv6(
+            remote,
+            local,
+            0,
+            &ipv6_extension_header(PROTOCOL_UDP, &[0; 6], &udp(53, 40000, &[0; 12])),
+        );
+
+        // when
+        let result = decode(&ethernet(ETHERTYPE_IPV6, &packet)).unwrap();
+
+        // then
+        assert_eq!(
+            result.connection.remote_socket,
+            Socket {
+                ip: remote.into(),
+                port: 53
+            }
+        );
+        assert_eq!(result.connection.local_socket.port, 40000);
+        assert_eq!(result.data_length, 20);
+    }
+
+    #[test]
+    fn should_attribute_ipv4_fragments_to_flow_of_first_fragment() {
+        // given
+        let mut decoder = decoder();
+        let datagram = udp(53, 40000, &[0xff; 24]);
+        let mut first_fragment = ipv4(REMOTE, LOCAL, PROTOCOL_UDP, &datagram[..16]);
+        first_fragment[4..8].copy_from_slice(&[0x12, 0x34, 0x20, 0x00]);
+        let mut last_fragment = ipv4(REMOTE, LOCAL, PROTOCOL_UDP, &datagram[16..]);
+        last_fragment[4..8].copy_from_slice(&[0x12, 0x34, 0x00, 0x02]);
+
+        // when
+        let early_last = decode_with(&mut decoder, &ethernet(ETHERTYPE_IPV4, &last_fragment));
+        let first = decode_with(&mut decoder, &ethernet(ETHERTYPE_IPV4, &first_fragment));
+        let late_last = decode_with(&mut decoder, &ethernet(ETHERTYPE_IPV4, &last_fragment));
+
+        // then
+        assert!(early_last.is_none());
+        let first = first.unwrap();
+        assert_eq!(first.connection.remote_socket.port, 53);
+        assert_eq!(first.data_length, 32);
+        assert_eq!(first.payload_length, 24);
+        assert_eq!(first.packets, 2);
+        let late_last = late_last.unwrap();
+        assert_eq!(late_last.connection, first.connection);
+        assert_eq!(late_last.data_length, 16);
+        assert_eq!(late_last.payload_length, 16);
+    }
+
+    #[test]
+    fn should_attribute_ipv6_fragments_with_extension_headers_after_fragment_header() {
+        // given
+        let remote = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 2);
+        let local = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1);
+        let fragmentable = ipv6_extension_header(PROTOCOL_UDP, &[0; 6], &udp(53, 40000, &[0; 24]));
+        let first_fragment = ipv6(
+            remote,
+            local,
+            PROTOCOL_IPV6_FRAGMENT,
+            &[
+                &[PROTOCOL_IPV6_OPTIONS, 0, 0x00, 0x01, 0, 0, 0, 42],
+                &fragmentable[..24],
+            ]
+            .concat(),
+        );
+        let last_fragment = ipv6(
+            remote,
+            local,
+            PROTOCOL_IPV6_FRAGMENT,
+            &[
+                &[PROTOCOL_IPV6_OPTIONS, 0, 0x00, 0x18, 0, 0, 0, 42],
+                &fragmentable[24..],
+            ]
+            .concat(),
+        );
+        let mut decoder = decoder();
+
+        // when
+        let first = decode_with(&mut decoder, &ethernet(ETHERTYPE_IPV6, &first_fragment));
+        let last = decode_with(&mut decoder, &ethernet(ETHERTYPE_IPV6, &last_fragment));
+
+        // then
+        let first = first.unwrap();
+        assert_eq!(first.connection.remote_socket.port, 53);
+        let last = last.unwrap();
+        assert_eq!(last.connection, first.connection);
+        assert_eq!(last.data_length, 16);
+        assert!(decoder.fragments.take_orphaned().is_empty());
+    }
+
+    #[test]
+    fn should_key_icmp_echo_by_identifier() {
+        // given
+        let request = ipv4(
+            LOCAL,
+            REMOTE,
+            PROTOCOL_ICMP,
+            &icmp(8, 0, &[0x12, 0x34, 0, 1, 0xff, 0xff]),
+        );
+        let reply = ipv4(
+            REMOTE,
+            LOCAL,
+            PROTOCOL_ICMP,
+            &icmp(0, 0, &[0x12, 0x34, 0, 1, 0xff, 0xff]),
+        );
+
+        // when
+        let request = decode(&ethernet(ETHERTYPE_IPV4, &request)).unwrap();
+        let reply = decode(&ethernet(ETHERTYPE_IPV4, &reply)).unwrap();
+
+        // then
+        assert_eq!(request.direction, Direction::Upload);
+        assert_eq!(reply.direction, Direction::Download);
+        assert_eq!(request.connection, reply.connection);
+        assert_eq!(
+            reply.connection.local_socket.protocol,
+            Protocol::Icmp(IcmpKind::Echo { identifier: 0x1234 })
+        );
+        assert_eq!(reply.connection.remote_socket.port, 0);
+        assert_eq!(reply.data_length, 10);
+    }
+
+    #[test]
+    fn should_key_icmpv6_messages_by_type_and_code() {
+        // given
+        let remote = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 2);
+        let local = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1);
+        let packet = ipv6(remote, local, PROTOCOL_ICMPV6, &icmp(1, 4, &[0; 4]));
+
+        // when
+        let result = decode(&ethernet(ETHERTYPE_IPV6, &packet)).unwrap();
+
+        // then
+        assert_eq!(
+            result.connection.local_socket.protocol,
+            Protocol::Icmpv6(IcmpKind::Message {
+                icmp_type: 1,
+                code: 4
+            })
+        );
+    }
+
+    #[test]
+    fn should_decode_sctp_ports() {
+        // given
+        let packet = ipv4(
+            REMOTE,
+            LOCAL,
+            PROTOCOL_SCTP,
+            &[&[0x0b, 0x59, 0x9c, 0x40][..], &[0; 24]].concat(),
+        );
+
+        // when
+        let result = decode(&ethernet(ETHERTYPE_IPV4, &packet)).unwrap();
+
+        // then
+        assert_eq!(result.connection.local_socket.protocol, Protocol::Sctp);
+        assert_eq!(result.connection.remote_socket.port, 2905);
+        assert_eq!(result.connection.local_socket.port, 40000);
+        assert_eq!(result.data_length, 28);
+    }
+
+    #[test]
+    fn should_decode_other_ip_protocols_without_ports() {
+        // given
+        let packet = ipv4(REMOTE, LOCAL, PROTOCOL_GRE, &[0; 42]);
+
+        // when
+        let result = decode(&ethernet(ETHERTYPE_IPV4, &packet)).unwrap();
+
+        // then
+        assert_eq!(
+            result.connection.local_socket.protocol,
+            Protocol::Other(PROTOCOL_GRE)
+        );
+        assert_eq!(result.connection.remote_socket.port, 0);
+        assert_eq!(result.connection.local_socket.port, 0);
+        assert_eq!(result.data_length, 42);
+    }
+
+    #[test]
+    fn should_report_inner_flows_of_vxlan_traffic() {
+        // given
+        let inner = ipv4(
+            Ipv4Addr::new(10, 244, 1, 7),
+            Ipv4Addr::new(10, 244, 2, 9),
+            PROTOCOL_UDP,
+            &udp(40000, 53, &[0; 12]),
+        );
+        let vxlan = [
+            &[0x08, 0, 0, 0, 0, 0, 0x01, 0][..],
+            &ethernet(ETHERTYPE_IPV4, &inner),
+        ]
+        .concat();
+        let outer = ipv4(LOCAL, REMOTE, PROTOCOL_UDP, &udp(54321, 4789, &vxlan));
+
+        // when
+        let result = decode(&ethernet(ETHERTYPE_IPV4, &outer)).unwrap();
+
+        // then
+        assert_eq!(result.direction, Direction::Upload);
+        assert_eq!(
+            result.connection.remote_socket,
+            Socket {
+                ip: Ipv4Addr::new(10, 244, 2, 9).into(),
+                port: 53
+            }
+        );
+        assert_eq!(result.connection.local_socket.port, 40000);
+        assert_eq!(result.connection.vni, Some(1));
+        assert_eq!(
+            result.tunnel,
+            Some(Tunnel {
+                kind: TunnelKind::Vxlan,
+                remote_endpoint: REMOTE.into(),
+                vni: Some(1)
+            })
+        );
+        assert_eq!(result.data_length, 20);
+        assert_eq!(result.tunnel_overhead_length, 50);
+    }
+
+    #[test]
+    fn should_decode_single_vlan_tag() {
+        // when
+        let result = decode(&ethernet(
+            ETHERTYPE_VLAN,
+            &vlan(0x2000 | 42, ETHERTYPE_IPV4, &udp_download()),
+        ))
+        .unwrap();
+
+        // then
+        assert_eq!(result.vlan_ids, vec![42]);
+        assert_eq!(result.connection.local_socket.port, 40000);
+        assert_eq!(result.data_length, 20);
+    }
+
+    #[test]
+    fn should_decode_double_vlan_tags() {
+        // when
+        let result = decode(&ethernet(
+            ETHERTYPE_QINQ,
+            &vlan(
+                100,
+                ETHERTYPE_VLAN,
+                &vlan(42, ETHERTYPE_IPV4, &udp_download()),
+            ),
+        ))
+        .unwrap();
+
+        // then
+        assert_eq!(result.vlan_ids, vec![100, 42]);
+        assert_eq!(result.connection.local_socket.port, 40000);
+    }
+
+    #[test]
+    fn should_ignore_more_than_two_vlan_tags() {
+        // when
+        let result = decode(&ethernet(
+            ETHERTYPE_QINQ,
+            &vlan(
+                100,
+                ETHERTYPE_VLAN,
+                &vlan(
+                    42,
+                    ETHERTYPE_VLAN,
+                    &vlan(7, ETHERTYPE_IPV4, &udp_download()),
+                ),
+            ),
+        ));
+
+        // then
+        assert!(result.is_none());
     }
 }
@@ -0,0 +1,528 @@
+use ::std::time::{Duration, SystemTime};
+
+use ::pnet::packet::tcp::{TcpFlags, TcpPacket};
+use ::pnet::packet::Packet;
+
+use crate::network::{Connection, Direction, ExpiringMap};
+
+const MAXIMUM_NUMBER_OF_CONNECTIONS: usize = 16384;
+/// Idle connections are forgotten after the timeout.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(300);
+/// Inverse weight of a new sample in the smoothed RTT, see RFC 6298.
+const RTT_SAMPLE_WEIGHT: u32 = 8;
+
+/// Which side initiated a connection, from the local point of view.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionRole {
+    Client,
+    Server,
+}
+
+/// The TCP control segments of a connection seen within a utilization window.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TcpLifecycle {
+    /// The first SYN, or the SYN-ACK if the SYN has not been seen.
+    pub opened: Option<SystemTime>,
+    /// The last FIN or RST.
+    pub closed: Option<SystemTime>,
+    pub role: Option<ConnectionRole>,
+    pub resets: u128,
+    /// Handshakes answered by a reset instead of a SYN-ACK.
+    pub failed_handshakes: u128,
+    handshake_pending: bool,
+}
+
+impl TcpLifecycle {
+    pub fn update(&mut self, flags: u16, direction: &Direction, timestamp: SystemTime) {
+        let syn = flags & TcpFlags::SYN != 0;
+        let ack = flags & TcpFlags::ACK != 0;
+        if syn && !ack {
+            self.opened.get_or_insert(timestamp);
+            self.role = Some(match direction {
+                Direction::Upload => ConnectionRole::Client,
+                Direction::Download => ConnectionRole::Server,
+            });
+            self.handshake_pending = true;
+        } else if syn {
+            self.opened.get_or_insert(timestamp);
+            self.role.get_or_insert(match direction {
+                Direction::Upload => ConnectionRole::Server,
+                Direction::Download => ConnectionRole::Client,
+            });
+            self.handshake_pending = false;
+        }
+        if flags & TcpFlags::RST != 0 {
+            self.resets += 1;
+            if self.handshake_pending {
+                self.failed_handshakes += 1;
+                self.handshake_pending = false;
+            }
+            self.closed = Some(timestamp);
+        } else if flags & TcpFlags::FIN != 0 {
+            self.closed = Some(timestamp);
+        }
+    }
+    pub fn merge(&mut self, other: &TcpLifecycle) {
+        self.opened = match (self.opened, other.opened) {
+            (Some(opened), Some(other_opened)) => Some(opened.min(other_opened)),
+            (opened, other_opened) => opened.or(other_opened),
+        };
+        self.closed = self.closed.max(other.closed);
+        self.role = self.role.or(other.role);
+        self.resets += other.resets;
+        self.failed_handshakes += other.failed_handshakes;
+        self.handshake_pending |= other.handshake_pending;
+    }
+}
+
+/// What a single TCP segment reveals about the path of its connection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TcpSample {
+    /// Set for the segment completing the handshake as seen locally.
+    pub handshake_rtt: Option<Duration>,
+    /// The smoothed RTT of the connection after this segment.
+    pub smoothed_rtt: Option<Duration>,
+    /// Payload bytes sent before.
+    pub retransmitted_bytes: u128,
+    /// Whether the segment skips sequence numbers, due to reordering or loss.
+    pub out_of_order: bool,
+}
+
+/// Path quality of a connection within a utilization window.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TcpPerformance {
+    pub handshake_rtt: Option<Duration>,
+    /// The last smoothed RTT within the window.
+    pub smoothed_rtt: Option<Duration>,
+    pub retransmitted_bytes_downloaded: u128,
+    pub retransmitted_bytes_uploaded: u128,
+    pub out_of_order_segments_downloaded: u128,
+    pub out_of_order_segments_uploaded: u128,
+}
+
+impl TcpPerformance {
+    pub fn update(&mut self, sample: &TcpSample, direction: &Direction) {
+        self.handshake_rtt = sample.handshake_rtt.or(self.handshake_rtt);
+        self.smoothed_rtt = sample.smoothed_rtt.or(self.smoothed_rtt);
+        let out_of_order = u128::from(sample.out_of_order);
+        match direction {
+            Direction::Download => {
+                self.retransmitted_bytes_downloaded += sample.retransmitted_bytes;
+                self.out_of_order_segments_downloaded += out_of_order;
+            }
+            Direction::Upload => {
+                self.retransmitted_bytes_uploaded += sample.retransmitted_bytes;
+                self.out_of_order_segments_uploaded += out_of_order;
+            }
+        }
+    }
+    pub fn merge(&mut self, other: &TcpPerformance) {
+        self.handshake_rtt = self.handshake_rtt.or(other.handshake_rtt);
+        self.smoothed_rtt = other.smoothed_rtt.or(self.smoothed_rtt);
+        self.retransmitted_bytes_downloaded += other.retransmitted_bytes_downloaded;
+        self.retransmitted_bytes_uploaded += other.retransmitted_bytes_uploaded;
+        self.out_of_order_segments_downloaded += other.out_of_order_segments_downloaded;
+        self.out_of_order_segments_uploaded += other.out_of_order_segments_uploaded;
+    }
+}
+
+/// A handshake segment sent locally, awaiting the answer of the remote side.
+#[derive(Clone, Copy)]
+enum Handshake {
+    AwaitingSynAck(SystemTime),
+    AwaitingAck(SystemTime),
+}
+
+#[derive(Default)]
+struct TcpState {
+    /// The next expected sequence number of each direction.
+    next_sequence_downloaded: Option<u32>,
+    next_sequence_uploaded: Option<u32>,
+    handshake: Option<Handshake>,
+    /// The end of an uploaded segment and when it was sent, awaiting its acknowledgement.
+    timed_segment: Option<(u32, SystemTime)>,
+    smoothed_rtt: Option<Duration>,
+}
+
+impl TcpState {
+    fn sample_rtt(&mut self, rtt: Duration) {
+        self.smoothed_rtt = Some(match self.smoothed_rtt {
+            None => rtt,
+            Some(smoothed_rtt) => {
+                (smoothed_rtt * (RTT_SAMPLE_WEIGHT - 1) + rtt) / RTT_SAMPLE_WEIGHT
+            }
+        });
+    }
+}
+
+/// Bounded table of TCP connections, estimating RTTs from the local point of view and spotting
+/// retransmissions by sequence numbers.
+///
+/// RTTs are sampled from uploaded segments and their acknowledgements only, so they include
+/// the remote side's delay but not the local one's. Segments filling a gap left by reordering
+/// are counted as retransmitted, as both are indistinguishable without timing heuristics.
+pub struct TcpTracker {
+    connections: ExpiringMap<Connection, TcpState>,
+}
+
+impl TcpTracker {
+    pub fn new() -> Self {
+        TcpTracker {
+            connections: ExpiringMap::new(MAXIMUM_NUMBER_OF_CONNECTIONS, CONNECTION_TIMEOUT),
+        }
+    }
+    pub fn observe(
+        &mut self,
+        connection: &Connection,
+        direction: &Direction,
+        segment: &TcpPacket,
+        timestamp: SystemTime,
+    ) -> TcpSample {
+        self.maintain(timestamp);
+        let flags = segment.get_flags();
+        if flags & TcpFlags::RST != 0 {
+            self.connections.remove(connection);
+            return TcpSample::default();
+        }
+        let state =
+            match self
+                .connections
+                .get_or_insert_with(*connection, timestamp, TcpState::default)
+            {
+                Some(state) => state,
+                None => return TcpSample::default(),
+            };
+
+        let syn = flags & TcpFlags::SYN != 0;
+        let ack = flags & TcpFlags::ACK != 0;
+        let mut sample = TcpSample::default();
+        let mut handshake_rtt = None;
+        match (direction, state.handshake) {
+            (Direction::Upload, None) if syn => {
+                state.handshake = Some(if ack {
+                    Handshake::AwaitingAck(timestamp)
+                } else {
+                    Handshake::AwaitingSynAck(timestamp)
+                });
+            }
+            (Direction::Upload, Some(_)) if syn => {
+                // Retransmitted, the answer would be ambiguous.
+                state.handshake = None;
+            }
+            (Direction::Download, Some(Handshake::AwaitingSynAck(sent))) if syn && ack => {
+                handshake_rtt = timestamp.duration_since(sent).ok();
+                state.handshake = None;
+            }
+            (Direction::Download, Some(Handshake::AwaitingAck(sent))) if !syn && ack => {
+                handshake_rtt = timestamp.duration_since(sent).ok();
+                state.handshake = None;
+            }
+            _ => {}
+        }
+        if let Some(rtt) = handshake_rtt {
+            sample.handshake_rtt = Some(rtt);
+            state.sample_rtt(rtt);
+        }
+
+        let payload_length = segment.payload().len() as u32;
+        let sequence_length =
+            payload_length + u32::from(syn) + u32::from(flags & TcpFlags::FIN != 0);
+        let sequence = segment.get_sequence();
+        let next_sequence = match direction {
+            Direction::Download => &mut state.next_sequence_downloaded,
+            Direction::Upload => &mut state.next_sequence_uploaded,
+        };
+        let (retransmitted_bytes, out_of_order) =
+            track_sequence(next_sequence, sequence, sequence_length, payload_length);
+        sample.retransmitted_bytes = u128::from(retransmitted_bytes);
+        sample.out_of_order = out_of_order;
+
+        match direction {
+            Direction::Upload if retransmitted_bytes > 0 => state.timed_segment = None,
+            Direction::Upload if payload_length > 0 && state.timed_segment.is_none() => {
+                state.timed_segment = Some((sequence.wrapping_add(sequence_length), timestamp));
+            }
+            Direction::Download if ack => {
+                if let Some((end, sent)) = state.timed_segment {
+                    if sequence_distance(end, segment.get_acknowledgement()) >= 0 {
+                        if let Ok(rtt) = timestamp.duration_since(sent) {
+                            state.sample_rtt(rtt);
+                        }
+                        state.timed_segment = None;
+                    }
+                }
+            }
+            _ => {}
+        }
+        sample.smoothed_rtt = state.smoothed_rtt;
+        sample
+    }
+    /// Forgets connections idle for longer than the timeout.
+    pub fn maintain(&mut self, now: SystemTime) {
+        self.connections.expire(now, drop);
+    }
+}
+
+/// Advances the next expected sequence number of a direction, returning the retransmitted
+/// payload bytes and whether sequence numbers were skipped.
+fn track_sequence(
+    next_sequence: &mut Option<u32>,
+    sequence: u32,
+    sequence_length: u32,
+    payload_length: u32,
+) -> (u32, bool) {
+    let end = sequence.wrapping_add(sequence_length);
+    let expected = match *next_sequence {
+        Some(expected) => expected,
+        None => {
+            *next_sequence = Some(end);
+            return (0, false);
+        }
+    };
+    if sequence_length == 0 {
+        return (0, false);
+    }
+    let distance = sequence_distance(expected, sequence);
+    if distance >= 0 {
+        *next_sequence = Some(end);
+        return (0, distance > 0);
+    }
+    if sequence_distance(expected, end) <= 0 {
+        (payload_length, false)
+    } else {
+        *next_sequence = Some(end);
+        (distance.unsigned_abs().min(payload_length), false)
+    }
+}
+
+/// How far `sequence` is ahead of `reference`, respecting wrap around.
+fn sequence_distance(reference: u32, sequence: u32) -> i32 {
+    sequence.wrapping_sub(reference) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::network::fixtures::{at_millis, connection, tcp};
+
+    use super::*;
+
+    const SYN: u8 = 0x02;
+    const ACK: u8 = 0x10;
+    const SYN_ACK: u8 = SYN | ACK;
+
+    fn observe(
+        tracker: &mut TcpTracker,
+        direction: Direction,
+        millis: u64,
+        sequence: u32,
+        acknowledgement: u32,
+        flags: u8,
+        payload_length: usize,
+    ) -> TcpSample {
+        let bytes = tcp(
+            40000,
+            443,
+            sequence,
+            acknowledgement,
+            flags,
+            &vec![0; payload_length],
+        );
+        tracker.observe(
+            &connection(),
+            &direction,
+            &TcpPacket::new(&bytes).unwrap(),
+            at_millis(millis),
+        )
+    }
+
+    #[test]
+    fn should_estimate_handshake_and_smoothed_rtt() {
+        // given
+        let mut tracker = TcpTracker::new();
+
+        // when
+        observe(&mut tracker, Direction::Upload, 0, 1000, 0, SYN, 0);
+        let handshake = observe(
+            &mut tracker,
+            Direction::Download,
+            20,
+            5000,
+            1001,
+            SYN_ACK,
+            0,
+        );
+        observe(&mut tracker, Direction::Upload, 100, 1001, 5001, ACK, 100);
+        observe(&mut tracker, Direction::Upload, 110, 1101, 5001, ACK, 100);
+        let acknowledgement = observe(&mut tracker, Direction::Download, 130, 5001, 1101, ACK, 0);
+
+        // then
+        assert_eq!(handshake.handshake_rtt, Some(Duration::from_millis(20)));
+        assert_eq!(handshake.smoothed_rtt, Some(Duration::from_millis(20)));
+        assert_eq!(acknowledgement.handshake_rtt, None);
+        assert_eq!(
+            acknowledgement.smoothed_rtt,
+            Some(Duration::from_micros(21_250))
+        );
+    }
+
+    #[test]
+    fn should_not_sample_rtt_of_retransmitted_segments() {
+        // given
+        let mut tracker = TcpTracker::new();
+        observe(&mut tracker, Direction::Upload, 0, 1001, 5001, ACK, 100);
+
+        // when
+        let retransmission = observe(&mut tracker, Direction::Upload, 200, 1001, 5001, ACK, 100);
+        let acknowledgement = observe(&mut tracker, Direction::Download, 210, 5001, 1101, ACK, 0);
+
+        // then
+        assert_eq!(retransmission.retransmitted_bytes, 100);
+        assert_eq!(acknowledgement.smoothed_rtt, None);
+    }
+
+    #[test]
+    fn should_count_skipped_sequence_numbers_as_out_of_order() {
+        // given
+        let mut tracker = TcpTracker::new();
+        observe(&mut tracker, Direction::Download, 0, 1, 1, ACK, 100);
+
+        // when
+        let skipping = observe(&mut tracker, Direction::Download, 1, 201, 1, ACK, 100);
+        let filling = observe(&mut tracker, Direction::Download, 2, 101, 1, ACK, 100);
+        let overlapping = observe(&mut tracker, Direction::Download, 3, 251, 1, ACK, 100);
+
+        // then
+        assert!(skipping.out_of_order);
+        assert_eq!(skipping.retransmitted_bytes, 0);
+        assert!(!filling.out_of_order);
+        assert_eq!(filling.retransmitted_bytes, 100);
+        assert_eq!(overlapping.retransmitted_bytes, 50);
+    }
+
+    #[test]
+    fn should_track_sequence_numbers_across_wrap_around() {
+        // given
+        let mut next_sequence = Some(u32::MAX - 49);
+
+        // when
+        let in_order = track_sequence(&mut next_sequence, u32::MAX - 49, 100, 100);
+        let retransmission = track_sequence(&mut next_sequence, u32::MAX - 49, 100, 100);
+
+        // then
+        assert_eq!(in_order, (0, false));
+        assert_eq!(retransmission, (100, false));
+        assert_eq!(next_sequence, Some(50));
+    }
+
+    #[test]
+    fn should_track_locally_initiated_connection() {
+        // given
+        let mut lifecycle = TcpLifecycle::default();
+
+        // when
+        lifecycle.update(TcpFlags::SYN, &Direction::Upload, at_millis(100));
+        lifecycle.update(
+            TcpFlags::SYN | TcpFlags::ACK,
+            &Direction::Download,
+            at_millis(101),
+        );
+        lifecycle.update(TcpFlags::ACK, &Direction::Upload, at_millis(102));
+        lifecycle.update(
+            TcpFlags::FIN | TcpFlags::ACK,
+            &Direction::Download,
+            at_millis(200),
+        );
+        lifecycle.update(
+            TcpFlags::FIN | TcpFlags::ACK,
+            &Direction::Upload,
+            at_millis(201),
+        );
+
+        // then
+        assert_eq!(lifecycle.opened, Some(at_millis(100)));
+        assert_eq!(lifecycle.closed, Some(at_millis(201)));
+        assert_eq!(lifecycle.role, Some(ConnectionRole::Client));
+        assert_eq!(lifecycle.resets, 0);
+        assert_eq!(lifecycle.failed_handshakes, 0);
+    }
+
+    #[test]
+    fn should_take_role_from_syn_ack_if_syn_was_missed() {
+        // given
+        let mut lifecycle = TcpLifecycle::default();
+
+        // when
+        lifecycle.update(
+            TcpFlags::SYN | TcpFlags::ACK,
+            &Direction::Upload,
+            at_millis(101),
+        );
+
+        // then
+        assert_eq!(lifecycle.opened, Some(at_millis(101)));
+        assert_eq!(lifecycle.role, Some(ConnectionRole::Server));
+    }
+
+    #[test]
+    fn should_count_handshakes_answered_by_reset_as_failed() {
+        // given
+        let mut lifecycle = TcpLifecycle::default();
+
+        // when
+        lifecycle.update(TcpFlags::SYN, &Direction::Upload, at_millis(100));
+        lifecycle.update(
+            TcpFlags::RST | TcpFlags::ACK,
+            &Direction::Download,
+            at_millis(101),
+        );
+        lifecycle.update(TcpFlags::RST, &Direction::Download, at_millis(102));
+
+        // then
+        assert_eq!(lifecycle.closed, Some(at_millis(102)));
+        assert_eq!(lifecycle.resets, 2);
+        assert_eq!(lifecycle.failed_handshakes, 1);
+    }
+
+    #[test]
+    fn should_merge_lifecycles_of_the_same_window() {
+        // given
+        let mut lifecycle = TcpLifecycle::default();
+        lifecycle.update(TcpFlags::ACK, &Direction::Upload, at_millis(150));
+        let mut other = TcpLifecycle::default();
+        other.update(TcpFlags::SYN, &Direction::Download, at_millis(100));
+        other.update(TcpFlags::RST, &Direction::Upload, at_millis(120));
+
+        // when
+        lifecycle.merge(&other);
+
+        // then
+        assert_eq!(lifecycle.opened, Some(at_millis(100)));
+        assert_eq!(lifecycle.closed, Some(at_millis(120)));
+        assert_eq!(lifecycle.role, Some(ConnectionRole::Server));
+        assert_eq!(lifecycle.failed_handshakes, 1);
+    }
+
+    #[test]
+    fn should_keep_last_smoothed_rtt_when_merging_performance() {
+        // given
+        let mut performance = TcpPerformance {
+            handshake_rtt: Some(Duration::from_millis(20)),
+            smoothed_rtt: Some(Duration::from_millis(25)),
+            ..TcpPerformance::default()
+        };
+        let later = TcpPerformance {
+            handshake_rtt: Some(Duration::from_millis(40)),
+            smoothed_rtt: Some(Duration::from_millis(30)),
+            retransmitted_bytes_uploaded: 100,
+            ..TcpPerformance::default()
+        };
+
+        // when
+        performance.merge(&later);
+
+        // then
+        assert_eq!(performance.handshake_rtt, Some(Duration::from_millis(20)));
+        assert_eq!(performance.smoothed_rtt, Some(Duration::from_millis(30)));
+        assert_eq!(performance.retransmitted_bytes_uploaded, 100);
+    }
+}
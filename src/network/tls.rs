@@ -0,0 +1,434 @@
+use ::std::time::{Duration, SystemTime};
+
+use crate::network::{Connection, ExpiringMap};
+
+const MAXIMUM_NUMBER_OF_CONNECTIONS: usize = 16384;
+/// Connections are only inspected up to the given number of segments carrying payload.
+const MAXIMUM_NUMBER_OF_INSPECTED_SEGMENTS: u8 = 4;
+const MAXIMUM_CLIENT_HELLO_LENGTH: usize = 16 * 1024;
+/// Data beyond a gap in the stream is only kept in up to the given number of chunks.
+const MAXIMUM_NUMBER_OF_OUT_OF_ORDER_CHUNKS: usize = 16;
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(60);
+const RECORD_HEADER_LENGTH: usize = 5;
+const HANDSHAKE_HEADER_LENGTH: usize = 4;
+const CONTENT_TYPE_HANDSHAKE: u8 = 22;
+const HANDSHAKE_TYPE_CLIENT_HELLO: u8 = 1;
+const RANDOM_LENGTH: usize = 32;
+const EXTENSION_SERVER_NAME: u16 = 0;
+const EXTENSION_APPLICATION_LAYER_PROTOCOL_NEGOTIATION: u16 = 16;
+const SERVER_NAME_TYPE_HOST_NAME: u8 = 0;
+
+/// What a client announced in the ClientHello of a TLS connection.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TlsClientHello {
+    pub server_name: Option<String>,
+    /// The offered application protocols, in order of preference.
+    pub alpn: Vec<String>,
+}
+
+/// How the ClientHello is framed within the inspected stream.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    /// TLS records of a TCP stream.
+    Records,
+    /// Bare handshake messages of the CRYPTO frames of QUIC Initial packets, RFC 9001.
+    Handshake,
+}
+
+struct PendingClientHello {
+    framing: Framing,
+    buffer: Vec<u8>,
+    /// Offsets and data beyond the end of the buffer, kept until the gap before them is filled.
+    out_of_order: Vec<(usize, Vec<u8>)>,
+    /// The sequence number of the first buffered byte of TCP streams.
+    origin: u32,
+    segments: u8,
+}
+
+impl PendingClientHello {
+    fn new(framing: Framing, origin: u32) -> Self {
+        PendingClientHello {
+            framing,
+            buffer: vec![],
+            out_of_order: vec![],
+            origin,
+            segments: 0,
+        }
+    }
+    /// Adds stream data to the buffer once all data before it is buffered.
+    fn add(&mut self, offset: usize, data: &[u8]) {
+        self.out_of_order.push((offset, data.to_vec()));
+        while let Some(position) = self
+            .out_of_order
+            .iter()
+            .position(|(offset, _)| *offset <= self.buffer.len())
+        {
+            let (offset, data) = self.out_of_order.swap_remove(position);
+            let buffered = self.buffer.len();
+            if offset + data.len() > buffered {
+                self.buffer.extend_from_slice(&data[buffered - offset..]);
+            }
+        }
+    }
+}
+
+struct InspectedConnection {
+    /// `None` once the inspection is done.
+    pending: Option<PendingClientHello>,
+}
+
+impl InspectedConnection {
+    /// Adds the stream data of a segment at the given offsets, returning the ClientHello once
+    /// complete.
+    fn inspect<'a>(
+        &mut self,
+        chunks: impl IntoIterator<Item = (u64, &'a [u8])>,
+    ) -> Option<TlsClientHello> {
+        let pending = self.pending.as_mut()?;
+        for (offset, data) in chunks {
+            if offset + data.len() as u64 > MAXIMUM_CLIENT_HELLO_LENGTH as u64
+                || pending.out_of_order.len() >= MAXIMUM_NUMBER_OF_OUT_OF_ORDER_CHUNKS
+            {
+                self.pending = None;
+                return None;
+            }
+            pending.add(offset as usize, data);
+        }
+        pending.segments += 1;
+        let message = match pending.framing {
+            Framing::Records => client_hello_message(&pending.buffer),
+            Framing::Handshake => handshake_message(&pending.buffer),
+        };
+        match message {
+            Some(message) => {
+                let client_hello = parse_client_hello(message);
+                self.pending = None;
+                client_hello
+            }
+            None => {
+                if pending.segments >= MAXIMUM_NUMBER_OF_INSPECTED_SEGMENTS
+                    || pending.buffer.len() >= MAXIMUM_CLIENT_HELLO_LENGTH
+                {
+                    self.pending = None;
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Bounded table of the connections whose first uploaded segments are inspected for a ClientHello.
+///
+/// ClientHello messages spanning several segments or QUIC packets are reassembled, even if they
+/// arrive out of order.
+pub struct TlsInspector {
+    connections: ExpiringMap<Connection, InspectedConnection>,
+}
+
+impl TlsInspector {
+    pub fn new() -> Self {
+        TlsInspector {
+            connections: ExpiringMap::new(MAXIMUM_NUMBER_OF_CONNECTIONS, CONNECTION_TIMEOUT),
+        }
+    }
+    /// Inspects the payload of an uploaded TCP segment, returning the ClientHello once complete.
+    pub fn observe(
+        &mut self,
+        connection: &Connection,
+        sequence_number: u32,
+        payload: &[u8],
+        timestamp: SystemTime,
+    ) -> Option<TlsClientHello> {
+        if payload.is_empty() {
+            return None;
+        }
+        let inspected = self.inspected(connection, timestamp, || {
+            if starts_client_hello(payload) {
+                Some(PendingClientHello::new(Framing::Records, sequence_number))
+            } else {
+                None
+            }
+        })?;
+        let offset = sequence_number.wrapping_sub(inspected.pending.as_ref()?.origin);
+        if offset > i32::MAX as u32 {
+            // Retransmitted
+            return None;
+        }
+        inspected.inspect(Some((u64::from(offset), payload)))
+    }
+    /// Inspects the CRYPTO frames of a client Initial packet, returning the ClientHello once
+    /// complete.
+    pub fn observe_crypto_frames(
+        &mut self,
+        connection: &Connection,
+        crypto_frames: &[(u64, Vec<u8>)],
+        timestamp: SystemTime,
+    ) -> Option<TlsClientHello> {
+        if crypto_frames.is_empty() {
+            return None;
+        }
+        let inspected = self.inspected(connection, timestamp, || {
+            Some(PendingClientHello::new(Framing::Handshake, 0))
+        })?;
+        inspected.inspect(
+            crypto_frames
+                .iter()
+                .map(|(offset, data)| (*offset, data.as_slice())),
+        )
+    }
+    fn inspected(
+        &mut self,
+        connection: &Connection,
+        timestamp: SystemTime,
+        pending: impl FnOnce() -> Option<PendingClientHello>,
+    ) -> Option<&mut InspectedConnection> {
+        self.maintain(timestamp);
+        self.connections
+            .get_or_insert_with(*connection, timestamp, || InspectedConnection {
+                pending: pending(),
+            })
+    }
+    /// Forgets connections not seen within the timeout.
+    pub fn maintain(&mut self, now: SystemTime) {
+        self.connections.expire(now, drop);
+    }
+}
+
+fn starts_client_hello(payload: &[u8]) -> bool {
+    payload.first() == Some(&CONTENT_TYPE_HANDSHAKE)
+        && payload.get(RECORD_HEADER_LENGTH) == Some(&HANDSHAKE_TYPE_CLIENT_HELLO)
+}
+
+/// Returns the body of the ClientHello once the buffered records contain it completely.
+///
+/// ClientHello messages fragmented across several records are not supported.
+fn client_hello_message(buffer: &[u8]) -> Option<&[u8]> {
+    let record_length = usize::from(u16::from_be_bytes([*buffer.get(3)?, *buffer.get(4)?]));
+    handshake_message(buffer.get(RECORD_HEADER_LENGTH..RECORD_HEADER_LENGTH + record_length)?)
+}
+
+/// Returns the body of the ClientHello once the buffered handshake messages contain it completely.
+fn handshake_message(buffer: &[u8]) -> Option<&[u8]> {
+    if *buffer.first()? != HANDSHAKE_TYPE_CLIENT_HELLO {
+        return None;
+    }
+    let length =
+        u32::from_be_bytes([0, *buffer.get(1)?, *buffer.get(2)?, *buffer.get(3)?]) as usize;
+    buffer.get(HANDSHAKE_HEADER_LENGTH..HANDSHAKE_HEADER_LENGTH + length)
+}
+
+/// Extracts the server name (RFC 6066) and the ALPN protocols (RFC 7301) of a ClientHello body.
+fn parse_client_hello(message: &[u8]) -> Option<TlsClientHello> {
+    let mut reader = Reader(message);
+    reader.skip(2 + RANDOM_LENGTH)?;
+    reader.vector(1)?;
+    reader.vector(2)?;
+    reader.vector(1)?;
+    let mut extensions = Reader(reader.vector(2)?);
+    let mut client_hello = TlsClientHello::default();
+    while !extensions.0.is_empty() {
+        let extension_type = extensions.u16()?;
+        let mut data = Reader(extensions.vector(2)?);
+        match extension_type {
+            EXTENSION_SERVER_NAME => {
+                let mut names = Reader(data.vector(2)?);
+                while !names.0.is_empty() {
+                    let name_type = names.u8()?;
+                    let name = names.vector(2)?;
+                    if name_type == SERVER_NAME_TYPE_HOST_NAME {
+                        client_hello.server_name =
+                            Some(String::from_utf8_lossy(name).to_ascii_lowercase());
+                    }
+                }
+            }
+            EXTENSION_APPLICATION_LAYER_PROTOCOL_NEGOTIATION => {
+                let mut protocols = Reader(data.vector(2)?);
+                while !protocols.0.is_empty() {
+                    let protocol = protocols.vector(1)?;
+                    client_hello
+                        .alpn
+                        .push(String::from_utf8_lossy(protocol).into_owned());
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(client_hello)
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        let taken = self.0.get(..length)?;
+        self.0 = &self.0[length..];
+        Some(taken)
+    }
+    fn skip(&mut self, length: usize) -> Option<()> {
+        self.take(length).map(|_| ())
+    }
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+    /// A vector prefixed by its length of the given number of bytes.
+    fn vector(&mut self, length_bytes: usize) -> Option<&'a [u8]> {
+        let length = match length_bytes {
+            1 => usize::from(self.u8()?),
+            _ => usize::from(self.u16()?),
+        };
+        self.take(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::network::fixtures::{at, connection};
+
+    use super::*;
+
+    fn vector(length_bytes: usize, content: &[u8]) -> Vec<u8> {
+        let length = (content.len() as u16).to_be_bytes();
+        [&length[2 - length_bytes..], content].concat()
+    }
+
+    fn extension(extension_type: u16, data: &[u8]) -> Vec<u8> {
+        [&extension_type.to_be_bytes()[..], &vector(2, data)].concat()
+    }
+
+    fn client_hello_record(extensions: &[u8]) -> Vec<u8> {
+        let body = [
+            &[0x03, 0x03][..],
+            &[0; 32],
+            &vector(1, &[0; 32]),
+            &vector(2, &[0x13, 0x01, 0x13, 0x02]),
+            &vector(1, &[0]),
+            &vector(2, extensions),
+        ]
+        .concat();
+        let length = (body.len() as u32).to_be_bytes();
+        let handshake = [&[1, length[1], length[2], length[3]][..], &body].concat();
+        [&[22, 0x03, 0x01][..], &vector(2, &handshake)].concat()
+    }
+
+    fn client_hello_of_example() -> Vec<u8> {
+        let server_name = vector(2, &[&[0][..], &vector(2, b"WWW.example.com")].concat());
+        let alpn = vector(2, &[vector(1, b"h2"), vector(1, b"http/1.1")].concat());
+        client_hello_record(
+            &[
+                extension(0x000a, &[0, 2, 0, 0x1d]),
+                extension(EXTENSION_SERVER_NAME, &server_name),
+                extension(EXTENSION_APPLICATION_LAYER_PROTOCOL_NEGOTIATION, &alpn),
+            ]
+            .concat(),
+        )
+    }
+
+    fn expected() -> Option<TlsClientHello> {
+        Some(TlsClientHello {
+            server_name: Some("www.example.com".to_string()),
+            alpn: vec!["h2".to_string(), "http/1.1".to_string()],
+        })
+    }
+
+    #[test]
+    fn should_extract_server_name_and_alpn() {
+        // given
+        let mut inspector = TlsInspector::new();
+
+        // when
+        let result = inspector.observe(&connection(), 1, &client_hello_of_example(), at(100));
+
+        // then
+        assert_eq!(result, expected());
+    }
+
+    #[test]
+    fn should_reassemble_client_hello_spanning_segments() {
+        // given
+        let mut inspector = TlsInspector::new();
+        let record = client_hello_of_example();
+        let (first, second) = record.split_at(40);
+
+        // when
+        let partial = inspector.observe(&connection(), 1, first, at(100));
+        let retransmitted = inspector.observe(&connection(), 1, first, at(100));
+        let complete = inspector.observe(&connection(), 41, second, at(100));
+
+        // then
+        assert_eq!(partial, None);
+        assert_eq!(retransmitted, None);
+        assert_eq!(complete, expected());
+    }
+
+    #[test]
+    fn should_extract_client_hello_of_quic_crypto_frames() {
+        // given
+        let mut inspector = TlsInspector::new();
+        let handshake = client_hello_of_example().split_off(RECORD_HEADER_LENGTH);
+        let (first, second) = handshake.split_at(40);
+
+        // when
+        let partial =
+            inspector.observe_crypto_frames(&connection(), &[(0, first.to_vec())], at(100));
+        let complete = inspector.observe_crypto_frames(
+            &connection(),
+            &[(0, first.to_vec()), (40, second.to_vec())],
+            at(100),
+        );
+
+        // then
+        assert_eq!(partial, None);
+        assert_eq!(complete, expected());
+    }
+
+    #[test]
+    fn should_reassemble_quic_crypto_frames_arriving_out_of_order() {
+        // given
+        let mut inspector = TlsInspector::new();
+        let handshake = client_hello_of_example().split_off(RECORD_HEADER_LENGTH);
+        let (first, rest) = handshake.split_at(30);
+        let (second, third) = rest.split_at(20);
+
+        // when
+        let last = inspector.observe_crypto_frames(&connection(), &[(50, third.to_vec())], at(100));
+        let middle =
+            inspector.observe_crypto_frames(&connection(), &[(30, second.to_vec())], at(100));
+        let complete =
+            inspector.observe_crypto_frames(&connection(), &[(0, first.to_vec())], at(100));
+
+        // then
+        assert_eq!(last, None);
+        assert_eq!(middle, None);
+        assert_eq!(complete, expected());
+    }
+
+    #[test]
+    fn should_only_inspect_first_segments() {
+        // given
+        let mut inspector = TlsInspector::new();
+        inspector.observe(&connection(), 1, b"GET / HTTP/1.1\r\n", at(100));
+
+        // when
+        let result = inspector.observe(&connection(), 17, &client_hello_of_example(), at(100));
+
+        // then
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn should_tolerate_malformed_extensions() {
+        // given
+        let mut inspector = TlsInspector::new();
+        let record = client_hello_record(&[0, 0, 0, 9, 0]);
+
+        // when
+        let result = inspector.observe(&connection(), 1, &record, at(100));
+
+        // then
+        assert_eq!(result, None);
+    }
+}
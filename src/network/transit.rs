@@ -0,0 +1,50 @@
+use ::std::net::SocketAddr;
+
+use crate::network::{Protocol, Segment, Socket};
+
+/// Traffic between two hosts other than this one, keyed by its source and destination.
+#[derive(PartialEq, Hash, Eq, Clone, PartialOrd, Ord, Debug, Copy)]
+pub struct TransitFlow {
+    pub source: Socket,
+    pub destination: Socket,
+    pub protocol: Protocol,
+}
+
+impl TransitFlow {
+    pub fn new(source: SocketAddr, destination: SocketAddr, protocol: Protocol) -> Self {
+        TransitFlow {
+            source: Socket {
+                ip: source.ip(),
+                port: source.port(),
+            },
+            destination: Socket {
+                ip: destination.ip(),
+                port: destination.port(),
+            },
+            protocol,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TransitFlowInfo {
+    pub interface_name: String,
+    pub vlan_ids: Vec<u16>,
+    pub total_bytes: u128,
+    /// Bytes following the transport layer headers, part of the total bytes.
+    pub total_payload_bytes: u128,
+    pub total_packets: u128,
+}
+
+impl TransitFlowInfo {
+    pub fn update(&mut self, seg: &Segment) {
+        self.total_bytes += seg.data_length;
+        self.total_payload_bytes += seg.payload_length;
+        self.total_packets += seg.packets;
+    }
+    pub fn merge(&mut self, other: &TransitFlowInfo) {
+        self.total_bytes += other.total_bytes;
+        self.total_payload_bytes += other.total_payload_bytes;
+        self.total_packets += other.total_packets;
+    }
+}
@@ -0,0 +1,264 @@
+use ::std::net::IpAddr;
+
+use ::pnet::packet::ethernet::{EtherType, EtherTypes};
+
+use crate::network::{decode_link_layer, LinkType, Protocol};
+
+pub const DEFAULT_VXLAN_PORT: u16 = 4789;
+pub const DEFAULT_GENEVE_PORT: u16 = 6081;
+const PROTOCOL_GRE: u8 = 47;
+const ETHERTYPE_TRANSPARENT_ETHERNET_BRIDGING: EtherType = EtherType(0x6558);
+const UDP_HEADER_LENGTH: usize = 8;
+const VXLAN_HEADER_LENGTH: usize = 8;
+const VXLAN_VALID_VNI: u8 = 0x08;
+const GENEVE_HEADER_LENGTH: usize = 8;
+const GRE_HEADER_LENGTH: usize = 4;
+const GRE_OPTIONAL_FIELD_LENGTH: usize = 4;
+const GRE_CHECKSUM_PRESENT: u16 = 0x8000;
+const GRE_KEY_PRESENT: u16 = 0x2000;
+const GRE_SEQUENCE_NUMBER_PRESENT: u16 = 0x1000;
+const GRE_VERSION: u16 = 0x0007;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum TunnelKind {
+    Vxlan,
+    Geneve,
+    Gre,
+}
+
+/// The tunnel an inner flow has been decapsulated from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Tunnel {
+    pub kind: TunnelKind,
+    pub remote_endpoint: IpAddr,
+    /// The VXLAN or Geneve network identifier, or the GRE key.
+    pub vni: Option<u32>,
+}
+
+/// The tunnel protocols decapsulated on an interface.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Decapsulation {
+    pub vxlan_port: u16,
+    pub geneve_port: u16,
+}
+
+impl Default for Decapsulation {
+    fn default() -> Self {
+        Decapsulation {
+            vxlan_port: DEFAULT_VXLAN_PORT,
+            geneve_port: DEFAULT_GENEVE_PORT,
+        }
+    }
+}
+
+/// The network layer packet carried by a tunnel.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Decapsulated<'a> {
+    pub kind: TunnelKind,
+    pub vni: Option<u32>,
+    pub ethertype: EtherType,
+    pub payload: &'a [u8],
+}
+
+impl Decapsulation {
+    /// Unwraps the given transport layer packet if it belongs to a tunnel.
+    pub fn decapsulate<'a>(
+        &self,
+        protocol: Protocol,
+        destination_port: u16,
+        transport: &'a [u8],
+    ) -> Option<Decapsulated<'a>> {
+        match protocol {
+            Protocol::Udp if destination_port == self.vxlan_port => {
+                vxlan(transport.get(UDP_HEADER_LENGTH..)?)
+            }
+            Protocol::Udp if destination_port == self.geneve_port => {
+                geneve(transport.get(UDP_HEADER_LENGTH..)?)
+            }
+            Protocol::Other(PROTOCOL_GRE) => gre(transport),
+            _ => None,
+        }
+    }
+}
+
+/// RFC 7348
+fn vxlan(packet: &[u8]) -> Option<Decapsulated<'_>> {
+    let header = packet.get(..VXLAN_HEADER_LENGTH)?;
+    if header[0] & VXLAN_VALID_VNI == 0 {
+        return None;
+    }
+    encapsulated(
+        TunnelKind::Vxlan,
+        Some(u32::from_be_bytes([0, header[4], header[5], header[6]])),
+        ETHERTYPE_TRANSPARENT_ETHERNET_BRIDGING,
+        &packet[VXLAN_HEADER_LENGTH..],
+    )
+}
+
+/// RFC 8926
+fn geneve(packet: &[u8]) -> Option<Decapsulated<'_>> {
+    let header = packet.get(..GENEVE_HEADER_LENGTH)?;
+    if header[0] >> 6 != 0 {
+        return None;
+    }
+    let options_length = usize::from(header[0] & 0x3f) * 4;
+    encapsulated(
+        TunnelKind::Geneve,
+        Some(u32::from_be_bytes([0, header[4], header[5], header[6]])),
+        EtherType(u16::from_be_bytes([header[2], header[3]])),
+        packet.get(GENEVE_HEADER_LENGTH + options_length..)?,
+    )
+}
+
+/// RFC 2784 and RFC 2890, the key being reported as network identifier.
+fn gre(packet: &[u8]) -> Option<Decapsulated<'_>> {
+    let header = packet.get(..GRE_HEADER_LENGTH)?;
+    let flags = u16::from_be_bytes([header[0], header[1]]);
+    if flags & GRE_VERSION != 0 {
+        return None;
+    }
+    let mut offset = GRE_HEADER_LENGTH;
+    if flags & GRE_CHECKSUM_PRESENT != 0 {
+        offset += GRE_OPTIONAL_FIELD_LENGTH;
+    }
+    let mut key = None;
+    if flags & GRE_KEY_PRESENT != 0 {
+        let field = packet.get(offset..offset + GRE_OPTIONAL_FIELD_LENGTH)?;
+        key = Some(u32::from_be_bytes([field[0], field[1], field[2], field[3]]));
+        offset += GRE_OPTIONAL_FIELD_LENGTH;
+    }
+    if flags & GRE_SEQUENCE_NUMBER_PRESENT != 0 {
+        offset += GRE_OPTIONAL_FIELD_LENGTH;
+    }
+    encapsulated(
+        TunnelKind::Gre,
+        key,
+        EtherType(u16::from_be_bytes([header[2], header[3]])),
+        packet.get(offset..)?,
+    )
+}
+
+fn encapsulated(
+    kind: TunnelKind,
+    vni: Option<u32>,
+    ethertype: EtherType,
+    payload: &[u8],
+) -> Option<Decapsulated<'_>> {
+    let (ethertype, payload) = if ethertype == ETHERTYPE_TRANSPARENT_ETHERNET_BRIDGING {
+        decode_link_layer(LinkType::Ethernet, payload, &mut vec![])?
+    } else {
+        (ethertype, payload)
+    };
+    match ethertype {
+        EtherTypes::Ipv4 | EtherTypes::Ipv6 => Some(Decapsulated {
+            kind,
+            vni,
+            ethertype,
+            payload,
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::network::fixtures::{ethernet, udp, ETHERTYPE_IPV4};
+
+    use super::*;
+
+    const INNER_PACKET: [u8; 4] = [0x45, 0, 0, 0];
+
+    fn decapsulate(
+        protocol: Protocol,
+        destination_port: u16,
+        transport: &[u8],
+    ) -> Option<Decapsulated<'_>> {
+        Decapsulation::default().decapsulate(protocol, destination_port, transport)
+    }
+
+    fn expected(kind: TunnelKind, vni: Option<u32>) -> Option<Decapsulated<'static>> {
+        Some(Decapsulated {
+            kind,
+            vni,
+            ethertype: EtherTypes::Ipv4,
+            payload: &INNER_PACKET,
+        })
+    }
+
+    #[test]
+    fn should_decapsulate_vxlan() {
+        // given
+        let header = [0x08, 0, 0, 0, 0, 0x10, 0x01, 0];
+        let datagram = udp(
+            54321,
+            4789,
+            &[&header[..], &ethernet(ETHERTYPE_IPV4, &INNER_PACKET)].concat(),
+        );
+
+        // when
+        let result = decapsulate(Protocol::Udp, 4789, &datagram);
+
+        // then
+        assert_eq!(result, expected(TunnelKind::Vxlan, Some(4097)));
+    }
+
+    #[test]
+    fn should_ignore_vxlan_without_valid_vni() {
+        // given
+        let header = [0, 0, 0, 0, 0, 0x10, 0x01, 0];
+        let datagram = udp(
+            54321,
+            4789,
+            &[&header[..], &ethernet(ETHERTYPE_IPV4, &INNER_PACKET)].concat(),
+        );
+
+        // when
+        let result = decapsulate(Protocol::Udp, 4789, &datagram);
+
+        // then
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn should_decapsulate_geneve_with_options() {
+        // given
+        let header = [0x01, 0, 0x08, 0x00, 0, 0, 0x2a, 0, 1, 2, 3, 4];
+        let datagram = udp(54321, 6081, &[&header[..], &INNER_PACKET].concat());
+
+        // when
+        let result = decapsulate(Protocol::Udp, 6081, &datagram);
+
+        // then
+        assert_eq!(result, expected(TunnelKind::Geneve, Some(42)));
+    }
+
+    #[test]
+    fn should_decapsulate_gre_with_key() {
+        // given
+        let header = [0xb0, 0, 0x65, 0x58, 0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 1];
+        let packet = [&header[..], &ethernet(ETHERTYPE_IPV4, &INNER_PACKET)].concat();
+
+        // when
+        let result = decapsulate(Protocol::Other(PROTOCOL_GRE), 0, &packet);
+
+        // then
+        assert_eq!(result, expected(TunnelKind::Gre, Some(7)));
+    }
+
+    #[test]
+    fn should_not_decapsulate_other_ports() {
+        // given
+        let header = [0x08, 0, 0, 0, 0, 0x10, 0x01, 0];
+        let datagram = udp(
+            4789,
+            53,
+            &[&header[..], &ethernet(ETHERTYPE_IPV4, &INNER_PACKET)].concat(),
+        );
+
+        // when
+        let result = decapsulate(Protocol::Udp, 53, &datagram);
+
+        // then
+        assert_eq!(result, None);
+    }
+}
@@ -1,55 +1,380 @@
-use crate::network::{Connection, Direction, Segment};
+use crate::network::{
+    Connection, ConntrackEntry, ConntrackTable, Direction, DnsCache, ForwardedFlow,
+    OrphanedFragments, Process, ProcessCache, QuicSample, Scope, Segment, TcpLifecycle,
+    TcpPerformance, TlsClientHello, TransitFlow, TransitFlowInfo, Tunnel, UnmatchedForwarding,
+};
 
 use ::std::collections::HashMap;
 use std::time::SystemTime;
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct ConnectionInfo {
     pub interface_name: String,
+    pub vlan_ids: Vec<u16>,
+    pub tunnel: Option<Tunnel>,
     pub total_bytes_downloaded: u128,
     pub total_bytes_uploaded: u128,
+    pub total_packets_downloaded: u128,
+    pub total_packets_uploaded: u128,
+    /// Bytes of the accounted headers, part of the total bytes.
+    pub total_header_bytes_downloaded: u128,
+    pub total_header_bytes_uploaded: u128,
+    /// Bytes following the transport layer headers, part of the total bytes.
+    pub total_payload_bytes_downloaded: u128,
+    pub total_payload_bytes_uploaded: u128,
+    /// Bytes of tunnel headers around the connection's traffic.
+    pub total_tunnel_overhead_bytes_downloaded: u128,
+    pub total_tunnel_overhead_bytes_uploaded: u128,
+    /// Set for TCP connections.
+    pub tcp: Option<TcpLifecycle>,
+    /// Set for TCP connections tracked by the sniffer.
+    pub tcp_performance: Option<TcpPerformance>,
+    /// Set for TLS connections whose ClientHello has been seen.
+    pub tls: Option<TlsClientHello>,
+    /// Set for UDP connections recognized as QUIC.
+    pub quic: Option<QuicSample>,
+    /// Names the remote address was queried by, see `Utilization::resolve_remote_names`.
+    pub remote_names: Vec<String>,
+    /// Scope of the connection's traffic, see `Scope`.
+    pub scope: Scope,
+    /// Original and reply tuples, see `Utilization::annotate_conntrack`.
+    pub conntrack: Option<ConntrackEntry>,
+    /// Process owning the local socket when the connection was first seen, see
+    /// `Accumulator::with_process_cache`.
+    pub process: Option<Process>,
+}
+
+#[derive(Clone, Default)]
+pub struct InterfaceInfo {
+    pub total_packets_downloaded: u128,
+    pub total_packets_uploaded: u128,
+    /// Traffic not attributable to any connection.
+    pub orphaned_fragments: OrphanedFragments,
+    /// Forwarded traffic whose other leg has not been seen, see `Forwarding`.
+    pub unmatched_forwarding: UnmatchedForwarding,
+}
+
+impl InterfaceInfo {
+    fn merge(&mut self, other: InterfaceInfo) {
+        self.total_packets_downloaded += other.total_packets_downloaded;
+        self.total_packets_uploaded += other.total_packets_uploaded;
+        self.orphaned_fragments.fragments += other.orphaned_fragments.fragments;
+        self.orphaned_fragments.bytes += other.orphaned_fragments.bytes;
+        self.unmatched_forwarding.packets += other.unmatched_forwarding.packets;
+        self.unmatched_forwarding.bytes += other.unmatched_forwarding.bytes;
+    }
 }
 
 #[derive(Clone)]
 pub struct Utilization {
     pub connections: HashMap<Connection, ConnectionInfo>,
+    pub interfaces: HashMap<String, InterfaceInfo>,
+    /// Traffic between other hosts, see `Sniffer::with_mirror`.
+    pub transit_flows: HashMap<TransitFlow, TransitFlowInfo>,
+    /// Traffic forwarded between interfaces, see `Sniffer::with_forwarding`. The interface of
+    /// the info is the one the traffic was accounted on.
+    pub forwarded_flows: HashMap<ForwardedFlow, TransitFlowInfo>,
     pub start: SystemTime,
     pub stop: SystemTime,
 }
 
 impl Utilization {
     pub fn new() -> Self {
+        Utilization::starting_at(SystemTime::now())
+    }
+    pub fn starting_at(start: SystemTime) -> Self {
         let connections = HashMap::new();
-        let now = SystemTime::now();
         Utilization {
             connections,
-            start: now,
-            stop: now,
+            interfaces: HashMap::new(),
+            transit_flows: HashMap::new(),
+            forwarded_flows: HashMap::new(),
+            start,
+            stop: start,
+        }
+    }
+    pub fn merge(&mut self, other: Utilization) {
+        for (connection, other_info) in other.connections {
+            match self.connections.get_mut(&connection) {
+                Some(info) => {
+                    info.total_bytes_downloaded += other_info.total_bytes_downloaded;
+                    info.total_bytes_uploaded += other_info.total_bytes_uploaded;
+                    info.total_packets_downloaded += other_info.total_packets_downloaded;
+                    info.total_packets_uploaded += other_info.total_packets_uploaded;
+                    info.total_header_bytes_downloaded += other_info.total_header_bytes_downloaded;
+                    info.total_header_bytes_uploaded += other_info.total_header_bytes_uploaded;
+                    info.total_payload_bytes_downloaded +=
+                        other_info.total_payload_bytes_downloaded;
+                    info.total_payload_bytes_uploaded += other_info.total_payload_bytes_uploaded;
+                    info.total_tunnel_overhead_bytes_downloaded +=
+                        other_info.total_tunnel_overhead_bytes_downloaded;
+                    info.total_tunnel_overhead_bytes_uploaded +=
+                        other_info.total_tunnel_overhead_bytes_uploaded;
+                    if let Some(other_tcp) = &other_info.tcp {
+                        info.tcp
+                            .get_or_insert_with(Default::default)
+                            .merge(other_tcp);
+                    }
+                    if let Some(other_performance) = &other_info.tcp_performance {
+                        info.tcp_performance
+                            .get_or_insert_with(Default::default)
+                            .merge(other_performance);
+                    }
+                    if let Some(other_quic) = &other_info.quic {
+                        info.quic
+                            .get_or_insert_with(Default::default)
+                            .merge(other_quic);
+                    }
+                    if info.tls.is_none() {
+                        info.tls = other_info.tls;
+                    }
+                    if info.process.is_none() {
+                        info.process = other_info.process;
+                    }
+                }
+                None => {
+                    self.connections.insert(connection, other_info);
+                }
+            }
+        }
+        for (transit_flow, other_info) in other.transit_flows {
+            match self.transit_flows.get_mut(&transit_flow) {
+                Some(info) => info.merge(&other_info),
+                None => {
+                    self.transit_flows.insert(transit_flow, other_info);
+                }
+            }
+        }
+        for (forwarded_flow, other_info) in other.forwarded_flows {
+            match self.forwarded_flows.get_mut(&forwarded_flow) {
+                Some(info) => info.merge(&other_info),
+                None => {
+                    self.forwarded_flows.insert(forwarded_flow, other_info);
+                }
+            }
+        }
+        for (interface_name, other_info) in other.interfaces {
+            self.interfaces
+                .entry(interface_name)
+                .or_default()
+                .merge(other_info);
+        }
+    }
+    /// Annotates all connections with the names their remote address was known by when the
+    /// utilization window started.
+    pub fn resolve_remote_names(&mut self, dns_cache: &DnsCache) {
+        for (connection, info) in self.connections.iter_mut() {
+            info.remote_names = dns_cache.names(&connection.remote_socket.ip, self.start);
+        }
+    }
+    /// Annotates all connections with the conntrack entry tracking them, if any.
+    pub fn annotate_conntrack(&mut self, conntrack_table: &ConntrackTable) {
+        for (connection, info) in self.connections.iter_mut() {
+            info.conntrack = conntrack_table.lookup(connection).cloned();
+        }
+    }
+    /// Resolves the processes owning the local sockets of connections not attributed yet, e.g.
+    /// of connections measured by conntrack, which are only seen when the window closes.
+    pub fn resolve_processes(&mut self, process_cache: &ProcessCache, now: SystemTime) {
+        for (connection, info) in self.connections.iter_mut() {
+            if info.process.is_none() {
+                info.process = process_cache.resolve(&connection.local_socket, now);
+            }
+        }
+    }
+    /// Resolves the process owning the local socket of a single connection not attributed yet.
+    pub fn resolve_process(
+        &mut self,
+        connection: &Connection,
+        process_cache: &ProcessCache,
+        now: SystemTime,
+    ) {
+        if let Some(info) = self.connections.get_mut(connection) {
+            if info.process.is_none() {
+                info.process = process_cache.resolve(&connection.local_socket, now);
+            }
         }
     }
-    pub fn clone_and_reset(&mut self) -> Self {
-        self.stop = SystemTime::now();
-        let clone = self.clone();
-        self.connections.clear();
-        self.start = self.stop;
-        clone
+    pub fn update_interface(&mut self, interface_name: &str, info: InterfaceInfo) {
+        match self.interfaces.get_mut(interface_name) {
+            Some(interface_info) => interface_info.merge(info),
+            None => {
+                self.interfaces.insert(interface_name.to_string(), info);
+            }
+        }
     }
     pub fn update(&mut self, seg: Segment) {
+        let mut interface_info = InterfaceInfo::default();
+        match seg.direction {
+            Direction::Download => interface_info.total_packets_downloaded = seg.packets,
+            Direction::Upload => interface_info.total_packets_uploaded = seg.packets,
+        }
+        self.update_interface(&seg.interface_name, interface_info);
+        if let Some(transit_flow) = seg.transit {
+            self.transit_flows
+                .entry(transit_flow)
+                .or_insert_with(|| TransitFlowInfo {
+                    interface_name: seg.interface_name.clone(),
+                    vlan_ids: seg.vlan_ids.clone(),
+                    ..TransitFlowInfo::default()
+                })
+                .update(&seg);
+            return;
+        }
+        if let Some(forwarded_flow) = seg.forwarded.clone() {
+            self.forwarded_flows
+                .entry(forwarded_flow)
+                .or_insert_with(|| TransitFlowInfo {
+                    interface_name: seg.interface_name.clone(),
+                    vlan_ids: seg.vlan_ids.clone(),
+                    ..TransitFlowInfo::default()
+                })
+                .update(&seg);
+            return;
+        }
         let total_bandwidth = self
             .connections
             .entry(seg.connection)
             .or_insert(ConnectionInfo {
                 interface_name: seg.interface_name,
+                vlan_ids: seg.vlan_ids,
+                tunnel: seg.tunnel,
                 total_bytes_downloaded: 0,
                 total_bytes_uploaded: 0,
+                total_packets_downloaded: 0,
+                total_packets_uploaded: 0,
+                total_header_bytes_downloaded: 0,
+                total_header_bytes_uploaded: 0,
+                total_payload_bytes_downloaded: 0,
+                total_payload_bytes_uploaded: 0,
+                total_tunnel_overhead_bytes_downloaded: 0,
+                total_tunnel_overhead_bytes_uploaded: 0,
+                tcp: None,
+                tcp_performance: None,
+                tls: None,
+                quic: None,
+                remote_names: vec![],
+                scope: seg.scope,
+                conntrack: None,
+                process: None,
             });
+        if let Some(tcp_flags) = seg.tcp_flags {
+            total_bandwidth
+                .tcp
+                .get_or_insert_with(Default::default)
+                .update(tcp_flags, &seg.direction, seg.timestamp);
+        }
+        if let Some(tcp_sample) = &seg.tcp_sample {
+            total_bandwidth
+                .tcp_performance
+                .get_or_insert_with(Default::default)
+                .update(tcp_sample, &seg.direction);
+        }
+        if let Some(quic) = &seg.quic {
+            total_bandwidth
+                .quic
+                .get_or_insert_with(Default::default)
+                .merge(quic);
+        }
+        if seg.tls_client_hello.is_some() {
+            total_bandwidth.tls = seg.tls_client_hello;
+        }
+        let header_length = seg.data_length.saturating_sub(seg.payload_length);
         match seg.direction {
             Direction::Download => {
                 total_bandwidth.total_bytes_downloaded += seg.data_length;
+                total_bandwidth.total_packets_downloaded += seg.packets;
+                total_bandwidth.total_header_bytes_downloaded += header_length;
+                total_bandwidth.total_payload_bytes_downloaded += seg.payload_length;
+                total_bandwidth.total_tunnel_overhead_bytes_downloaded +=
+                    seg.tunnel_overhead_length;
             }
             Direction::Upload => {
                 total_bandwidth.total_bytes_uploaded += seg.data_length;
+                total_bandwidth.total_packets_uploaded += seg.packets;
+                total_bandwidth.total_header_bytes_uploaded += header_length;
+                total_bandwidth.total_payload_bytes_uploaded += seg.payload_length;
+                total_bandwidth.total_tunnel_overhead_bytes_uploaded += seg.tunnel_overhead_length;
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ::std::collections::HashMap;
+
+    use crate::network::fixtures::{connection, segment};
+    use crate::network::LocalSocket;
+
+    use super::*;
+
+    #[test]
+    fn should_resolve_processes_of_connections_not_attributed_yet() {
+        // given
+        fn socket_inodes() -> HashMap<LocalSocket, u64> {
+            HashMap::from([(connection().local_socket, 7)])
+        }
+        fn inode_processes() -> HashMap<u64, Process> {
+            HashMap::from([(
+                7,
+                Process {
+                    pid: 1234,
+                    name: "curl".to_string(),
+                    container: None,
+                    systemd_unit: None,
+                },
+            )])
+        }
+        let unknown = Connection {
+            local_socket: LocalSocket {
+                port: 40001,
+                ..connection().local_socket
+            },
+            ..connection()
+        };
+        let mut utilization = Utilization::new();
+        utilization.update(segment(connection(), Direction::Upload, 10));
+        utilization.update(segment(unknown, Direction::Upload, 10));
+
+        // when
+        utilization.resolve_processes(
+            &ProcessCache::new(socket_inodes, inode_processes),
+            SystemTime::UNIX_EPOCH,
+        );
+
+        // then
+        let process = |connection: &Connection| utilization.connections[connection].process.clone();
+        assert_eq!(process(&connection()).unwrap().pid, 1234);
+        assert_eq!(process(&unknown), None);
+    }
+
+    #[test]
+    fn should_keep_connections_of_different_tunnel_networks_apart() {
+        // given
+        let mut utilization = Utilization::new();
+
+        // when
+        for vni in [Some(1), Some(2), Some(1)] {
+            utilization.update(segment(
+                Connection {
+                    vni,
+                    ..connection()
+                },
+                Direction::Upload,
+                10,
+            ));
+        }
+
+        // then
+        assert_eq!(utilization.connections.len(), 2);
+        assert_eq!(
+            utilization.connections[&Connection {
+                vni: Some(1),
+                ..connection()
+            }]
+                .total_bytes_uploaded,
+            20
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use ::std::collections::HashMap;
+use ::std::fs;
+
+use ::serde_json::{Map, Value};
+
+use crate::network::{Container, ContainerRuntime};
+
+const CONTAINER_ID_LENGTH: usize = 64;
+
+/// Resolves the containers of processes by their cgroup, once per mount namespace.
+///
+/// Every container has its own mount namespace, so processes sharing the one of the host are
+/// not looked up at all.
+pub(crate) struct ContainerResolver {
+    host_mount_namespace: Option<String>,
+    containers: HashMap<String, Option<Container>>,
+}
+
+impl ContainerResolver {
+    pub(crate) fn new() -> Self {
+        ContainerResolver {
+            host_mount_namespace: mount_namespace(1),
+            containers: HashMap::new(),
+        }
+    }
+    pub(crate) fn resolve(&mut self, pid: i32) -> Option<Container> {
+        match mount_namespace(pid) {
+            None => container_of(pid),
+            Some(mount_namespace)
+                if Some(&mount_namespace) == self.host_mount_namespace.as_ref() =>
+            {
+                None
+            }
+            Some(mount_namespace) => self
+                .containers
+                .entry(mount_namespace)
+                .or_insert_with(|| container_of(pid))
+                .clone(),
+        }
+    }
+}
+
+fn mount_namespace(pid: i32) -> Option<String> {
+    fs::read_link(format!("/proc/{}/ns/mnt", pid))
+        .ok()?
+        .into_os_string()
+        .into_string()
+        .ok()
+}
+
+fn container_of(pid: i32) -> Option<Container> {
+    let cgroup = fs::read_to_string(format!("/proc/{}/cgroup", pid)).ok()?;
+    let mut container = parse_cgroup(&cgroup)?;
+    if let Some(metadata) = metadata_paths(container.runtime, &container.id)
+        .iter()
+        .find_map(|path| fs::read_to_string(path).ok())
+    {
+        annotate(&mut container, &metadata);
+    }
+    Some(container)
+}
+
+/// Finds the container of a process by the cgroup paths of `/proc/<pid>/cgroup`, named by the
+/// cgroupfs or the systemd cgroup driver.
+fn parse_cgroup(cgroup: &str) -> Option<Container> {
+    cgroup.lines().find_map(|line| {
+        let path = line.splitn(3, ':').nth(2)?;
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let (runtime, id) = segments
+            .iter()
+            .rev()
+            .find_map(|segment| container_segment(segment, &segments))?;
+        let kubepods = segments
+            .iter()
+            .any(|segment| segment.starts_with("kubepods"));
+        Some(Container {
+            id: id.to_string(),
+            runtime,
+            name: None,
+            pod_uid: segments
+                .iter()
+                .filter(|_| kubepods)
+                .find_map(|segment| pod_uid(segment)),
+            pod_name: None,
+            pod_namespace: None,
+        })
+    })
+}
+
+fn container_segment<'a>(
+    segment: &'a str,
+    segments: &[&str],
+) -> Option<(ContainerRuntime, &'a str)> {
+    let scope = segment.strip_suffix(".scope").unwrap_or(segment);
+    let (runtime, id) = if let Some(id) = scope.strip_prefix("docker-") {
+        (ContainerRuntime::Docker, id)
+    } else if let Some(id) = scope.strip_prefix("cri-containerd-") {
+        (ContainerRuntime::Containerd, id)
+    } else if let Some(id) = scope.strip_prefix("crio-") {
+        (ContainerRuntime::CriO, id)
+    } else if let Some(id) = scope.strip_prefix("libpod-") {
+        (ContainerRuntime::Podman, id)
+    } else {
+        // cgroupfs driver, the runtime being named by a parent unless managed by the kubelet
+        let runtime = if segments.contains(&"docker") {
+            ContainerRuntime::Docker
+        } else if segments.contains(&"crio") {
+            ContainerRuntime::CriO
+        } else if segments.contains(&"libpod_parent") {
+            ContainerRuntime::Podman
+        } else {
+            ContainerRuntime::Unknown
+        };
+        (runtime, scope)
+    };
+    if id.len() == CONTAINER_ID_LENGTH && id.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some((runtime, id))
+    } else {
+        None
+    }
+}
+
+/// The kubelet names pod cgroups `pod<uid>` with the cgroupfs driver, and
+/// `kubepods-<qos>-pod<uid>.slice` with dashes of the uid replaced by underscores with the
+/// systemd driver.
+fn pod_uid(segment: &str) -> Option<String> {
+    let pod = match segment.strip_suffix(".slice") {
+        Some(slice) if slice.starts_with("kubepods") => slice.rsplit_once("-pod")?.1,
+        Some(_) => return None,
+        None => segment.strip_prefix("pod")?,
+    };
+    Some(pod.replace('_', "-"))
+}
+
+/// Files describing a container: the `config.v2.json` of docker and the OCI runtime spec of
+/// the other runtimes, whose annotations name Kubernetes pods. Those of both CRI runtimes are
+/// tried if the runtime is unknown.
+fn metadata_paths(runtime: ContainerRuntime, id: &str) -> Vec<String> {
+    match runtime {
+        ContainerRuntime::Unknown => [ContainerRuntime::Containerd, ContainerRuntime::CriO]
+            .iter()
+            .flat_map(|&runtime| metadata_paths(runtime, id))
+            .collect(),
+        ContainerRuntime::Docker => {
+            vec![format!("/var/lib/docker/containers/{}/config.v2.json", id)]
+        }
+        ContainerRuntime::Containerd => ["k8s.io", "moby", "default"]
+            .iter()
+            .map(|namespace| {
+                format!(
+                    "/run/containerd/io.containerd.runtime.v2.task/{}/{}/config.json",
+                    namespace, id
+                )
+            })
+            .collect(),
+        ContainerRuntime::CriO | ContainerRuntime::Podman => ["/run", "/var/lib"]
+            .iter()
+            .map(|root| {
+                format!(
+                    "{}/containers/storage/overlay-containers/{}/userdata/config.json",
+                    root, id
+                )
+            })
+            .collect(),
+    }
+}
+
+fn annotate(container: &mut Container, metadata: &str) {
+    let metadata: Value = match serde_json::from_str(metadata) {
+        Ok(metadata) => metadata,
+        Err(_) => return,
+    };
+    let empty = Map::new();
+    let labels = metadata
+        .pointer("/Config/Labels")
+        .or_else(|| metadata.get("annotations"))
+        .and_then(Value::as_object)
+        .unwrap_or(&empty);
+    let label = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|key| labels.get(*key).and_then(Value::as_str))
+            .map(str::to_string)
+    };
+    container.pod_name = label(&["io.kubernetes.pod.name", "io.kubernetes.cri.sandbox-name"]);
+    container.pod_namespace = label(&[
+        "io.kubernetes.pod.namespace",
+        "io.kubernetes.cri.sandbox-namespace",
+    ]);
+    if container.pod_uid.is_none() {
+        container.pod_uid = label(&["io.kubernetes.pod.uid", "io.kubernetes.cri.sandbox-uid"]);
+    }
+    container.name = label(&[
+        "io.kubernetes.container.name",
+        "io.kubernetes.cri.container-name",
+    ])
+    .or_else(|| {
+        metadata
+            .get("Name")
+            .and_then(Value::as_str)
+            .map(|name| name.trim_start_matches('/').to_string())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "3f2a7c1e9b4d5a6f8e0c1b2a3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f6a";
+
+    #[test]
+    fn should_parse_containers_of_cgroup_drivers() {
+        // given
+        let cases = [
+            (
+                format!("0::/system.slice/docker-{}.scope\n", ID),
+                ContainerRuntime::Docker,
+                None,
+            ),
+            (
+                format!(
+                    "0::/kubepods.slice/kubepods-burstable.slice/\
+                     kubepods-burstable-pod1b2c3d4e_0000_1111_2222_333344445555.slice/\
+                     cri-containerd-{}.scope\n",
+                    ID
+                ),
+                ContainerRuntime::Containerd,
+                Some("1b2c3d4e-0000-1111-2222-333344445555".to_string()),
+            ),
+            (
+                format!(
+                    "12:pids:/kubepods/besteffort/pod9f8e7d6c-0000-1111-2222-333344445555/{}\n\
+                     1:name=systemd:/kubepods/besteffort/pod9f8e7d6c-0000-1111-2222-333344445555/{}\n",
+                    ID, ID
+                ),
+                ContainerRuntime::Unknown,
+                Some("9f8e7d6c-0000-1111-2222-333344445555".to_string()),
+            ),
+            (
+                format!("0::/machine.slice/libpod-{}.scope/container\n", ID),
+                ContainerRuntime::Podman,
+                None,
+            ),
+        ];
+
+        for (cgroup, runtime, pod_uid) in cases {
+            // when
+            let container = parse_cgroup(&cgroup).unwrap();
+
+            // then
+            assert_eq!(container.id, ID);
+            assert_eq!(container.runtime, runtime);
+            assert_eq!(container.pod_uid, pod_uid);
+        }
+        assert_eq!(
+            parse_cgroup("0::/user.slice/user-1000.slice/session-2.scope\n"),
+            None
+        );
+        assert_eq!(
+            parse_cgroup(&format!("0::/machine.slice/libpod-conmon-{}.scope\n", ID)),
+            None
+        );
+    }
+
+    #[test]
+    fn should_annotate_pods_of_runtime_metadata() {
+        // given
+        let mut containerd =
+            parse_cgroup(&format!("0::/kubepods/cri-containerd-{}.scope", ID)).unwrap();
+        let mut docker = parse_cgroup(&format!("0::/docker/{}", ID)).unwrap();
+
+        // when
+        annotate(
+            &mut containerd,
+            r#"{"ociVersion": "1.0.2", "annotations": {
+                "io.kubernetes.cri.container-name": "app",
+                "io.kubernetes.cri.sandbox-name": "app-7d9f8c6b5-x2x4z",
+                "io.kubernetes.cri.sandbox-namespace": "shop",
+                "io.kubernetes.cri.sandbox-uid": "1b2c3d4e-0000-1111-2222-333344445555"
+            }}"#,
+        );
+        annotate(
+            &mut docker,
+            r#"{"Name": "/postgres", "Config": {"Labels": {}}}"#,
+        );
+
+        // then
+        assert_eq!(containerd.name.as_deref(), Some("app"));
+        assert_eq!(containerd.pod_name.as_deref(), Some("app-7d9f8c6b5-x2x4z"));
+        assert_eq!(containerd.pod_namespace.as_deref(), Some("shop"));
+        assert_eq!(
+            containerd.pod_uid.as_deref(),
+            Some("1b2c3d4e-0000-1111-2222-333344445555")
+        );
+        assert_eq!(docker.runtime, ContainerRuntime::Docker);
+        assert_eq!(docker.name.as_deref(), Some("postgres"));
+        assert_eq!(docker.pod_name, None);
+    }
+}
@@ -1,6 +1,7 @@
 use failure::{Backtrace, Context, Fail};
 use std::fmt;
 
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct GetInterfaceError {
     inner: Context<GetInterfaceErrorKind>,
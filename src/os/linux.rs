@@ -1,42 +1,172 @@
 use ::std::collections::HashMap;
+use ::std::time::{Duration, UNIX_EPOCH};
 
+use ::pnet::datalink::NetworkInterface;
 use ::procfs::process::FDTarget;
 
-use crate::network::{LocalSocket, Protocol};
+use crate::network::{
+    conntrack_dump_request, parse_conntrack_messages, parse_proc_conntrack, ConntrackEntry,
+    LinkType, LocalSocket, Process, ProcessDetails, Protocol,
+};
+use crate::os::containers::ContainerResolver;
+use crate::os::systemd::systemd_unit_of;
 use crate::OpenSockets;
 
+const ARPHRD_ETHER: u16 = 1;
+const ARPHRD_PPP: u16 = 512;
+const ARPHRD_TUNNEL: u16 = 768;
+const ARPHRD_TUNNEL6: u16 = 769;
+const ARPHRD_LOOPBACK: u16 = 772;
+const ARPHRD_SIT: u16 = 776;
+const ARPHRD_IPGRE: u16 = 778;
+const ARPHRD_IP6GRE: u16 = 823;
+const ARPHRD_NONE: u16 = 65534;
+
+const MAXIMUM_PASSWD_BUFFER_LENGTH: usize = 1024 * 1024;
+
+/// Determines the framing of packet socket frames by the hardware type of the interface.
+pub(crate) fn get_link_type(network_interface: &NetworkInterface) -> LinkType {
+    let hardware_type =
+        std::fs::read_to_string(format!("/sys/class/net/{}/type", network_interface.name))
+            .ok()
+            .and_then(|hardware_type| hardware_type.trim().parse::<u16>().ok());
+    match hardware_type {
+        Some(ARPHRD_ETHER) | Some(ARPHRD_LOOPBACK) => LinkType::Ethernet,
+        Some(ARPHRD_PPP) | Some(ARPHRD_TUNNEL) | Some(ARPHRD_TUNNEL6) | Some(ARPHRD_SIT)
+        | Some(ARPHRD_IPGRE) | Some(ARPHRD_IP6GRE) | Some(ARPHRD_NONE) => LinkType::RawIp,
+        Some(_) => LinkType::Unsupported,
+        None => LinkType::Ethernet,
+    }
+}
+
 pub(crate) fn get_open_sockets() -> OpenSockets {
-    let mut open_sockets = HashMap::new();
-    let mut inode_to_procname = HashMap::new();
+    let mut user_names = HashMap::new();
+    let boot_time = procfs::boot_time_secs()
+        .ok()
+        .map(|boot_time| UNIX_EPOCH + Duration::from_secs(boot_time));
+    let ticks_per_second = procfs::ticks_per_second().ok().filter(|ticks| *ticks > 0);
+    let mut containers = ContainerResolver::new();
+    let inode_processes = get_socket_owners(|process| ProcessDetails {
+        process: Process {
+            pid: process.pid,
+            name: process.stat.comm.clone(),
+            container: containers.resolve(process.pid),
+            systemd_unit: systemd_unit_of(process.pid),
+        },
+        parent_pid: Some(process.stat.ppid),
+        executable: process.exe().ok(),
+        command_line: process.cmdline().unwrap_or_default(),
+        uid: Some(process.owner),
+        user_name: user_names
+            .entry(process.owner)
+            .or_insert_with(|| user_name(process.owner))
+            .clone(),
+        start_time: boot_time
+            .zip(ticks_per_second)
+            .map(|(boot_time, ticks_per_second)| {
+                boot_time
+                    + Duration::from_millis(process.stat.starttime * 1000 / ticks_per_second as u64)
+            }),
+    });
+    let open_sockets = get_socket_inodes()
+        .into_iter()
+        .filter_map(|(local_socket, inode)| {
+            let process = inode_processes.get(&inode)?;
+            Some((local_socket, process.clone()))
+        })
+        .collect();
+
+    OpenSockets {
+        sockets_to_procs: open_sockets,
+    }
+}
+
+/// Maps the inodes of all sockets held open by any process to that process.
+pub(crate) fn get_inode_processes() -> HashMap<u64, Process> {
+    let mut containers = ContainerResolver::new();
+    get_socket_owners(|process| Process {
+        pid: process.pid,
+        name: process.stat.comm.clone(),
+        container: containers.resolve(process.pid),
+        systemd_unit: systemd_unit_of(process.pid),
+    })
+}
+
+/// Maps the inodes of all sockets held open by any process to the description of that process,
+/// only describing processes holding sockets.
+fn get_socket_owners<T, F>(mut describe: F) -> HashMap<u64, T>
+where
+    T: Clone,
+    F: FnMut(&procfs::process::Process) -> T,
+{
+    let mut socket_owners = HashMap::new();
 
     if let Ok(all_procs) = procfs::process::all_processes() {
         for process in all_procs {
             if let Ok(fds) = process.fd() {
-                let procname = process.stat.comm;
+                let mut owner = None;
                 for fd in fds {
                     if let FDTarget::Socket(inode) = fd.target {
-                        inode_to_procname.insert(inode, procname.clone());
+                        let owner = owner.get_or_insert_with(|| describe(&process));
+                        socket_owners.insert(inode, owner.clone());
                     }
                 }
             }
         }
     }
 
+    socket_owners
+}
+
+/// Looks up the name of a user by the name service switch, so users of LDAP or systemd-homed
+/// are found as well as those of `/etc/passwd`.
+fn user_name(uid: u32) -> Option<String> {
+    let mut buffer = vec![0 as libc::c_char; 1024];
+    loop {
+        // SAFETY: the entry is zeroed and only read if found, its strings pointing into the buffer
+        let (result, name) = unsafe {
+            let mut passwd: libc::passwd = std::mem::zeroed();
+            let mut found: *mut libc::passwd = std::ptr::null_mut();
+            let result = libc::getpwuid_r(
+                uid,
+                &mut passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut found,
+            );
+            let name = (result == 0 && !found.is_null()).then(|| {
+                std::ffi::CStr::from_ptr(passwd.pw_name)
+                    .to_string_lossy()
+                    .into_owned()
+            });
+            (result, name)
+        };
+        if result == libc::ERANGE && buffer.len() < MAXIMUM_PASSWD_BUFFER_LENGTH {
+            buffer.resize(buffer.len() * 2, 0);
+            continue;
+        }
+        return name;
+    }
+}
+
+/// Maps the local sockets of all TCP and UDP sockets to their inode, sockets without one like
+/// those in TIME_WAIT being left out.
+pub(crate) fn get_socket_inodes() -> HashMap<LocalSocket, u64> {
+    let mut socket_inodes = HashMap::new();
+
     if let Ok(mut tcp) = ::procfs::net::tcp() {
         if let Ok(mut tcp6) = ::procfs::net::tcp6() {
             tcp.append(&mut tcp6);
         }
-        for entry in tcp.into_iter() {
-            if let Some(procname) = inode_to_procname.get(&entry.inode) {
-                open_sockets.insert(
-                    LocalSocket {
-                        ip: entry.local_address.ip(),
-                        port: entry.local_address.port(),
-                        protocol: Protocol::Tcp,
-                    },
-                    procname.clone(),
-                );
-            };
+        for entry in tcp.into_iter().filter(|entry| entry.inode != 0) {
+            socket_inodes.insert(
+                LocalSocket {
+                    ip: entry.local_address.ip(),
+                    port: entry.local_address.port(),
+                    protocol: Protocol::Tcp,
+                },
+                entry.inode,
+            );
         }
     }
 
@@ -44,21 +174,122 @@ pub(crate) fn get_open_sockets() -> OpenSockets {
         if let Ok(mut udp6) = ::procfs::net::udp6() {
             udp.append(&mut udp6);
         }
-        for entry in udp.into_iter() {
-            if let Some(procname) = inode_to_procname.get(&entry.inode) {
-                open_sockets.insert(
-                    LocalSocket {
-                        ip: entry.local_address.ip(),
-                        port: entry.local_address.port(),
-                        protocol: Protocol::Udp,
-                    },
-                    procname.clone(),
-                );
+        for entry in udp.into_iter().filter(|entry| entry.inode != 0) {
+            socket_inodes.insert(
+                LocalSocket {
+                    ip: entry.local_address.ip(),
+                    port: entry.local_address.port(),
+                    protocol: Protocol::Udp,
+                },
+                entry.inode,
+            );
+        }
+    }
+
+    socket_inodes
+}
+
+const NETLINK_RECEIVE_BUFFER_SIZE: usize = 65536;
+const NETLINK_RECEIVE_TIMEOUT_SECONDS: libc::time_t = 1;
+
+/// Reads all conntrack entries by a ctnetlink dump, or from `/proc/net/nf_conntrack` if netlink
+/// is not available.
+pub(crate) fn read_conntrack() -> std::io::Result<Vec<ConntrackEntry>> {
+    dump_conntrack().or_else(|netlink_error| {
+        std::fs::read_to_string("/proc/net/nf_conntrack")
+            .map(|table| parse_proc_conntrack(&table))
+            .map_err(|_| netlink_error)
+    })
+}
+
+fn dump_conntrack() -> std::io::Result<Vec<ConntrackEntry>> {
+    let socket = NetlinkSocket::open()?;
+    socket.send(&conntrack_dump_request(1))?;
+    let mut entries = vec![];
+    let mut buffer = vec![0u8; NETLINK_RECEIVE_BUFFER_SIZE];
+    loop {
+        let length = socket.receive(&mut buffer)?;
+        let (mut received, done) = parse_conntrack_messages(&buffer[..length])?;
+        entries.append(&mut received);
+        if done {
+            return Ok(entries);
+        }
+    }
+}
+
+struct NetlinkSocket(libc::c_int);
+
+impl NetlinkSocket {
+    fn open() -> std::io::Result<Self> {
+        // SAFETY: plain system calls on a socket owned by the returned value
+        unsafe {
+            let fd = libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_NETFILTER,
+            );
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let socket = NetlinkSocket(fd);
+            let timeout = libc::timeval {
+                tv_sec: NETLINK_RECEIVE_TIMEOUT_SECONDS,
+                tv_usec: 0,
             };
+            if libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &timeout as *const libc::timeval as *const libc::c_void,
+                std::mem::size_of::<libc::timeval>() as libc::socklen_t,
+            ) < 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(socket)
         }
     }
+    fn send(&self, request: &[u8]) -> std::io::Result<()> {
+        // SAFETY: the address is zeroed and sized for sockaddr_nl, addressing the kernel
+        let sent = unsafe {
+            let mut address: libc::sockaddr_nl = std::mem::zeroed();
+            address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            libc::sendto(
+                self.0,
+                request.as_ptr() as *const libc::c_void,
+                request.len(),
+                0,
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if sent < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    fn receive(&self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        // SAFETY: the kernel writes at most the length of the buffer
+        let received = unsafe {
+            libc::recv(
+                self.0,
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+                0,
+            )
+        };
+        if received < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(received as usize)
+    }
+}
 
-    OpenSockets {
-        sockets_to_procs: open_sockets,
+impl Drop for NetlinkSocket {
+    fn drop(&mut self) {
+        // SAFETY: the socket is owned and closed only once
+        unsafe {
+            libc::close(self.0);
+        }
     }
 }
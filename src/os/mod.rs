@@ -1,4 +1,7 @@
+mod containers;
+#[allow(non_local_definitions)]
 mod errors;
+mod systemd;
 
 pub mod linux;
 pub mod shared;
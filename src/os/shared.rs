@@ -5,16 +5,36 @@ use pnet::datalink::Channel::Ethernet;
 use pnet::datalink::DataLinkReceiver;
 use pnet::datalink::{self, Config, NetworkInterface};
 
+use crate::configuration::SniffingConfiguration;
+use crate::network::LinkType;
 use crate::os::errors::GetInterfaceErrorKind;
 use crate::os::linux::get_open_sockets;
+pub(crate) use crate::os::linux::{get_inode_processes, get_socket_inodes, read_conntrack};
 use crate::OsInputOutput;
 
+pub(crate) fn get_link_type(interface: &NetworkInterface) -> LinkType {
+    // See https://github.com/libpnet/libpnet/blob/master/examples/packetdump.rs
+    // VPN interfaces (such as utun0, utun1, etc) have POINT_TO_POINT bit set to 1
+    if cfg!(target_os = "macos") {
+        if interface.is_loopback() || interface.is_point_to_point() {
+            // The pnet code for BPF loopback adds a zero'd out Ethernet header
+            LinkType::ZeroedEthernet
+        } else {
+            LinkType::Ethernet
+        }
+    } else {
+        crate::os::linux::get_link_type(interface)
+    }
+}
+
 pub(crate) fn get_datalink_channel(
     interface: &NetworkInterface,
+    promiscuous: bool,
 ) -> Result<Box<dyn DataLinkReceiver>, GetInterfaceErrorKind> {
     let config = Config {
         read_timeout: Some(time::Duration::new(1, 0)),
         read_buffer_size: 65536,
+        promiscuous,
         ..Default::default()
     };
 
@@ -109,13 +129,19 @@ where
     }
 }
 
-pub fn get_input() -> Result<OsInputOutput, failure::Error> {
+/// Opens all interfaces which are up and have addresses, and mirror interfaces without any.
+pub fn get_input(sniffing: &SniffingConfiguration) -> Result<OsInputOutput, failure::Error> {
     let network_interfaces = datalink::interfaces();
 
     let network_frames = network_interfaces
         .iter()
-        .filter(|iface| iface.is_up() && !iface.ips.is_empty())
-        .map(|iface| (iface, get_datalink_channel(iface)));
+        .filter(|iface| iface.is_up() && (!iface.ips.is_empty() || sniffing.is_mirror(&iface.name)))
+        .map(|iface| {
+            (
+                iface,
+                get_datalink_channel(iface, sniffing.is_promiscuous(&iface.name)),
+            )
+        });
 
     let (available_network_frames, network_interfaces) = {
         let network_frames = network_frames.clone();
@@ -150,6 +176,15 @@ pub fn get_input() -> Result<OsInputOutput, failure::Error> {
     })
 }
 
+/// Opens no interfaces, for measurement sources other than capturing traffic.
+pub fn get_input_without_capture() -> OsInputOutput {
+    OsInputOutput {
+        network_interfaces: vec![],
+        network_frames: vec![],
+        get_open_sockets,
+    }
+}
+
 #[inline]
 fn eperm_message() -> &'static str {
     r#"
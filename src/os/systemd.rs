@@ -0,0 +1,76 @@
+use ::std::fs;
+
+use crate::network::SystemdUnit;
+
+/// Suffixes of the units processes can run in, slices only grouping other units.
+const UNIT_SUFFIXES: [&str; 2] = [".service", ".scope"];
+
+pub(crate) fn systemd_unit_of(pid: i32) -> Option<SystemdUnit> {
+    let cgroup = fs::read_to_string(format!("/proc/{}/cgroup", pid)).ok()?;
+    parse_systemd_unit(&cgroup)
+}
+
+/// Finds the unit of a process by the path of the unified hierarchy in `/proc/<pid>/cgroup`, or
+/// of the named systemd hierarchy on hosts still using cgroup v1.
+fn parse_systemd_unit(cgroup: &str) -> Option<SystemdUnit> {
+    let path = cgroup.lines().find_map(|line| {
+        let mut fields = line.splitn(3, ':');
+        let controllers = fields.nth(1)?;
+        let path = fields.next()?;
+        if controllers == "name=systemd" || controllers.is_empty() {
+            Some(path)
+        } else {
+            None
+        }
+    })?;
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let position = segments
+        .iter()
+        .rposition(|segment| UNIT_SUFFIXES.iter().any(|suffix| segment.ends_with(suffix)))?;
+    let unit = segments[position];
+    Some(SystemdUnit {
+        unit: unit.to_string(),
+        slice: segments[..position]
+            .iter()
+            .rev()
+            .find(|segment| segment.ends_with(".slice"))
+            .map(|slice| slice.to_string()),
+        session: unit
+            .strip_prefix("session-")
+            .and_then(|session| session.strip_suffix(".scope"))
+            .map(str::to_string),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_units_of_cgroup_hierarchies() {
+        // when
+        let service = parse_systemd_unit("0::/system.slice/nginx.service\n").unwrap();
+        let session =
+            parse_systemd_unit("0::/user.slice/user-1000.slice/session-2.scope\n").unwrap();
+        let user_service = parse_systemd_unit(
+            "0::/user.slice/user-1000.slice/user@1000.service/app.slice/syncthing.service\n",
+        )
+        .unwrap();
+        let cgroup_v1 = parse_systemd_unit(
+            "12:pids:/system.slice/sshd.service\n1:name=systemd:/system.slice/sshd.service\n",
+        )
+        .unwrap();
+
+        // then
+        assert_eq!(service.unit, "nginx.service");
+        assert_eq!(service.slice.as_deref(), Some("system.slice"));
+        assert_eq!(service.session, None);
+        assert_eq!(session.unit, "session-2.scope");
+        assert_eq!(session.slice.as_deref(), Some("user-1000.slice"));
+        assert_eq!(session.session.as_deref(), Some("2"));
+        assert_eq!(user_service.unit, "syncthing.service");
+        assert_eq!(user_service.slice.as_deref(), Some("app.slice"));
+        assert_eq!(cgroup_v1.unit, "sshd.service");
+        assert_eq!(parse_systemd_unit("0::/\n"), None);
+    }
+}
@@ -1,5 +1,8 @@
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter, Write};
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::time::SystemTime;
 
 use ipnetwork::IpNetwork;
@@ -7,16 +10,157 @@ use pnet::datalink::NetworkInterface;
 use serde::{Serialize, Serializer};
 use time::{Duration, OffsetDateTime};
 
-use crate::network::Protocol;
+use crate::network::{
+    AccountingMode, Connection, ConnectionInfo, ConnectionRole, ConntrackEntry, Container,
+    ContainerRuntime, ForwardedFlow, IcmpKind, InterfaceInfo, LocalSocket, Process, ProcessDetails,
+    Protocol, QuicSample, Scope, Socket, SystemdUnit, TcpLifecycle, TlsClientHello, TransitFlow,
+    TransitFlowInfo, Tunnel, TunnelKind,
+};
 use crate::{MachineId, OpenSockets, OsRelease, Utilization};
 
 #[derive(Serialize)]
 #[serde(tag = "type", content = "content")]
+#[allow(clippy::enum_variant_names)]
 pub enum Message {
     #[serde(rename = "bandwhichd/measurement/agent-network-configuration/v1")]
     NetworkConfigurationV1Measurement(NetworkConfigurationV1MeasurementMessage),
+    #[serde(rename = "bandwhichd/measurement/agent-network-configuration/v2")]
+    NetworkConfigurationV2Measurement(NetworkConfigurationV2MeasurementMessage),
     #[serde(rename = "bandwhichd/measurement/agent-network-utilization/v1")]
     NetworkUtilizationV1Measurement(NetworkUtilizationV1MeasurementMessage),
+    #[serde(rename = "bandwhichd/measurement/agent-network-utilization/v2")]
+    NetworkUtilizationV2Measurement(NetworkUtilizationV2MeasurementMessage),
+    #[serde(rename = "bandwhichd/measurement/agent-network-utilization/v3")]
+    NetworkUtilizationV3Measurement(NetworkUtilizationV3MeasurementMessage),
+    #[serde(rename = "bandwhichd/measurement/agent-network-transit/v1")]
+    NetworkTransitV1Measurement(NetworkTransitV1MeasurementMessage),
+}
+
+impl Message {
+    /// Wraps a network configuration measurement in the configured message version.
+    pub fn network_configuration(
+        machine_id: MachineId,
+        timestamp: SystemTime,
+        maybe_os_release: Option<OsRelease>,
+        hostname: String,
+        network_interfaces: Vec<NetworkInterface>,
+        open_sockets: OpenSockets,
+        details: &ConfigurationDetails,
+    ) -> Self {
+        match details.version {
+            ConfigurationMessageVersion::V1 => Message::NetworkConfigurationV1Measurement(
+                NetworkConfigurationV1MeasurementMessage::from(
+                    machine_id,
+                    timestamp,
+                    maybe_os_release,
+                    hostname,
+                    network_interfaces,
+                    open_sockets,
+                ),
+            ),
+            ConfigurationMessageVersion::V2 => Message::NetworkConfigurationV2Measurement(
+                NetworkConfigurationV2MeasurementMessage::from(
+                    machine_id,
+                    timestamp,
+                    maybe_os_release,
+                    hostname,
+                    network_interfaces,
+                    open_sockets,
+                    details,
+                ),
+            ),
+        }
+    }
+    /// Wraps a network utilization measurement in the configured message version.
+    pub fn network_utilization(
+        machine_id: MachineId,
+        utilization: Utilization,
+        details: UtilizationDetails,
+    ) -> Self {
+        match details.version {
+            UtilizationMessageVersion::V1 => Message::NetworkUtilizationV1Measurement(
+                NetworkUtilizationV1MeasurementMessage::from(machine_id, utilization, details),
+            ),
+            UtilizationMessageVersion::V2 => Message::NetworkUtilizationV2Measurement(
+                NetworkUtilizationV2MeasurementMessage::from(machine_id, utilization, details),
+            ),
+            UtilizationMessageVersion::V3 => Message::NetworkUtilizationV3Measurement(
+                NetworkUtilizationV3MeasurementMessage::from(machine_id, utilization, details),
+            ),
+        }
+    }
+    /// Wraps the transit flows of a network utilization measurement, if there are any.
+    pub fn network_transit(
+        machine_id: MachineId,
+        utilization: &Utilization,
+        details: UtilizationDetails,
+    ) -> Option<Self> {
+        if utilization.transit_flows.is_empty()
+            && utilization.forwarded_flows.is_empty()
+            && utilization
+                .interfaces
+                .values()
+                .all(|interface_info| interface_info.unmatched_forwarding.is_empty())
+        {
+            return None;
+        }
+        Some(Message::NetworkTransitV1Measurement(
+            NetworkTransitV1MeasurementMessage::from(machine_id, utilization, details),
+        ))
+    }
+}
+
+/// A destination measurement messages are published to.
+pub trait Sink {
+    fn publish(&mut self, message: &Message) -> Result<(), failure::Error>;
+}
+
+pub struct HttpSink {
+    client: reqwest::blocking::Client,
+    endpoint: String,
+}
+
+impl HttpSink {
+    pub fn new(server: &str) -> Self {
+        HttpSink {
+            client: reqwest::blocking::Client::new(),
+            endpoint: format!("{}/v1/messages", server),
+        }
+    }
+}
+
+impl Sink for HttpSink {
+    fn publish(&mut self, message: &Message) -> Result<(), failure::Error> {
+        let response = self
+            .client
+            .post(self.endpoint.clone())
+            .json(message)
+            .send()?;
+        if response.status() != 200 {
+            failure::bail!("response: {:?}", response);
+        }
+        Ok(())
+    }
+}
+
+/// Writes each message as a single line of JSON.
+pub struct JsonLinesSink<W: std::io::Write> {
+    writer: W,
+}
+
+impl<W: std::io::Write> JsonLinesSink<W> {
+    pub fn new(writer: W) -> Self {
+        JsonLinesSink { writer }
+    }
+}
+
+impl<W: std::io::Write> Sink for JsonLinesSink<W> {
+    fn publish(&mut self, message: &Message) -> Result<(), failure::Error> {
+        serde_json::to_writer(&mut self.writer, message)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
 }
 
 #[derive(Serialize)]
@@ -38,23 +182,10 @@ impl NetworkConfigurationV1MeasurementMessage {
         network_interfaces: Vec<NetworkInterface>,
         open_sockets: OpenSockets,
     ) -> Self {
-        let mut interfaces: Vec<InterfaceV1> = network_interfaces
-            .into_iter()
-            .map(|network_interface| InterfaceV1 {
-                name: network_interface.name.clone(),
-                is_up: network_interface.is_up(),
-                networks: network_interface.ips,
-            })
-            .collect();
-        interfaces.sort();
         let mut open_sockets: Vec<OpenSocketV1> = open_sockets
             .sockets_to_procs
             .into_iter()
-            .map(|(socket, process)| OpenSocketV1 {
-                socket_address: socket.into(),
-                protocol: ProtocolV1(socket.protocol),
-                process,
-            })
+            .map(|(socket, process_details)| OpenSocketV1::from(socket, &process_details))
             .collect();
         open_sockets.sort();
         NetworkConfigurationV1MeasurementMessage {
@@ -62,12 +193,194 @@ impl NetworkConfigurationV1MeasurementMessage {
             machine_id,
             maybe_os_release,
             timestamp: TimestampV1(timestamp.into()),
-            interfaces,
+            interfaces: InterfaceV1::from_all(network_interfaces),
             open_sockets,
         }
     }
 }
 
+/// Adds the identity of the processes of open sockets.
+#[derive(Serialize)]
+pub struct NetworkConfigurationV2MeasurementMessage {
+    pub machine_id: MachineId,
+    pub timestamp: TimestampV1,
+    pub maybe_os_release: Option<OsRelease>,
+    pub hostname: String,
+    pub interfaces: Vec<InterfaceV1>,
+    pub open_sockets: Vec<OpenSocketV2>,
+}
+
+impl NetworkConfigurationV2MeasurementMessage {
+    pub fn from(
+        machine_id: MachineId,
+        timestamp: SystemTime,
+        maybe_os_release: Option<OsRelease>,
+        hostname: String,
+        network_interfaces: Vec<NetworkInterface>,
+        open_sockets: OpenSockets,
+        details: &ConfigurationDetails,
+    ) -> Self {
+        let mut open_sockets: Vec<OpenSocketV2> = open_sockets
+            .sockets_to_procs
+            .into_iter()
+            .map(|(socket, process_details)| OpenSocketV2::from(socket, &process_details, details))
+            .collect();
+        open_sockets.sort();
+        NetworkConfigurationV2MeasurementMessage {
+            hostname,
+            machine_id,
+            maybe_os_release,
+            timestamp: TimestampV1(timestamp.into()),
+            interfaces: InterfaceV1::from_all(network_interfaces),
+            open_sockets,
+        }
+    }
+}
+
+/// Optional details included in network configuration messages.
+#[derive(Clone, Default)]
+pub struct ConfigurationDetails {
+    pub version: ConfigurationMessageVersion,
+    /// Maximum number of characters of command lines, 0 leaving them out.
+    pub command_line_length: usize,
+    /// Arguments whose values are replaced in command lines, matched case-insensitively by
+    /// whole words of the argument name, e.g. `password` matching `--db-password=secret` and
+    /// `-DdbPassword=secret` but `key` not matching `--keyboard`.
+    pub redacted_arguments: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConfigurationMessageVersion {
+    #[default]
+    V1,
+    /// Adds the pid, parent pid, executable, command line, user, start time, container and
+    /// systemd unit of the processes of open sockets.
+    V2,
+}
+
+impl FromStr for ConfigurationMessageVersion {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim_start_matches('v') {
+            "1" => Ok(ConfigurationMessageVersion::V1),
+            "2" => Ok(ConfigurationMessageVersion::V2),
+            _ => Err("expected one of v1 or v2".to_string()),
+        }
+    }
+}
+
+const REDACTED_VALUE: &str = "<redacted>";
+
+/// Joins the arguments of a command line, replacing the values of redacted arguments given
+/// either as `--name=value` or as `--name value`, and truncates it to the configured length.
+fn command_line(arguments: &[String], details: &ConfigurationDetails) -> Option<String> {
+    if details.command_line_length == 0 || arguments.is_empty() {
+        return None;
+    }
+    let redacted = |name: &str| {
+        let name_words = words(name);
+        details.redacted_arguments.iter().any(|redacted_argument| {
+            let redacted_words = words(redacted_argument);
+            !redacted_words.is_empty()
+                && name_words
+                    .windows(redacted_words.len())
+                    .any(|window| window == redacted_words.as_slice())
+        })
+    };
+    let mut redact_next = false;
+    let redacted_arguments: Vec<String> = arguments
+        .iter()
+        .map(|argument| {
+            if std::mem::take(&mut redact_next) && !argument.starts_with('-') {
+                return REDACTED_VALUE.to_string();
+            }
+            match argument.split_once('=') {
+                Some((name, _)) if redacted(name) => format!("{}={}", name, REDACTED_VALUE),
+                Some(_) => argument.clone(),
+                None => {
+                    redact_next = argument.starts_with('-') && redacted(argument);
+                    argument.clone()
+                }
+            }
+        })
+        .collect();
+    Some(
+        redacted_arguments
+            .join(" ")
+            .chars()
+            .take(details.command_line_length)
+            .collect(),
+    )
+}
+
+/// Lowercased words of an argument name, split at non-alphanumeric characters and camel case
+/// humps, e.g. `db` and `password` of `--dbPassword`.
+fn words(name: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut previous_lowercase = false;
+    for c in name.chars() {
+        if (!c.is_alphanumeric() || (c.is_uppercase() && previous_lowercase)) && !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+        if c.is_alphanumeric() {
+            word.extend(c.to_lowercase());
+        }
+        previous_lowercase = c.is_lowercase();
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// Optional details included in network utilization messages.
+#[derive(Clone, Copy, Default)]
+pub struct UtilizationDetails {
+    pub vlan_ids: bool,
+    /// Whether DNS responses are sniffed to report the names of remote addresses.
+    pub remote_names: bool,
+    /// Whether connections are annotated with the tuples of their conntrack entries.
+    pub conntrack: bool,
+    /// Whether connections are attributed to the processes owning their local sockets.
+    pub processes: bool,
+    /// Whether the bytes of connections are summed up by the systemd units of their processes,
+    /// published from utilization message v2 on.
+    pub systemd_units: bool,
+    pub version: UtilizationMessageVersion,
+    pub accounting_mode: AccountingMode,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UtilizationMessageVersion {
+    #[default]
+    V1,
+    /// Adds the accounting mode, the header and payload bytes of connections, the packet counts
+    /// of connections and interfaces, orphaned fragments of interfaces, the lifecycle of TCP
+    /// connections and connections of protocols other than TCP and UDP.
+    ///
+    /// Connections are also annotated with the tunnel they were decapsulated from, the TLS
+    /// ClientHello they started with, their QUIC version and connection ID and their scope if
+    /// not unicast.
+    V2,
+    /// Adds RTTs, retransmissions and reordering of TCP connections.
+    V3,
+}
+
+impl FromStr for UtilizationMessageVersion {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim_start_matches('v') {
+            "1" => Ok(UtilizationMessageVersion::V1),
+            "2" => Ok(UtilizationMessageVersion::V2),
+            "3" => Ok(UtilizationMessageVersion::V3),
+            _ => Err("expected one of v1, v2 or v3".to_string()),
+        }
+    }
+}
+
 #[derive(Serialize)]
 pub struct NetworkUtilizationV1MeasurementMessage {
     pub machine_id: MachineId,
@@ -76,33 +389,164 @@ pub struct NetworkUtilizationV1MeasurementMessage {
 }
 
 impl NetworkUtilizationV1MeasurementMessage {
-    pub fn from(machine_id: MachineId, utilization: Utilization) -> Self {
+    pub fn from(
+        machine_id: MachineId,
+        utilization: Utilization,
+        details: UtilizationDetails,
+    ) -> Self {
         let mut connections: Vec<ConnectionV1> = utilization
             .connections
-            .into_iter()
-            .map(|(connection, connection_info)| ConnectionV1 {
-                interface_name: connection_info.interface_name,
-                local_socket_address: connection.local_socket.into(),
-                remote_socket_address: connection.remote_socket.into(),
-                protocol: ProtocolV1(connection.local_socket.protocol),
-                received: BytesCount(connection_info.total_bytes_downloaded),
-                sent: BytesCount(connection_info.total_bytes_uploaded),
+            .iter()
+            .filter(|(connection, _)| is_published_in_v1(connection.local_socket.protocol))
+            .map(|(connection, connection_info)| {
+                ConnectionV1::from(connection, connection_info, details)
             })
             .collect();
         connections.sort();
-        let start: OffsetDateTime = utilization.start.into();
-        let stop: OffsetDateTime = utilization.stop.into();
         NetworkUtilizationV1MeasurementMessage {
             machine_id,
-            timeframe: TimeframeV1 {
-                start: TimestampV1(start),
-                duration: DurationV1(stop - start),
-            },
+            timeframe: TimeframeV1::from(utilization.start, utilization.stop),
+            connections,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct NetworkUtilizationV2MeasurementMessage {
+    pub machine_id: MachineId,
+    pub timeframe: TimeframeV1,
+    /// The layer whose length `received` and `sent` count.
+    pub accounting_mode: AccountingModeV2,
+    pub connections: Vec<ConnectionV2>,
+    pub interfaces: Vec<InterfaceUtilizationV2>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub systemd_units: Vec<SystemdUnitUtilizationV1>,
+}
+
+impl NetworkUtilizationV2MeasurementMessage {
+    pub fn from(
+        machine_id: MachineId,
+        utilization: Utilization,
+        details: UtilizationDetails,
+    ) -> Self {
+        let mut connections: Vec<ConnectionV2> = utilization
+            .connections
+            .iter()
+            .map(|(connection, connection_info)| {
+                ConnectionV2::from(connection, connection_info, details)
+            })
+            .collect();
+        connections.sort();
+        NetworkUtilizationV2MeasurementMessage {
+            machine_id,
+            timeframe: TimeframeV1::from(utilization.start, utilization.stop),
+            accounting_mode: AccountingModeV2(details.accounting_mode),
+            connections,
+            interfaces: InterfaceUtilizationV2::all(&utilization.interfaces),
+            systemd_units: SystemdUnitUtilizationV1::all(&utilization, details),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct NetworkUtilizationV3MeasurementMessage {
+    pub machine_id: MachineId,
+    pub timeframe: TimeframeV1,
+    /// The layer whose length `received` and `sent` count.
+    pub accounting_mode: AccountingModeV2,
+    pub connections: Vec<ConnectionV3>,
+    pub interfaces: Vec<InterfaceUtilizationV2>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub systemd_units: Vec<SystemdUnitUtilizationV1>,
+}
+
+impl NetworkUtilizationV3MeasurementMessage {
+    pub fn from(
+        machine_id: MachineId,
+        utilization: Utilization,
+        details: UtilizationDetails,
+    ) -> Self {
+        let mut connections: Vec<ConnectionV3> = utilization
+            .connections
+            .iter()
+            .map(|(connection, connection_info)| {
+                ConnectionV3::from(connection, connection_info, details)
+            })
+            .collect();
+        connections.sort();
+        NetworkUtilizationV3MeasurementMessage {
+            machine_id,
+            timeframe: TimeframeV1::from(utilization.start, utilization.stop),
+            accounting_mode: AccountingModeV2(details.accounting_mode),
             connections,
+            interfaces: InterfaceUtilizationV2::all(&utilization.interfaces),
+            systemd_units: SystemdUnitUtilizationV1::all(&utilization, details),
         }
     }
 }
 
+/// Traffic between other hosts seen on mirror ports or forwarded by a router, reported by source
+/// and destination instead of local and remote sockets.
+#[derive(Serialize)]
+pub struct NetworkTransitV1MeasurementMessage {
+    pub machine_id: MachineId,
+    pub timeframe: TimeframeV1,
+    /// The layer whose length `bytes` count.
+    pub accounting_mode: AccountingModeV2,
+    pub flows: Vec<TransitFlowV1>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub forwarded: Vec<ForwardedFlowV1>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub interfaces: Vec<InterfaceTransitV1>,
+}
+
+impl NetworkTransitV1MeasurementMessage {
+    pub fn from(
+        machine_id: MachineId,
+        utilization: &Utilization,
+        details: UtilizationDetails,
+    ) -> Self {
+        let mut flows: Vec<TransitFlowV1> = utilization
+            .transit_flows
+            .iter()
+            .map(|(transit_flow, transit_flow_info)| {
+                TransitFlowV1::from(transit_flow, transit_flow_info, details)
+            })
+            .collect();
+        flows.sort();
+        let mut forwarded: Vec<ForwardedFlowV1> = utilization
+            .forwarded_flows
+            .iter()
+            .map(|(forwarded_flow, transit_flow_info)| {
+                ForwardedFlowV1::from(forwarded_flow, transit_flow_info, details)
+            })
+            .collect();
+        forwarded.sort();
+        let mut interfaces: Vec<InterfaceTransitV1> = utilization
+            .interfaces
+            .iter()
+            .filter(|(_, interface_info)| !interface_info.unmatched_forwarding.is_empty())
+            .map(|(name, interface_info)| InterfaceTransitV1 {
+                name: name.clone(),
+                unmatched_forwarded_packets: PacketsCount(
+                    interface_info.unmatched_forwarding.packets,
+                ),
+                unmatched_forwarded_bytes: BytesCount(interface_info.unmatched_forwarding.bytes),
+            })
+            .collect();
+        interfaces.sort();
+        NetworkTransitV1MeasurementMessage {
+            machine_id,
+            timeframe: TimeframeV1::from(utilization.start, utilization.stop),
+            accounting_mode: AccountingModeV2(details.accounting_mode),
+            flows,
+            forwarded,
+            interfaces,
+        }
+    }
+}
+
+#[derive(Ord, PartialOrd, Eq, PartialEq)]
 pub struct TimestampV1(OffsetDateTime);
 
 impl Display for TimestampV1 {
@@ -131,6 +575,17 @@ pub struct TimeframeV1 {
     pub duration: DurationV1,
 }
 
+impl TimeframeV1 {
+    fn from(start: SystemTime, stop: SystemTime) -> Self {
+        let start: OffsetDateTime = start.into();
+        let stop: OffsetDateTime = stop.into();
+        TimeframeV1 {
+            start: TimestampV1(start),
+            duration: DurationV1(stop - start),
+        }
+    }
+}
+
 impl Display for TimeframeV1 {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         self.start.fmt(f)?;
@@ -148,8 +603,27 @@ impl Serialize for TimeframeV1 {
     }
 }
 
+#[derive(Ord, PartialOrd, Eq, PartialEq)]
 pub struct DurationV1(Duration);
 
+impl DurationV1 {
+    fn from(duration: std::time::Duration) -> Self {
+        DurationV1(Duration::new(
+            duration.as_secs() as i64,
+            duration.subsec_nanos() as i32,
+        ))
+    }
+}
+
+impl Serialize for DurationV1 {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_str())
+    }
+}
+
 impl Display for DurationV1 {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_char('P')?;
@@ -184,19 +658,381 @@ pub struct InterfaceV1 {
     pub networks: Vec<IpNetwork>,
 }
 
+impl InterfaceV1 {
+    fn from_all(network_interfaces: Vec<NetworkInterface>) -> Vec<Self> {
+        let mut interfaces: Vec<InterfaceV1> = network_interfaces
+            .into_iter()
+            .map(|network_interface| InterfaceV1 {
+                name: network_interface.name.clone(),
+                is_up: network_interface.is_up(),
+                networks: network_interface.ips,
+            })
+            .collect();
+        interfaces.sort();
+        interfaces
+    }
+}
+
 #[derive(Serialize, Ord, PartialOrd, Eq, PartialEq)]
 pub struct ConnectionV1 {
     pub interface_name: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub vlan_ids: Vec<u16>,
     pub local_socket_address: SocketAddr,
     pub remote_socket_address: SocketAddr,
+    /// Names the remote address was queried by, as seen in DNS responses.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub remote_names: Vec<String>,
     pub protocol: ProtocolV1,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icmp: Option<IcmpV1>,
+    /// The protocol number of connections using protocol `other`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_protocol: Option<u8>,
+    /// Set if connections are annotated with conntrack entries, see `UtilizationDetails`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conntrack: Option<ConntrackV1>,
+    /// Set if connections are attributed to processes, see `UtilizationDetails`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub process: Option<ProcessV1>,
     pub received: BytesCount,
     pub sent: BytesCount,
 }
 
+impl ConnectionV1 {
+    fn from(
+        connection: &Connection,
+        connection_info: &ConnectionInfo,
+        details: UtilizationDetails,
+    ) -> Self {
+        ConnectionV1 {
+            interface_name: connection_info.interface_name.clone(),
+            vlan_ids: if details.vlan_ids {
+                connection_info.vlan_ids.clone()
+            } else {
+                vec![]
+            },
+            local_socket_address: connection.local_socket.into(),
+            remote_socket_address: connection.remote_socket.into(),
+            remote_names: connection_info.remote_names.clone(),
+            protocol: ProtocolV1(connection.local_socket.protocol),
+            icmp: IcmpV1::from(connection.local_socket.protocol),
+            ip_protocol: match connection.local_socket.protocol {
+                Protocol::Other(number) => Some(number),
+                _ => None,
+            },
+            conntrack: connection_info.conntrack.as_ref().map(ConntrackV1::from),
+            process: connection_info
+                .process
+                .as_ref()
+                .filter(|_| details.processes)
+                .map(ProcessV1::from),
+            received: BytesCount(connection_info.total_bytes_downloaded),
+            sent: BytesCount(connection_info.total_bytes_uploaded),
+        }
+    }
+}
+
+/// Splits the bytes of a connection into those of headers and those following the transport
+/// layer header.
+#[derive(Serialize, Ord, PartialOrd, Eq, PartialEq)]
+pub struct ConnectionV2 {
+    #[serde(flatten)]
+    pub connection: ConnectionV1,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnel: Option<TunnelV1>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsV1>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quic: Option<QuicV1>,
+    /// Omitted for unicast connections.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<ScopeV1>,
+    pub received_header_bytes: BytesCount,
+    pub received_payload_bytes: BytesCount,
+    pub sent_header_bytes: BytesCount,
+    pub sent_payload_bytes: BytesCount,
+    pub received_packets: PacketsCount,
+    pub sent_packets: PacketsCount,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp: Option<TcpV2>,
+}
+
+impl ConnectionV2 {
+    fn from(
+        connection: &Connection,
+        connection_info: &ConnectionInfo,
+        details: UtilizationDetails,
+    ) -> Self {
+        ConnectionV2 {
+            connection: ConnectionV1::from(connection, connection_info, details),
+            tunnel: TunnelV1::from(
+                connection_info.tunnel,
+                connection_info.total_tunnel_overhead_bytes_downloaded,
+                connection_info.total_tunnel_overhead_bytes_uploaded,
+            ),
+            tls: connection_info.tls.as_ref().map(TlsV1::from),
+            quic: connection_info.quic.as_ref().map(QuicV1::from),
+            scope: match connection_info.scope {
+                Scope::Unicast => None,
+                scope => Some(ScopeV1(scope)),
+            },
+            received_header_bytes: BytesCount(connection_info.total_header_bytes_downloaded),
+            received_payload_bytes: BytesCount(connection_info.total_payload_bytes_downloaded),
+            sent_header_bytes: BytesCount(connection_info.total_header_bytes_uploaded),
+            sent_payload_bytes: BytesCount(connection_info.total_payload_bytes_uploaded),
+            received_packets: PacketsCount(connection_info.total_packets_downloaded),
+            sent_packets: PacketsCount(connection_info.total_packets_uploaded),
+            tcp: connection_info.tcp.as_ref().map(TcpV2::from),
+        }
+    }
+}
+
+#[derive(Serialize, Ord, PartialOrd, Eq, PartialEq)]
+pub struct TransitFlowV1 {
+    pub interface_name: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub vlan_ids: Vec<u16>,
+    pub source_socket_address: SocketAddr,
+    pub destination_socket_address: SocketAddr,
+    pub protocol: ProtocolV1,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icmp: Option<IcmpV1>,
+    /// The protocol number of flows using protocol `other`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_protocol: Option<u8>,
+    pub bytes: BytesCount,
+    pub payload_bytes: BytesCount,
+    pub packets: PacketsCount,
+}
+
+impl TransitFlowV1 {
+    fn from(
+        transit_flow: &TransitFlow,
+        transit_flow_info: &TransitFlowInfo,
+        details: UtilizationDetails,
+    ) -> Self {
+        TransitFlowV1 {
+            interface_name: transit_flow_info.interface_name.clone(),
+            vlan_ids: if details.vlan_ids {
+                transit_flow_info.vlan_ids.clone()
+            } else {
+                vec![]
+            },
+            source_socket_address: transit_flow.source.into(),
+            destination_socket_address: transit_flow.destination.into(),
+            protocol: ProtocolV1(transit_flow.protocol),
+            icmp: IcmpV1::from(transit_flow.protocol),
+            ip_protocol: match transit_flow.protocol {
+                Protocol::Other(number) => Some(number),
+                _ => None,
+            },
+            bytes: BytesCount(transit_flow_info.total_bytes),
+            payload_bytes: BytesCount(transit_flow_info.total_payload_bytes),
+            packets: PacketsCount(transit_flow_info.total_packets),
+        }
+    }
+}
+
+/// Both legs of forwarded traffic, the translated addresses omitted if the router did not change
+/// them.
+#[derive(Serialize, Ord, PartialOrd, Eq, PartialEq)]
+pub struct ForwardedFlowV1 {
+    pub ingress_interface_name: String,
+    pub egress_interface_name: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub vlan_ids: Vec<u16>,
+    pub source_socket_address: SocketAddr,
+    pub destination_socket_address: SocketAddr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub translated_source_socket_address: Option<SocketAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub translated_destination_socket_address: Option<SocketAddr>,
+    pub protocol: ProtocolV1,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icmp: Option<IcmpV1>,
+    /// The protocol number of flows using protocol `other`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_protocol: Option<u8>,
+    pub bytes: BytesCount,
+    pub payload_bytes: BytesCount,
+    pub packets: PacketsCount,
+}
+
+impl ForwardedFlowV1 {
+    fn from(
+        forwarded_flow: &ForwardedFlow,
+        transit_flow_info: &TransitFlowInfo,
+        details: UtilizationDetails,
+    ) -> Self {
+        let ingress = &forwarded_flow.ingress;
+        let egress = &forwarded_flow.egress;
+        let translated = |original: Socket, translated: Socket| {
+            if original == translated {
+                None
+            } else {
+                Some(translated.into())
+            }
+        };
+        ForwardedFlowV1 {
+            ingress_interface_name: forwarded_flow.ingress_interface_name.clone(),
+            egress_interface_name: forwarded_flow.egress_interface_name.clone(),
+            vlan_ids: if details.vlan_ids {
+                transit_flow_info.vlan_ids.clone()
+            } else {
+                vec![]
+            },
+            source_socket_address: ingress.source.into(),
+            destination_socket_address: ingress.destination.into(),
+            translated_source_socket_address: translated(ingress.source, egress.source),
+            translated_destination_socket_address: translated(
+                ingress.destination,
+                egress.destination,
+            ),
+            protocol: ProtocolV1(ingress.protocol),
+            icmp: IcmpV1::from(ingress.protocol),
+            ip_protocol: match ingress.protocol {
+                Protocol::Other(number) => Some(number),
+                _ => None,
+            },
+            bytes: BytesCount(transit_flow_info.total_bytes),
+            payload_bytes: BytesCount(transit_flow_info.total_payload_bytes),
+            packets: PacketsCount(transit_flow_info.total_packets),
+        }
+    }
+}
+
+/// Adds the path quality of TCP connections, estimated from sequence and acknowledgement
+/// numbers.
+#[derive(Serialize, Ord, PartialOrd, Eq, PartialEq)]
+pub struct ConnectionV3 {
+    #[serde(flatten)]
+    pub connection: ConnectionV2,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handshake_rtt: Option<DurationV1>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smoothed_rtt: Option<DurationV1>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub received_retransmitted_bytes: Option<BytesCount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sent_retransmitted_bytes: Option<BytesCount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub received_out_of_order_segments: Option<PacketsCount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sent_out_of_order_segments: Option<PacketsCount>,
+}
+
+impl ConnectionV3 {
+    fn from(
+        connection: &Connection,
+        connection_info: &ConnectionInfo,
+        details: UtilizationDetails,
+    ) -> Self {
+        let performance = connection_info.tcp_performance.as_ref();
+        ConnectionV3 {
+            connection: ConnectionV2::from(connection, connection_info, details),
+            handshake_rtt: performance
+                .and_then(|performance| performance.handshake_rtt)
+                .map(DurationV1::from),
+            smoothed_rtt: performance
+                .and_then(|performance| performance.smoothed_rtt)
+                .map(DurationV1::from),
+            received_retransmitted_bytes: performance
+                .map(|performance| BytesCount(performance.retransmitted_bytes_downloaded)),
+            sent_retransmitted_bytes: performance
+                .map(|performance| BytesCount(performance.retransmitted_bytes_uploaded)),
+            received_out_of_order_segments: performance
+                .map(|performance| PacketsCount(performance.out_of_order_segments_downloaded)),
+            sent_out_of_order_segments: performance
+                .map(|performance| PacketsCount(performance.out_of_order_segments_uploaded)),
+        }
+    }
+}
+
+/// The TCP control segments of a connection seen within the timeframe.
+#[derive(Serialize, Ord, PartialOrd, Eq, PartialEq)]
+pub struct TcpV2 {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opened: Option<TimestampV1>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub closed: Option<TimestampV1>,
+    /// Whether the connection was initiated locally (`client`) or remotely (`server`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<&'static str>,
+    pub resets: PacketsCount,
+    pub failed_handshakes: HandshakesCount,
+}
+
+impl TcpV2 {
+    fn from(lifecycle: &TcpLifecycle) -> Self {
+        TcpV2 {
+            opened: lifecycle.opened.map(|opened| TimestampV1(opened.into())),
+            closed: lifecycle.closed.map(|closed| TimestampV1(closed.into())),
+            role: lifecycle.role.map(|role| match role {
+                ConnectionRole::Client => "client",
+                ConnectionRole::Server => "server",
+            }),
+            resets: PacketsCount(lifecycle.resets),
+            failed_handshakes: HandshakesCount(lifecycle.failed_handshakes),
+        }
+    }
+}
+
+pub struct AccountingModeV2(AccountingMode);
+
+impl Serialize for AccountingModeV2 {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.0.to_string().as_str())
+    }
+}
+
+#[derive(Serialize, Ord, PartialOrd, Eq, PartialEq)]
+pub struct InterfaceTransitV1 {
+    pub name: String,
+    /// Forwarded traffic whose other leg has not been seen.
+    pub unmatched_forwarded_packets: PacketsCount,
+    pub unmatched_forwarded_bytes: BytesCount,
+}
+
+#[derive(Serialize, Ord, PartialOrd, Eq, PartialEq)]
+pub struct InterfaceUtilizationV2 {
+    pub name: String,
+    pub received_packets: PacketsCount,
+    pub sent_packets: PacketsCount,
+    /// Traffic not attributable to any connection.
+    pub orphaned_fragments: PacketsCount,
+    pub orphaned_fragment_bytes: BytesCount,
+}
+
+impl InterfaceUtilizationV2 {
+    fn all(interfaces: &HashMap<String, InterfaceInfo>) -> Vec<Self> {
+        let mut interfaces: Vec<InterfaceUtilizationV2> = interfaces
+            .iter()
+            .map(|(name, interface_info)| InterfaceUtilizationV2 {
+                name: name.clone(),
+                received_packets: PacketsCount(interface_info.total_packets_downloaded),
+                sent_packets: PacketsCount(interface_info.total_packets_uploaded),
+                orphaned_fragments: PacketsCount(interface_info.orphaned_fragments.fragments),
+                orphaned_fragment_bytes: BytesCount(interface_info.orphaned_fragments.bytes),
+            })
+            .collect();
+        interfaces.sort();
+        interfaces
+    }
+}
+
 #[derive(Ord, PartialOrd, Eq, PartialEq)]
 pub struct ProtocolV1(Protocol);
 
+/// Whether connections of the protocol are published in v1 utilization messages, whose
+/// consumers only know TCP and UDP.
+fn is_published_in_v1(protocol: Protocol) -> bool {
+    matches!(protocol, Protocol::Tcp | Protocol::Udp)
+}
+
 impl Serialize for ProtocolV1 {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -205,10 +1041,276 @@ impl Serialize for ProtocolV1 {
         serializer.serialize_str(match self.0 {
             Protocol::Tcp => "tcp",
             Protocol::Udp => "udp",
+            Protocol::Icmp(_) => "icmp",
+            Protocol::Icmpv6(_) => "icmpv6",
+            Protocol::Sctp => "sctp",
+            Protocol::Other(_) => "other",
         })
     }
 }
 
+/// Replaces the ports of ICMP connections, which are serialized as port 0.
+#[derive(Serialize, Ord, PartialOrd, Eq, PartialEq)]
+#[serde(untagged)]
+pub enum IcmpV1 {
+    Echo {
+        echo_identifier: u16,
+    },
+    Message {
+        #[serde(rename = "type")]
+        icmp_type: u8,
+        code: u8,
+    },
+}
+
+impl IcmpV1 {
+    fn from(protocol: Protocol) -> Option<Self> {
+        match protocol {
+            Protocol::Tcp | Protocol::Udp | Protocol::Sctp | Protocol::Other(_) => None,
+            Protocol::Icmp(kind) | Protocol::Icmpv6(kind) => Some(match kind {
+                IcmpKind::Echo { identifier } => IcmpV1::Echo {
+                    echo_identifier: identifier,
+                },
+                IcmpKind::Message { icmp_type, code } => IcmpV1::Message { icmp_type, code },
+            }),
+        }
+    }
+}
+
+/// The tunnel a connection has been decapsulated from, `received` and `sent` counting the
+/// bytes of the tunnel headers.
+#[derive(Serialize, Ord, PartialOrd, Eq, PartialEq)]
+pub struct TunnelV1 {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub remote_endpoint: IpAddr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vni: Option<u32>,
+    pub received: BytesCount,
+    pub sent: BytesCount,
+}
+
+impl TunnelV1 {
+    fn from(tunnel: Option<Tunnel>, overhead_received: u128, overhead_sent: u128) -> Option<Self> {
+        let tunnel = tunnel?;
+        Some(TunnelV1 {
+            kind: match tunnel.kind {
+                TunnelKind::Vxlan => "vxlan",
+                TunnelKind::Geneve => "geneve",
+                TunnelKind::Gre => "gre",
+            },
+            remote_endpoint: tunnel.remote_endpoint,
+            vni: tunnel.vni,
+            received: BytesCount(overhead_received),
+            sent: BytesCount(overhead_sent),
+        })
+    }
+}
+
+/// What the client announced in the ClientHello of a TLS connection.
+#[derive(Serialize, Ord, PartialOrd, Eq, PartialEq)]
+pub struct TlsV1 {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_name: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub alpn: Vec<String>,
+}
+
+impl TlsV1 {
+    fn from(client_hello: &TlsClientHello) -> Self {
+        TlsV1 {
+            server_name: client_hello.server_name.clone(),
+            alpn: client_hello.alpn.clone(),
+        }
+    }
+}
+
+/// The version and the server chosen connection ID of QUIC connections, connections sharing the
+/// connection ID being paths of the same migrated connection.
+#[derive(Serialize, Ord, PartialOrd, Eq, PartialEq)]
+pub struct QuicV1 {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection_id: Option<String>,
+}
+
+impl QuicV1 {
+    fn from(quic: &QuicSample) -> Self {
+        QuicV1 {
+            version: quic.version.map(|version| format!("{:#010x}", version)),
+            connection_id: quic
+                .connection_id
+                .map(|connection_id| connection_id.to_string()),
+        }
+    }
+}
+
+/// The tuples of the conntrack entry of a connection, as seen before and after address
+/// translation.
+#[derive(Serialize, Ord, PartialOrd, Eq, PartialEq)]
+pub struct ConntrackV1 {
+    pub original: ConntrackTupleV1,
+    pub reply: ConntrackTupleV1,
+}
+
+#[derive(Serialize, Ord, PartialOrd, Eq, PartialEq)]
+pub struct ConntrackTupleV1 {
+    pub source_socket_address: SocketAddr,
+    pub destination_socket_address: SocketAddr,
+}
+
+impl ConntrackV1 {
+    fn from(entry: &ConntrackEntry) -> Self {
+        let tuple = |flow: &TransitFlow| ConntrackTupleV1 {
+            source_socket_address: flow.source.into(),
+            destination_socket_address: flow.destination.into(),
+        };
+        ConntrackV1 {
+            original: tuple(&entry.original),
+            reply: tuple(&entry.reply),
+        }
+    }
+}
+
+#[derive(Serialize, Ord, PartialOrd, Eq, PartialEq)]
+pub struct ProcessV1 {
+    pub pid: i32,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<ContainerV1>,
+    #[serde(flatten)]
+    pub systemd_unit: Option<SystemdUnitV1>,
+}
+
+impl ProcessV1 {
+    fn from(process: &Process) -> Self {
+        ProcessV1 {
+            pid: process.pid,
+            name: process.name.clone(),
+            container: process.container.as_ref().map(ContainerV1::from),
+            systemd_unit: process.systemd_unit.as_ref().map(SystemdUnitV1::from),
+        }
+    }
+}
+
+/// The systemd unit of a process, flattened into the process or open socket.
+#[derive(Clone, Serialize, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct SystemdUnitV1 {
+    #[serde(rename = "systemd_unit")]
+    pub unit: String,
+    #[serde(rename = "systemd_slice", skip_serializing_if = "Option::is_none")]
+    pub slice: Option<String>,
+    #[serde(rename = "systemd_session", skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+}
+
+impl SystemdUnitV1 {
+    fn from(systemd_unit: &SystemdUnit) -> Self {
+        SystemdUnitV1 {
+            unit: systemd_unit.unit.clone(),
+            slice: systemd_unit.slice.clone(),
+            session: systemd_unit.session.clone(),
+        }
+    }
+}
+
+/// Bytes of all connections of processes of a systemd unit, see `UtilizationDetails`.
+#[derive(Serialize, Ord, PartialOrd, Eq, PartialEq)]
+pub struct SystemdUnitUtilizationV1 {
+    #[serde(flatten)]
+    pub systemd_unit: SystemdUnitV1,
+    pub connections: usize,
+    pub received: BytesCount,
+    pub sent: BytesCount,
+}
+
+impl SystemdUnitUtilizationV1 {
+    fn all(utilization: &Utilization, details: UtilizationDetails) -> Vec<Self> {
+        if !details.systemd_units {
+            return vec![];
+        }
+        let mut units: HashMap<SystemdUnitV1, (usize, u128, u128)> = HashMap::new();
+        for connection_info in utilization.connections.values() {
+            let systemd_unit = connection_info
+                .process
+                .as_ref()
+                .and_then(|process| process.systemd_unit.as_ref());
+            if let Some(systemd_unit) = systemd_unit {
+                let totals = units.entry(SystemdUnitV1::from(systemd_unit)).or_default();
+                totals.0 += 1;
+                totals.1 += connection_info.total_bytes_downloaded;
+                totals.2 += connection_info.total_bytes_uploaded;
+            }
+        }
+        let mut units: Vec<SystemdUnitUtilizationV1> = units
+            .into_iter()
+            .map(
+                |(systemd_unit, (connections, received, sent))| SystemdUnitUtilizationV1 {
+                    systemd_unit,
+                    connections,
+                    received: BytesCount(received),
+                    sent: BytesCount(sent),
+                },
+            )
+            .collect();
+        units.sort();
+        units
+    }
+}
+
+/// The container of a process and, if run by Kubernetes, its pod.
+#[derive(Serialize, Ord, PartialOrd, Eq, PartialEq)]
+pub struct ContainerV1 {
+    pub id: String,
+    pub runtime: ContainerRuntimeV1,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pod_uid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pod_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pod_namespace: Option<String>,
+}
+
+impl ContainerV1 {
+    fn from(container: &Container) -> Self {
+        ContainerV1 {
+            id: container.id.clone(),
+            runtime: ContainerRuntimeV1(container.runtime),
+            name: container.name.clone(),
+            pod_uid: container.pod_uid.clone(),
+            pod_name: container.pod_name.clone(),
+            pod_namespace: container.pod_namespace.clone(),
+        }
+    }
+}
+
+#[derive(Ord, PartialOrd, Eq, PartialEq)]
+pub struct ContainerRuntimeV1(ContainerRuntime);
+
+impl Serialize for ContainerRuntimeV1 {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.0.to_string())
+    }
+}
+
+#[derive(Ord, PartialOrd, Eq, PartialEq)]
+pub struct ScopeV1(Scope);
+
+impl Serialize for ScopeV1 {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.0.to_string())
+    }
+}
+
 #[derive(Ord, PartialOrd, Eq, PartialEq)]
 pub struct BytesCount(u128);
 
@@ -221,6 +1323,30 @@ impl Serialize for BytesCount {
     }
 }
 
+#[derive(Ord, PartialOrd, Eq, PartialEq)]
+pub struct PacketsCount(u128);
+
+impl Serialize for PacketsCount {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.0.to_string().as_str())
+    }
+}
+
+#[derive(Ord, PartialOrd, Eq, PartialEq)]
+pub struct HandshakesCount(u128);
+
+impl Serialize for HandshakesCount {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.0.to_string().as_str())
+    }
+}
+
 #[derive(Serialize, Ord, PartialOrd, Eq, PartialEq)]
 pub struct OpenSocketV1 {
     pub socket_address: SocketAddr,
@@ -228,6 +1354,71 @@ pub struct OpenSocketV1 {
     pub process: String,
 }
 
+impl OpenSocketV1 {
+    fn from(socket: LocalSocket, process_details: &ProcessDetails) -> Self {
+        OpenSocketV1 {
+            socket_address: socket.into(),
+            protocol: ProtocolV1(socket.protocol),
+            process: process_details.process.name.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Ord, PartialOrd, Eq, PartialEq)]
+pub struct OpenSocketV2 {
+    #[serde(flatten)]
+    pub open_socket: OpenSocketV1,
+    pub pid: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_pid: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub executable: Option<PathBuf>,
+    /// Truncated and with the values of secret arguments redacted, see `ConfigurationDetails`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command_line: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_time: Option<TimestampV1>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<ContainerV1>,
+    #[serde(flatten)]
+    pub systemd_unit: Option<SystemdUnitV1>,
+}
+
+impl OpenSocketV2 {
+    fn from(
+        socket: LocalSocket,
+        process_details: &ProcessDetails,
+        details: &ConfigurationDetails,
+    ) -> Self {
+        OpenSocketV2 {
+            open_socket: OpenSocketV1::from(socket, process_details),
+            pid: process_details.process.pid,
+            parent_pid: process_details.parent_pid,
+            executable: process_details.executable.clone(),
+            command_line: command_line(&process_details.command_line, details),
+            uid: process_details.uid,
+            user_name: process_details.user_name.clone(),
+            start_time: process_details
+                .start_time
+                .map(|start_time| TimestampV1(start_time.into())),
+            container: process_details
+                .process
+                .container
+                .as_ref()
+                .map(ContainerV1::from),
+            systemd_unit: process_details
+                .process
+                .systemd_unit
+                .as_ref()
+                .map(SystemdUnitV1::from),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
@@ -237,15 +1428,32 @@ mod tests {
 
     use assert_json_diff::assert_json_eq;
     use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
+    use pnet::packet::tcp::TcpFlags;
     use serde_json::json;
     use serde_json::{from_str, Value};
     use time::macros::datetime;
 
-    use crate::network::{Connection, ConnectionInfo, Socket};
+    use crate::network::fixtures::segment;
+    use crate::network::{
+        Connection, ConnectionId, ConnectionInfo, ConntrackTable, Direction, DnsAnswer, DnsCache,
+        InterfaceInfo, OrphanedFragments, Segment, Socket, TcpSample,
+    };
     use crate::LocalSocket;
 
     use super::*;
 
+    fn process_details(name: &str) -> ProcessDetails {
+        ProcessDetails {
+            process: Process {
+                pid: 1,
+                name: name.to_string(),
+                container: None,
+                systemd_unit: None,
+            },
+            ..ProcessDetails::default()
+        }
+    }
+
     #[test]
     fn should_serialize_network_configuration_v1_measurement_message_json() {
         // given
@@ -332,7 +1540,7 @@ mod tests {
                                 port: 37863,
                                 protocol: Protocol::Tcp,
                             },
-                            "java".to_string(),
+                            process_details("java"),
                         ),
                         (
                             LocalSocket {
@@ -340,7 +1548,7 @@ mod tests {
                                 port: 68,
                                 protocol: Protocol::Udp,
                             },
-                            "dhclient".to_string(),
+                            process_details("dhclient"),
                         ),
                     ]),
                 },
@@ -414,6 +1622,176 @@ mod tests {
         assert_json_eq!(actual, expected);
     }
 
+    #[test]
+    fn should_serialize_process_identity_of_open_sockets_in_configuration_v2() {
+        // given
+        let message = Message::network_configuration(
+            MachineId::new("<machine-id>".to_string()),
+            SystemTime::from(datetime!(2022-05-06 15:14:51 utc)),
+            None,
+            "some-host.example.com".to_string(),
+            vec![],
+            OpenSockets {
+                sockets_to_procs: HashMap::from([(
+                    LocalSocket {
+                        ip: IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                        port: 5432,
+                        protocol: Protocol::Tcp,
+                    },
+                    ProcessDetails {
+                        process: Process {
+                            pid: 4242,
+                            name: "java".to_string(),
+                            container: None,
+                            systemd_unit: None,
+                        },
+                        parent_pid: Some(1),
+                        executable: Some("/usr/lib/jvm/bin/java".into()),
+                        command_line: vec![
+                            "java".to_string(),
+                            "-Ddb.password=hunter2".to_string(),
+                            "--token".to_string(),
+                            "abc".to_string(),
+                            "-jar".to_string(),
+                            "service-with-a-long-name.jar".to_string(),
+                        ],
+                        uid: Some(1000),
+                        user_name: Some("service".to_string()),
+                        start_time: Some(SystemTime::from(datetime!(2022-05-06 12:00:00 utc))),
+                    },
+                )]),
+            },
+            &ConfigurationDetails {
+                version: ConfigurationMessageVersion::V2,
+                command_line_length: 66,
+                redacted_arguments: vec!["password".to_string(), "token".to_string()],
+            },
+        );
+
+        // when
+        let result = serde_json::to_value(&message).unwrap();
+
+        // then
+        assert_json_eq!(
+            result["type"].clone(),
+            json!("bandwhichd/measurement/agent-network-configuration/v2")
+        );
+        assert_json_eq!(
+            result["content"]["open_sockets"].clone(),
+            json!([{
+                "socket_address": "[::]:5432",
+                "protocol": "tcp",
+                "process": "java",
+                "pid": 4242,
+                "parent_pid": 1,
+                "executable": "/usr/lib/jvm/bin/java",
+                "command_line": "java -Ddb.password=<redacted> --token <redacted> -jar service-with",
+                "uid": 1000,
+                "user_name": "service",
+                "start_time": "2022-05-06T12:00:00Z"
+            }])
+        );
+    }
+
+    #[test]
+    fn should_redact_arguments_by_whole_words_of_their_names() {
+        // given
+        let details = ConfigurationDetails {
+            version: ConfigurationMessageVersion::V2,
+            command_line_length: 256,
+            redacted_arguments: vec!["key".to_string(), "client-secret".to_string()],
+        };
+        let arguments = [
+            "app",
+            "--keyboard=us",
+            "--monkey",
+            "patch",
+            "--api-key=abc",
+            "-DsigningKey=def",
+            "--KEY",
+            "ghi",
+            "--client_secret=jkl",
+            "--secret=mno",
+        ]
+        .map(String::from);
+
+        // when
+        let result = command_line(&arguments, &details);
+
+        // then
+        assert_eq!(
+            result.unwrap(),
+            "app --keyboard=us --monkey patch --api-key=<redacted> -DsigningKey=<redacted> \
+             --KEY <redacted> --client_secret=<redacted> --secret=mno"
+        );
+    }
+
+    #[test]
+    fn should_serialize_systemd_unit_of_open_sockets_only_in_configuration_v2() {
+        // given
+        let open_sockets = || OpenSockets {
+            sockets_to_procs: HashMap::from([(
+                LocalSocket {
+                    ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                    port: 80,
+                    protocol: Protocol::Tcp,
+                },
+                ProcessDetails {
+                    process: Process {
+                        pid: 812,
+                        name: "nginx".to_string(),
+                        container: None,
+                        systemd_unit: Some(SystemdUnit {
+                            unit: "nginx.service".to_string(),
+                            slice: Some("system.slice".to_string()),
+                            session: None,
+                        }),
+                    },
+                    ..ProcessDetails::default()
+                },
+            )]),
+        };
+        let message = |version| {
+            Message::network_configuration(
+                MachineId::new("<machine-id>".to_string()),
+                SystemTime::from(datetime!(2022-05-06 15:14:51 utc)),
+                None,
+                "some-host.example.com".to_string(),
+                vec![],
+                open_sockets(),
+                &ConfigurationDetails {
+                    version,
+                    ..ConfigurationDetails::default()
+                },
+            )
+        };
+
+        // when
+        let v1 = serde_json::to_value(message(ConfigurationMessageVersion::V1)).unwrap();
+        let v2 = serde_json::to_value(message(ConfigurationMessageVersion::V2)).unwrap();
+
+        // then
+        assert_json_eq!(
+            v1["content"]["open_sockets"].clone(),
+            json!([{
+                "socket_address": "0.0.0.0:80",
+                "protocol": "tcp",
+                "process": "nginx"
+            }])
+        );
+        assert_json_eq!(
+            v2["content"]["open_sockets"].clone(),
+            json!([{
+                "socket_address": "0.0.0.0:80",
+                "protocol": "tcp",
+                "process": "nginx",
+                "pid": 812,
+                "systemd_unit": "nginx.service",
+                "systemd_slice": "system.slice"
+            }])
+        );
+    }
+
     #[test]
     fn should_serialize_network_utilization_v1_measurement_message_json() {
         // given
@@ -433,11 +1811,30 @@ mod tests {
                                     port: 36070,
                                     protocol: Protocol::Tcp,
                                 },
+                                vni: None,
                             },
                             ConnectionInfo {
                                 interface_name: "lo".to_string(),
+                                vlan_ids: vec![],
+                                tunnel: None,
                                 total_bytes_downloaded: 0,
                                 total_bytes_uploaded: 13882,
+                                total_packets_downloaded: 0,
+                                total_packets_uploaded: 0,
+                                total_header_bytes_downloaded: 0,
+                                total_header_bytes_uploaded: 0,
+                                total_payload_bytes_downloaded: 0,
+                                total_payload_bytes_uploaded: 0,
+                                total_tunnel_overhead_bytes_downloaded: 0,
+                                total_tunnel_overhead_bytes_uploaded: 0,
+                                tcp: None,
+                                tcp_performance: None,
+                                tls: None,
+                                quic: None,
+                                remote_names: vec![],
+                                scope: Scope::Unicast,
+                                conntrack: None,
+                                process: None,
                             },
                         ),
                         (
@@ -451,11 +1848,30 @@ mod tests {
                                     port: 8080,
                                     protocol: Protocol::Tcp,
                                 },
+                                vni: None,
                             },
                             ConnectionInfo {
                                 interface_name: "lo".to_string(),
+                                vlan_ids: vec![],
+                                tunnel: None,
                                 total_bytes_downloaded: 608,
                                 total_bytes_uploaded: 0,
+                                total_packets_downloaded: 0,
+                                total_packets_uploaded: 0,
+                                total_header_bytes_downloaded: 0,
+                                total_header_bytes_uploaded: 0,
+                                total_payload_bytes_downloaded: 0,
+                                total_payload_bytes_uploaded: 0,
+                                total_tunnel_overhead_bytes_downloaded: 0,
+                                total_tunnel_overhead_bytes_uploaded: 0,
+                                tcp: None,
+                                tcp_performance: None,
+                                tls: None,
+                                quic: None,
+                                remote_names: vec![],
+                                scope: Scope::Unicast,
+                                conntrack: None,
+                                process: None,
                             },
                         ),
                         (
@@ -469,17 +1885,40 @@ mod tests {
                                     port: 43254,
                                     protocol: Protocol::Udp,
                                 },
+                                vni: None,
                             },
                             ConnectionInfo {
                                 interface_name: "tun0".to_string(),
+                                vlan_ids: vec![],
+                                tunnel: None,
                                 total_bytes_downloaded: 120,
                                 total_bytes_uploaded: 64,
+                                total_packets_downloaded: 0,
+                                total_packets_uploaded: 0,
+                                total_header_bytes_downloaded: 0,
+                                total_header_bytes_uploaded: 0,
+                                total_payload_bytes_downloaded: 0,
+                                total_payload_bytes_uploaded: 0,
+                                total_tunnel_overhead_bytes_downloaded: 0,
+                                total_tunnel_overhead_bytes_uploaded: 0,
+                                tcp: None,
+                                tcp_performance: None,
+                                tls: None,
+                                quic: None,
+                                remote_names: vec![],
+                                scope: Scope::Unicast,
+                                conntrack: None,
+                                process: None,
                             },
                         ),
                     ]),
+                    interfaces: HashMap::new(),
+                    transit_flows: HashMap::new(),
+                    forwarded_flows: HashMap::new(),
                     start: SystemTime::from(datetime!(2022-05-06 15:14:51.74223728 utc)),
                     stop: SystemTime::from(datetime!(2022-05-06 15:15:01.84260156 utc)),
                 },
+                UtilizationDetails::default(),
             ));
 
         // when
@@ -522,4 +1961,864 @@ mod tests {
         });
         assert_json_eq!(actual, expected);
     }
+
+    /// Serializes a network utilization measurement in the given message version.
+    fn utilization_message(utilization: &Utilization, version: UtilizationMessageVersion) -> Value {
+        serde_json::to_value(Message::network_utilization(
+            MachineId::new("<machine-id>".to_string()),
+            utilization.clone(),
+            UtilizationDetails {
+                version,
+                ..UtilizationDetails::default()
+            },
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn should_serialize_orphaned_fragments_of_interfaces_from_v2_on() {
+        // given
+        let mut utilization =
+            Utilization::starting_at(SystemTime::from(datetime!(2022-05-06 15:14:51 utc)));
+        utilization.update_interface(
+            "eth0",
+            InterfaceInfo {
+                orphaned_fragments: OrphanedFragments {
+                    fragments: 2,
+                    bytes: 2960,
+                },
+                ..InterfaceInfo::default()
+            },
+        );
+
+        // when
+        let v1 = utilization_message(&utilization, UtilizationMessageVersion::V1);
+        let v2 = utilization_message(&utilization, UtilizationMessageVersion::V2);
+
+        // then
+        assert_eq!(v1["content"].get("interfaces"), None);
+        assert_json_eq!(
+            v2["content"]["interfaces"].clone(),
+            json!([
+                {
+                    "name": "eth0",
+                    "received_packets": "0",
+                    "sent_packets": "0",
+                    "orphaned_fragments": "2",
+                    "orphaned_fragment_bytes": "2960"
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn should_serialize_connections_without_ports() {
+        // given
+        let mut utilization =
+            Utilization::starting_at(SystemTime::from(datetime!(2022-05-06 15:14:51 utc)));
+        for (protocol, data_length) in [
+            (Protocol::Icmp(IcmpKind::Echo { identifier: 4660 }), 64),
+            (
+                Protocol::Icmpv6(IcmpKind::Message {
+                    icmp_type: 1,
+                    code: 4,
+                }),
+                48,
+            ),
+            (Protocol::Other(50), 1400),
+        ] {
+            utilization.update(segment(
+                Connection::new(
+                    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 0),
+                    IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+                    0,
+                    protocol,
+                ),
+                Direction::Download,
+                data_length,
+            ));
+        }
+        let message = Message::network_utilization(
+            MachineId::new("<machine-id>".to_string()),
+            utilization,
+            UtilizationDetails {
+                version: UtilizationMessageVersion::V2,
+                ..UtilizationDetails::default()
+            },
+        );
+
+        // when
+        let result = serde_json::to_value(&message).unwrap();
+
+        // then
+        assert_json_eq!(
+            result["content"]["connections"].clone(),
+            json!([
+                {
+                    "interface_name": "eth0",
+                    "local_socket_address": "10.0.0.1:0",
+                    "remote_socket_address": "10.0.0.2:0",
+                    "protocol": "icmp",
+                    "icmp": {
+                        "echo_identifier": 4660
+                    },
+                    "received": "64",
+                    "sent": "0",
+                    "received_header_bytes": "0",
+                    "received_payload_bytes": "64",
+                    "sent_header_bytes": "0",
+                    "sent_payload_bytes": "0",
+                    "received_packets": "1",
+                    "sent_packets": "0"
+                },
+                {
+                    "interface_name": "eth0",
+                    "local_socket_address": "10.0.0.1:0",
+                    "remote_socket_address": "10.0.0.2:0",
+                    "protocol": "icmpv6",
+                    "icmp": {
+                        "type": 1,
+                        "code": 4
+                    },
+                    "received": "48",
+                    "sent": "0",
+                    "received_header_bytes": "0",
+                    "received_payload_bytes": "48",
+                    "sent_header_bytes": "0",
+                    "sent_payload_bytes": "0",
+                    "received_packets": "1",
+                    "sent_packets": "0"
+                },
+                {
+                    "interface_name": "eth0",
+                    "local_socket_address": "10.0.0.1:0",
+                    "remote_socket_address": "10.0.0.2:0",
+                    "protocol": "other",
+                    "ip_protocol": 50,
+                    "received": "1400",
+                    "sent": "0",
+                    "received_header_bytes": "0",
+                    "received_payload_bytes": "1400",
+                    "sent_header_bytes": "0",
+                    "sent_payload_bytes": "0",
+                    "received_packets": "1",
+                    "sent_packets": "0"
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn should_omit_connections_of_other_protocols_in_network_utilization_v1_measurement_message() {
+        // given
+        let mut utilization =
+            Utilization::starting_at(SystemTime::from(datetime!(2022-05-06 15:14:51 utc)));
+        for protocol in [
+            Protocol::Icmp(IcmpKind::Echo { identifier: 4660 }),
+            Protocol::Sctp,
+            Protocol::Other(50),
+            Protocol::Udp,
+        ] {
+            utilization.update(segment(
+                Connection::new(
+                    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 0),
+                    IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+                    0,
+                    protocol,
+                ),
+                Direction::Download,
+                64,
+            ));
+        }
+        let message = Message::network_utilization(
+            MachineId::new("<machine-id>".to_string()),
+            utilization,
+            UtilizationDetails::default(),
+        );
+
+        // when
+        let result = serde_json::to_value(&message).unwrap();
+
+        // then
+        assert_json_eq!(
+            result["content"]["connections"].clone(),
+            json!([
+                {
+                    "interface_name": "eth0",
+                    "local_socket_address": "10.0.0.1:0",
+                    "remote_socket_address": "10.0.0.2:0",
+                    "protocol": "udp",
+                    "received": "64",
+                    "sent": "0"
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn should_serialize_tunnel_of_decapsulated_connections_from_v2_on() {
+        // given
+        let mut utilization =
+            Utilization::starting_at(SystemTime::from(datetime!(2022-05-06 15:14:51 utc)));
+        utilization.update(Segment {
+            tunnel: Some(Tunnel {
+                kind: TunnelKind::Vxlan,
+                remote_endpoint: IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2)),
+                vni: Some(1),
+            }),
+            tunnel_overhead_length: 50,
+            payload_length: 12,
+            ..segment(
+                Connection::new(
+                    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 244, 2, 9)), 53),
+                    IpAddr::V4(Ipv4Addr::new(10, 244, 1, 7)),
+                    40000,
+                    Protocol::Udp,
+                ),
+                Direction::Upload,
+                20,
+            )
+        });
+
+        // when
+        let v1 = utilization_message(&utilization, UtilizationMessageVersion::V1);
+        let result = utilization_message(&utilization, UtilizationMessageVersion::V2);
+
+        // then
+        assert_eq!(v1["content"]["connections"][0].get("tunnel"), None);
+        assert_json_eq!(
+            result["content"]["connections"][0]["tunnel"].clone(),
+            json!({
+                "type": "vxlan",
+                "remote_endpoint": "192.168.0.2",
+                "vni": 1,
+                "received": "0",
+                "sent": "50"
+            })
+        );
+    }
+
+    #[test]
+    fn should_serialize_remote_names_of_connections() {
+        // given
+        let start = SystemTime::from(datetime!(2022-05-06 15:14:51 utc));
+        let remote = IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34));
+        let dns_cache = DnsCache::default();
+        dns_cache.insert(
+            vec![DnsAnswer {
+                address: remote,
+                name: "www.example.com".to_string(),
+                ttl: std::time::Duration::from_secs(60),
+            }],
+            start,
+        );
+        let mut utilization = Utilization::starting_at(start);
+        utilization.update(Segment {
+            timestamp: start,
+            payload_length: 0,
+            ..segment(
+                Connection::new(
+                    SocketAddr::new(remote, 443),
+                    IpAddr::V4(Ipv4Addr::new(192, 168, 10, 87)),
+                    40000,
+                    Protocol::Tcp,
+                ),
+                Direction::Upload,
+                20,
+            )
+        });
+        utilization.resolve_remote_names(&dns_cache);
+        let message =
+            Message::NetworkUtilizationV1Measurement(NetworkUtilizationV1MeasurementMessage::from(
+                MachineId::new("<machine-id>".to_string()),
+                utilization,
+                UtilizationDetails::default(),
+            ));
+
+        // when
+        let result = serde_json::to_value(&message).unwrap();
+
+        // then
+        assert_json_eq!(
+            result["content"]["connections"][0]["remote_names"].clone(),
+            json!(["www.example.com"])
+        );
+    }
+
+    #[test]
+    fn should_serialize_tls_client_hello_of_connections_from_v2_on() {
+        // given
+        let mut utilization =
+            Utilization::starting_at(SystemTime::from(datetime!(2022-05-06 15:14:51 utc)));
+        utilization.update(Segment {
+            payload_length: 517,
+            tls_client_hello: Some(TlsClientHello {
+                server_name: Some("www.example.com".to_string()),
+                alpn: vec!["h2".to_string(), "http/1.1".to_string()],
+            }),
+            ..segment(
+                Connection::new(
+                    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34)), 443),
+                    IpAddr::V4(Ipv4Addr::new(192, 168, 10, 87)),
+                    40000,
+                    Protocol::Tcp,
+                ),
+                Direction::Upload,
+                537,
+            )
+        });
+
+        // when
+        let v1 = utilization_message(&utilization, UtilizationMessageVersion::V1);
+        let result = utilization_message(&utilization, UtilizationMessageVersion::V2);
+
+        // then
+        assert_eq!(v1["content"]["connections"][0].get("tls"), None);
+        assert_json_eq!(
+            result["content"]["connections"][0]["tls"].clone(),
+            json!({
+                "server_name": "www.example.com",
+                "alpn": ["h2", "http/1.1"]
+            })
+        );
+    }
+
+    #[test]
+    fn should_publish_transit_flows_in_separate_message() {
+        // given
+        let mut utilization =
+            Utilization::starting_at(SystemTime::from(datetime!(2022-05-06 15:14:51 utc)));
+        utilization.stop = SystemTime::from(datetime!(2022-05-06 15:15:01 utc));
+        let source = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 10, 34)), 40000);
+        let destination = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34)), 443);
+        utilization.update(Segment {
+            interface_name: "span0".to_string(),
+            payload_length: 1460,
+            transit: Some(TransitFlow::new(source, destination, Protocol::Tcp)),
+            ..segment(
+                Connection::new(source, destination.ip(), destination.port(), Protocol::Tcp),
+                Direction::Download,
+                1500,
+            )
+        });
+
+        // when
+        let transit = Message::network_transit(
+            MachineId::new("<machine-id>".to_string()),
+            &utilization,
+            UtilizationDetails::default(),
+        )
+        .unwrap();
+        let utilization = Message::network_utilization(
+            MachineId::new("<machine-id>".to_string()),
+            utilization,
+            UtilizationDetails::default(),
+        );
+
+        // then
+        assert_json_eq!(
+            serde_json::to_value(&transit).unwrap(),
+            json!({
+                "type": "bandwhichd/measurement/agent-network-transit/v1",
+                "content": {
+                    "machine_id": "d2c1d575-326e-b00b-c3eb-26ef934301f0",
+                    "timeframe": "2022-05-06T15:14:51Z/PT10S",
+                    "accounting_mode": "l4",
+                    "flows": [
+                        {
+                            "interface_name": "span0",
+                            "source_socket_address": "192.168.10.34:40000",
+                            "destination_socket_address": "93.184.216.34:443",
+                            "protocol": "tcp",
+                            "bytes": "1500",
+                            "payload_bytes": "1460",
+                            "packets": "1"
+                        }
+                    ]
+                }
+            })
+        );
+        assert_json_eq!(
+            serde_json::to_value(&utilization).unwrap()["content"]["connections"].clone(),
+            json!([])
+        );
+    }
+
+    #[test]
+    fn should_serialize_translated_legs_of_forwarded_flows() {
+        // given
+        let mut utilization =
+            Utilization::starting_at(SystemTime::from(datetime!(2022-05-06 15:14:51 utc)));
+        let client = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10)), 40000);
+        let router = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 1)), 61000);
+        let server = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34)), 443);
+        utilization.update(Segment {
+            interface_name: "wan0".to_string(),
+            payload_length: 0,
+            forwarded: Some(ForwardedFlow {
+                ingress_interface_name: "lan0".to_string(),
+                egress_interface_name: "wan0".to_string(),
+                ingress: TransitFlow::new(client, server, Protocol::Tcp),
+                egress: TransitFlow::new(router, server, Protocol::Tcp),
+            }),
+            ..segment(
+                Connection::new(server, router.ip(), router.port(), Protocol::Tcp),
+                Direction::Upload,
+                60,
+            )
+        });
+
+        // when
+        let result = serde_json::to_value(
+            Message::network_transit(
+                MachineId::new("<machine-id>".to_string()),
+                &utilization,
+                UtilizationDetails::default(),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        // then
+        assert_json_eq!(
+            result["content"]["forwarded"].clone(),
+            json!([
+                {
+                    "ingress_interface_name": "lan0",
+                    "egress_interface_name": "wan0",
+                    "source_socket_address": "192.168.1.10:40000",
+                    "destination_socket_address": "93.184.216.34:443",
+                    "translated_source_socket_address": "203.0.113.1:61000",
+                    "protocol": "tcp",
+                    "bytes": "60",
+                    "payload_bytes": "0",
+                    "packets": "1"
+                }
+            ])
+        );
+        assert_json_eq!(result["content"]["flows"].clone(), json!([]));
+    }
+
+    #[test]
+    fn should_serialize_scope_of_non_unicast_connections_from_v2_on() {
+        // given
+        let mut utilization =
+            Utilization::starting_at(SystemTime::from(datetime!(2022-05-06 15:14:51 utc)));
+        for (destination, scope) in [
+            (Ipv4Addr::new(224, 0, 0, 251), Scope::Multicast),
+            (Ipv4Addr::new(192, 168, 10, 1), Scope::Unicast),
+        ] {
+            utilization.update(Segment {
+                payload_length: 32,
+                scope,
+                ..segment(
+                    Connection::new(
+                        SocketAddr::new(IpAddr::V4(destination), 5353),
+                        IpAddr::V4(Ipv4Addr::new(192, 168, 10, 87)),
+                        5353,
+                        Protocol::Udp,
+                    ),
+                    Direction::Upload,
+                    40,
+                )
+            });
+        }
+
+        // when
+        let v1 = utilization_message(&utilization, UtilizationMessageVersion::V1);
+        let result = utilization_message(&utilization, UtilizationMessageVersion::V2);
+
+        // then
+        assert_eq!(v1["content"]["connections"][1].get("scope"), None);
+        assert_eq!(result["content"]["connections"][0].get("scope"), None);
+        assert_json_eq!(
+            result["content"]["connections"][1]["scope"].clone(),
+            json!("multicast")
+        );
+    }
+
+    #[test]
+    fn should_serialize_conntrack_tuples_of_annotated_connections() {
+        // given
+        let mut utilization =
+            Utilization::starting_at(SystemTime::from(datetime!(2022-05-06 15:14:51 utc)));
+        utilization.update(Segment {
+            payload_length: 0,
+            ..segment(
+                Connection::new(
+                    "93.184.216.34:443".parse().unwrap(),
+                    IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10)),
+                    40000,
+                    Protocol::Tcp,
+                ),
+                Direction::Upload,
+                40,
+            )
+        });
+        utilization.annotate_conntrack(&ConntrackTable::new(vec![ConntrackEntry {
+            original: TransitFlow::new(
+                "192.168.1.10:40000".parse().unwrap(),
+                "93.184.216.34:443".parse().unwrap(),
+                Protocol::Tcp,
+            ),
+            reply: TransitFlow::new(
+                "93.184.216.34:443".parse().unwrap(),
+                "203.0.113.1:61000".parse().unwrap(),
+                Protocol::Tcp,
+            ),
+            original_counters: None,
+            reply_counters: None,
+        }]));
+        let message =
+            Message::NetworkUtilizationV1Measurement(NetworkUtilizationV1MeasurementMessage::from(
+                MachineId::new("<machine-id>".to_string()),
+                utilization,
+                UtilizationDetails::default(),
+            ));
+
+        // when
+        let result = serde_json::to_value(&message).unwrap();
+
+        // then
+        assert_json_eq!(
+            result["content"]["connections"][0]["conntrack"].clone(),
+            json!({
+                "original": {
+                    "source_socket_address": "192.168.1.10:40000",
+                    "destination_socket_address": "93.184.216.34:443"
+                },
+                "reply": {
+                    "source_socket_address": "93.184.216.34:443",
+                    "destination_socket_address": "203.0.113.1:61000"
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn should_aggregate_connections_by_systemd_unit_from_v2_on() {
+        // given
+        let mut utilization =
+            Utilization::starting_at(SystemTime::from(datetime!(2022-05-06 15:14:51 utc)));
+        for local_port in [40000, 40001] {
+            utilization.update(Segment {
+                payload_length: 60,
+                ..segment(
+                    Connection::new(
+                        "93.184.216.34:443".parse().unwrap(),
+                        IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10)),
+                        local_port,
+                        Protocol::Tcp,
+                    ),
+                    Direction::Download,
+                    100,
+                )
+            });
+        }
+        for info in utilization.connections.values_mut() {
+            info.process = Some(Process {
+                pid: 812,
+                name: "nginx".to_string(),
+                container: None,
+                systemd_unit: Some(SystemdUnit {
+                    unit: "nginx.service".to_string(),
+                    slice: Some("system.slice".to_string()),
+                    session: None,
+                }),
+            });
+        }
+        let message = |version| {
+            Message::network_utilization(
+                MachineId::new("<machine-id>".to_string()),
+                utilization.clone(),
+                UtilizationDetails {
+                    systemd_units: true,
+                    version,
+                    ..UtilizationDetails::default()
+                },
+            )
+        };
+
+        // when
+        let v1 = serde_json::to_value(message(UtilizationMessageVersion::V1)).unwrap();
+        let result = serde_json::to_value(message(UtilizationMessageVersion::V2)).unwrap();
+
+        // then
+        assert_eq!(v1["content"].get("systemd_units"), None);
+        assert_eq!(result["content"]["connections"][0].get("process"), None);
+        assert_json_eq!(
+            result["content"]["systemd_units"].clone(),
+            json!([{
+                "systemd_unit": "nginx.service",
+                "systemd_slice": "system.slice",
+                "connections": 2,
+                "received": "200",
+                "sent": "0"
+            }])
+        );
+    }
+
+    #[test]
+    fn should_serialize_process_of_connections_only_if_enabled() {
+        // given
+        let mut utilization =
+            Utilization::starting_at(SystemTime::from(datetime!(2022-05-06 15:14:51 utc)));
+        utilization.update(segment(
+            Connection::new(
+                "93.184.216.34:443".parse().unwrap(),
+                IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10)),
+                40000,
+                Protocol::Tcp,
+            ),
+            Direction::Download,
+            100,
+        ));
+        for info in utilization.connections.values_mut() {
+            info.process = Some(Process {
+                pid: 812,
+                name: "nginx".to_string(),
+                container: None,
+                systemd_unit: Some(SystemdUnit {
+                    unit: "nginx.service".to_string(),
+                    slice: Some("system.slice".to_string()),
+                    session: None,
+                }),
+            });
+        }
+        let message = Message::network_utilization(
+            MachineId::new("<machine-id>".to_string()),
+            utilization,
+            UtilizationDetails {
+                processes: true,
+                ..UtilizationDetails::default()
+            },
+        );
+
+        // when
+        let result = serde_json::to_value(&message).unwrap();
+
+        // then
+        assert_json_eq!(
+            result["content"]["connections"][0]["process"].clone(),
+            json!({
+                "pid": 812,
+                "name": "nginx",
+                "systemd_unit": "nginx.service",
+                "systemd_slice": "system.slice"
+            })
+        );
+        assert_eq!(result["content"].get("systemd_units"), None);
+    }
+
+    #[test]
+    fn should_serialize_quic_connection_id_of_migrated_paths_from_v2_on() {
+        // given
+        let mut utilization =
+            Utilization::starting_at(SystemTime::from(datetime!(2022-05-06 15:14:51 utc)));
+        for (local_port, version) in [(40000, Some(1)), (40001, None)] {
+            utilization.update(Segment {
+                payload_length: 1200,
+                quic: Some(QuicSample {
+                    version,
+                    connection_id: ConnectionId::new(&[0xc0, 0xff, 0xee, 0x01]),
+                }),
+                ..segment(
+                    Connection::new(
+                        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34)), 443),
+                        IpAddr::V4(Ipv4Addr::new(192, 168, 10, 87)),
+                        local_port,
+                        Protocol::Udp,
+                    ),
+                    Direction::Upload,
+                    1208,
+                )
+            });
+        }
+
+        // when
+        let v1 = utilization_message(&utilization, UtilizationMessageVersion::V1);
+        let result = utilization_message(&utilization, UtilizationMessageVersion::V2);
+
+        // then
+        assert_eq!(v1["content"]["connections"][0].get("quic"), None);
+        assert_json_eq!(
+            result["content"]["connections"][0]["quic"].clone(),
+            json!({
+                "version": "0x00000001",
+                "connection_id": "c0ffee01"
+            })
+        );
+        assert_json_eq!(
+            result["content"]["connections"][1]["quic"].clone(),
+            json!({
+                "connection_id": "c0ffee01"
+            })
+        );
+    }
+
+    #[test]
+    fn should_serialize_network_utilization_v2_measurement_message_json() {
+        // given
+        let mut utilization =
+            Utilization::starting_at(SystemTime::from(datetime!(2022-05-06 15:14:51 utc)));
+        utilization.stop = SystemTime::from(datetime!(2022-05-06 15:15:01 utc));
+        for (seconds, direction, data_length, payload_length, tcp_flags) in [
+            (52, Direction::Upload, 52, 0, TcpFlags::SYN),
+            (
+                53,
+                Direction::Download,
+                1500,
+                1448,
+                TcpFlags::SYN | TcpFlags::ACK,
+            ),
+            (
+                58,
+                Direction::Download,
+                92,
+                40,
+                TcpFlags::FIN | TcpFlags::ACK,
+            ),
+        ] {
+            utilization.update(Segment {
+                timestamp: SystemTime::from(datetime!(2022-05-06 15:14:00 utc))
+                    + std::time::Duration::from_secs(seconds),
+                payload_length,
+                tcp_flags: Some(tcp_flags),
+                ..segment(
+                    Connection::new(
+                        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 443),
+                        IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+                        40000,
+                        Protocol::Tcp,
+                    ),
+                    direction,
+                    data_length,
+                )
+            });
+        }
+        let message = Message::network_utilization(
+            MachineId::new("<machine-id>".to_string()),
+            utilization,
+            UtilizationDetails {
+                version: UtilizationMessageVersion::V2,
+                accounting_mode: AccountingMode::L3,
+                ..UtilizationDetails::default()
+            },
+        );
+
+        // when
+        let result = serde_json::to_value(&message).unwrap();
+
+        // then
+        assert_json_eq!(
+            result,
+            json!({
+                "type": "bandwhichd/measurement/agent-network-utilization/v2",
+                "content": {
+                    "machine_id": "d2c1d575-326e-b00b-c3eb-26ef934301f0",
+                    "timeframe": "2022-05-06T15:14:51Z/PT10S",
+                    "accounting_mode": "l3",
+                    "connections": [
+                        {
+                            "interface_name": "eth0",
+                            "local_socket_address": "10.0.0.1:40000",
+                            "remote_socket_address": "10.0.0.2:443",
+                            "protocol": "tcp",
+                            "received": "1592",
+                            "sent": "52",
+                            "received_header_bytes": "104",
+                            "received_payload_bytes": "1488",
+                            "sent_header_bytes": "52",
+                            "sent_payload_bytes": "0",
+                            "received_packets": "2",
+                            "sent_packets": "1",
+                            "tcp": {
+                                "opened": "2022-05-06T15:14:52Z",
+                                "closed": "2022-05-06T15:14:58Z",
+                                "role": "client",
+                                "resets": "0",
+                                "failed_handshakes": "0"
+                            }
+                        }
+                    ],
+                    "interfaces": [
+                        {
+                            "name": "eth0",
+                            "received_packets": "2",
+                            "sent_packets": "1",
+                            "orphaned_fragments": "0",
+                            "orphaned_fragment_bytes": "0"
+                        }
+                    ]
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn should_serialize_tcp_performance_in_network_utilization_v3_measurement_message() {
+        // given
+        let mut utilization =
+            Utilization::starting_at(SystemTime::from(datetime!(2022-05-06 15:14:51 utc)));
+        for (direction, tcp_sample) in [
+            (
+                Direction::Download,
+                TcpSample {
+                    handshake_rtt: Some(std::time::Duration::from_millis(20)),
+                    smoothed_rtt: Some(std::time::Duration::from_millis(20)),
+                    retransmitted_bytes: 0,
+                    out_of_order: true,
+                },
+            ),
+            (
+                Direction::Upload,
+                TcpSample {
+                    handshake_rtt: None,
+                    smoothed_rtt: Some(std::time::Duration::from_micros(21_250)),
+                    retransmitted_bytes: 1448,
+                    out_of_order: false,
+                },
+            ),
+        ] {
+            utilization.update(Segment {
+                payload_length: 1448,
+                tcp_sample: Some(tcp_sample),
+                ..segment(
+                    Connection::new(
+                        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 443),
+                        IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+                        40000,
+                        Protocol::Tcp,
+                    ),
+                    direction,
+                    1500,
+                )
+            });
+        }
+        let message = Message::network_utilization(
+            MachineId::new("<machine-id>".to_string()),
+            utilization,
+            UtilizationDetails {
+                version: UtilizationMessageVersion::V3,
+                ..UtilizationDetails::default()
+            },
+        );
+
+        // when
+        let result = serde_json::to_value(&message).unwrap();
+
+        // then
+        assert_eq!(
+            result["type"],
+            json!("bandwhichd/measurement/agent-network-utilization/v3")
+        );
+        let connection = &result["content"]["connections"][0];
+        assert_eq!(connection["handshake_rtt"], json!("PT0.02S"));
+        assert_eq!(connection["smoothed_rtt"], json!("PT0.02125S"));
+        assert_eq!(connection["received_retransmitted_bytes"], json!("0"));
+        assert_eq!(connection["sent_retransmitted_bytes"], json!("1448"));
+        assert_eq!(connection["received_out_of_order_segments"], json!("1"));
+        assert_eq!(connection["sent_out_of_order_segments"], json!("0"));
+        assert_eq!(connection["received_packets"], json!("1"));
+    }
 }
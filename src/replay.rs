@@ -0,0 +1,321 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::time::{Duration, SystemTime};
+
+use pnet::datalink::NetworkInterface;
+
+use crate::configuration::{Configuration, ReplayConfiguration};
+use crate::machine_id::MachineId;
+use crate::network::{DnsCache, InterfaceInfo, RecordedFrames, Sniffer, Utilization};
+use crate::publish::{HttpSink, JsonLinesSink, Message, Sink, UtilizationDetails};
+
+/// Replays a recording, publishing to the server if given or printing to stdout otherwise.
+pub fn run(configuration: Configuration, publish_interval: Duration) -> Result<(), failure::Error> {
+    let server = configuration.server;
+    let details = configuration.utilization_details;
+    let decapsulation = configuration.decapsulation;
+    let ignored_scopes = configuration.ignored_scopes;
+    let sniffing = configuration.sniffing;
+    let configuration: ReplayConfiguration = configuration
+        .replay
+        .ok_or_else(|| failure::err_msg("BANDWHICHD_REPLAY_FILE is not set"))?;
+    let frames = RecordedFrames::new(BufReader::new(File::open(&configuration.file)?))?;
+    let network_interface = NetworkInterface {
+        name: configuration.interface_name,
+        description: configuration.file.display().to_string(),
+        index: 0,
+        mac: None,
+        ips: configuration.local_networks,
+        flags: 0,
+    };
+    let mut sniffer = Sniffer::from_frames(network_interface, Box::new(frames))
+        .with_accounting_mode(details.accounting_mode)
+        .with_ignored_scopes(ignored_scopes);
+    if let Some(decapsulation) = decapsulation.for_interface(sniffer.interface_name()) {
+        sniffer = sniffer.with_decapsulation(decapsulation);
+    }
+    if details.remote_names {
+        sniffer = sniffer.with_dns_cache(DnsCache::default());
+    }
+    if sniffing.is_mirror(sniffer.interface_name()) {
+        sniffer = sniffer.with_mirror();
+    }
+    let mut sink: Box<dyn Sink> = match server {
+        Some(server) => Box::new(HttpSink::new(&server)),
+        None => Box::new(JsonLinesSink::new(std::io::stdout())),
+    };
+    replay(
+        MachineId::default(),
+        &mut sniffer,
+        publish_interval,
+        details,
+        sink.as_mut(),
+    )
+}
+
+/// Builds utilization windows from the capture timestamps of all recorded frames.
+///
+/// Windows are aligned to the first segment, the last window ends with the last segment.
+pub fn replay(
+    machine_id: MachineId,
+    sniffer: &mut Sniffer,
+    publish_interval: Duration,
+    details: UtilizationDetails,
+    sink: &mut dyn Sink,
+) -> Result<(), failure::Error> {
+    let mut current: Option<Utilization> = None;
+    let mut last_timestamp = SystemTime::UNIX_EPOCH;
+    // Traffic not attributable to any connection, kept until the first window exists.
+    let mut pending_interface_info: Vec<InterfaceInfo> = vec![];
+
+    while !sniffer.is_exhausted() {
+        let segment = sniffer.next();
+        pending_interface_info.extend(sniffer.take_interface_info());
+        let segment = match segment {
+            Some(segment) => segment,
+            None => continue,
+        };
+        last_timestamp = last_timestamp.max(segment.timestamp);
+        let utilization =
+            current.get_or_insert_with(|| Utilization::starting_at(segment.timestamp));
+        for interface_info in pending_interface_info.drain(..) {
+            utilization.update_interface(sniffer.interface_name(), interface_info);
+        }
+        while segment.timestamp >= utilization.start + publish_interval {
+            let stop = utilization.start + publish_interval;
+            let mut closed = std::mem::replace(utilization, Utilization::starting_at(stop));
+            closed.stop = stop;
+            if let Some(dns_cache) = sniffer.dns_cache() {
+                closed.resolve_remote_names(dns_cache);
+            }
+            publish(&machine_id, closed, details, sink)?;
+        }
+        utilization.update(segment);
+    }
+
+    if let Some(mut utilization) = current {
+        pending_interface_info.extend(sniffer.take_interface_info());
+        for interface_info in pending_interface_info {
+            utilization.update_interface(sniffer.interface_name(), interface_info);
+        }
+        utilization.stop = last_timestamp.max(utilization.start);
+        if let Some(dns_cache) = sniffer.dns_cache() {
+            utilization.resolve_remote_names(dns_cache);
+        }
+        publish(&machine_id, utilization, details, sink)?;
+    }
+    Ok(())
+}
+
+fn publish(
+    machine_id: &MachineId,
+    utilization: Utilization,
+    details: UtilizationDetails,
+    sink: &mut dyn Sink,
+) -> Result<(), failure::Error> {
+    let transit = Message::network_transit(machine_id.clone(), &utilization, details);
+    sink.publish(&Message::network_utilization(
+        machine_id.clone(),
+        utilization,
+        details,
+    ))?;
+    match transit {
+        Some(transit) => sink.publish(&transit),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::io::Cursor;
+    use std::net::Ipv4Addr;
+
+    use ipnetwork::{IpNetwork, Ipv4Network};
+    use pcap_file::pcap::{PcapPacket, PcapWriter};
+    use serde_json::{json, Value};
+
+    use crate::network::fixtures;
+    use crate::publish::UtilizationMessageVersion;
+
+    use super::*;
+
+    struct CollectingSink {
+        messages: Vec<Value>,
+    }
+
+    impl Sink for CollectingSink {
+        fn publish(&mut self, message: &Message) -> Result<(), failure::Error> {
+            self.messages.push(serde_json::to_value(message)?);
+            Ok(())
+        }
+    }
+
+    fn ipv4_udp_frame(
+        source: Ipv4Addr,
+        source_port: u16,
+        destination: Ipv4Addr,
+        destination_port: u16,
+        payload_length: usize,
+    ) -> Vec<u8> {
+        fixtures::ethernet(
+            fixtures::ETHERTYPE_IPV4,
+            &fixtures::ipv4(
+                source,
+                destination,
+                fixtures::PROTOCOL_UDP,
+                &fixtures::udp(source_port, destination_port, &vec![0; payload_length]),
+            ),
+        )
+    }
+
+    fn eth0(local: Ipv4Addr) -> NetworkInterface {
+        NetworkInterface {
+            name: "eth0".to_string(),
+            description: "".to_string(),
+            index: 0,
+            mac: None,
+            ips: vec![IpNetwork::V4(Ipv4Network::new(local, 24).unwrap())],
+            flags: 0,
+        }
+    }
+
+    fn recording(frames: &[(Duration, Vec<u8>)]) -> Vec<u8> {
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        for (timestamp, frame) in frames {
+            writer
+                .write_packet(&PcapPacket {
+                    timestamp: *timestamp,
+                    orig_len: frame.len() as u32,
+                    data: Cow::Borrowed(frame),
+                })
+                .unwrap();
+        }
+        writer.into_writer()
+    }
+
+    #[test]
+    fn should_replay_recording_into_utilization_windows() {
+        // given
+        let local = Ipv4Addr::new(192, 168, 10, 87);
+        let remote = Ipv4Addr::new(192, 168, 10, 34);
+        let start = Duration::from_secs(1_651_850_091);
+        let recording = recording(&[
+            (start, ipv4_udp_frame(local, 43254, remote, 53, 32)),
+            (
+                start + Duration::from_millis(2500),
+                ipv4_udp_frame(remote, 53, local, 43254, 100),
+            ),
+            (
+                start + Duration::from_secs(12),
+                ipv4_udp_frame(remote, 53, local, 43254, 12),
+            ),
+        ]);
+        let frames = RecordedFrames::new(Cursor::new(recording)).unwrap();
+        let mut sniffer = Sniffer::from_frames(eth0(local), Box::new(frames));
+        let mut sink = CollectingSink { messages: vec![] };
+
+        // when
+        let result = replay(
+            MachineId::new("<machine-id>".to_string()),
+            &mut sniffer,
+            Duration::from_secs(10),
+            UtilizationDetails::default(),
+            &mut sink,
+        );
+
+        // then
+        assert!(result.is_ok());
+        assert_eq!(
+            sink.messages,
+            vec![
+                json!({
+                    "type": "bandwhichd/measurement/agent-network-utilization/v1",
+                    "content": {
+                        "machine_id": "d2c1d575-326e-b00b-c3eb-26ef934301f0",
+                        "timeframe": "2022-05-06T15:14:51Z/PT10S",
+                        "connections": [
+                            {
+                                "interface_name": "eth0",
+                                "local_socket_address": "192.168.10.87:43254",
+                                "remote_socket_address": "192.168.10.34:53",
+                                "protocol": "udp",
+                                "received": "108",
+                                "sent": "40"
+                            }
+                        ]
+                    }
+                }),
+                json!({
+                    "type": "bandwhichd/measurement/agent-network-utilization/v1",
+                    "content": {
+                        "machine_id": "d2c1d575-326e-b00b-c3eb-26ef934301f0",
+                        "timeframe": "2022-05-06T15:15:01Z/PT2S",
+                        "connections": [
+                            {
+                                "interface_name": "eth0",
+                                "local_socket_address": "192.168.10.87:43254",
+                                "remote_socket_address": "192.168.10.34:53",
+                                "protocol": "udp",
+                                "received": "20",
+                                "sent": "0"
+                            }
+                        ]
+                    }
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_replay_orphaned_fragments_seen_before_first_segment() {
+        // given
+        let local = Ipv4Addr::new(192, 168, 10, 87);
+        let remote = Ipv4Addr::new(192, 168, 10, 34);
+        let start = Duration::from_secs(1_651_850_091);
+        let last_fragment = |identification: u8| {
+            let mut packet = fixtures::ipv4(remote, local, fixtures::PROTOCOL_UDP, &[0; 8]);
+            packet[4..8].copy_from_slice(&[0x12, identification, 0x00, 0x02]);
+            fixtures::ethernet(fixtures::ETHERTYPE_IPV4, &packet)
+        };
+        let recording = recording(&[
+            (start, last_fragment(1)),
+            (start + Duration::from_secs(40), last_fragment(2)),
+            (
+                start + Duration::from_secs(41),
+                ipv4_udp_frame(remote, 53, local, 43254, 12),
+            ),
+        ]);
+        let frames = RecordedFrames::new(Cursor::new(recording)).unwrap();
+        let mut sniffer = Sniffer::from_frames(eth0(local), Box::new(frames));
+        let mut sink = CollectingSink { messages: vec![] };
+
+        // when
+        let result = replay(
+            MachineId::new("<machine-id>".to_string()),
+            &mut sniffer,
+            Duration::from_secs(10),
+            UtilizationDetails {
+                version: UtilizationMessageVersion::V2,
+                ..UtilizationDetails::default()
+            },
+            &mut sink,
+        );
+
+        // then
+        assert!(result.is_ok());
+        assert_eq!(sink.messages.len(), 1);
+        assert_eq!(
+            sink.messages[0]["content"]["interfaces"],
+            json!([
+                {
+                    "name": "eth0",
+                    "received_packets": "1",
+                    "sent_packets": "0",
+                    "orphaned_fragments": "1",
+                    "orphaned_fragment_bytes": "8"
+                }
+            ])
+        );
+    }
+}